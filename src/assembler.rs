@@ -0,0 +1,1013 @@
+/*
+    Assembles programs written in Octo, the assembly language of the Octo IDE. Supported are
+    labels, `:const`, `:alias`, `:macro`, `:org`, `:byte` and `:call`, every CHIP-8, SCHIP and
+    XO-CHIP statement, `if ... then`, `if ... begin ... else ... end`, `loop ... while ...
+    again` and bare numbers for sprites and other data. Comparisons with <, >, <= and >= are
+    compiled through VF the way Octo does. `:calc`, `:unpack`, `:next` and strings are not
+    supported.
+
+    When the program defines `main` anywhere but at the start, it begins with a jump to it.
+
+    http://johnearnest.github.io/Octo/docs/Manual.html
+*/
+use crate::error::AssemblyError;
+use crate::instruction::Instruction;
+use crate::mem::PROGRAM_MEMORY_START;
+use std::collections::{HashMap, VecDeque};
+
+/// Deepest nesting of macro expansions, to stop a macro that invokes itself
+const MAX_MACRO_DEPTH: usize = 1000;
+
+/// Assembles Octo source into a program to load at 0x200
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssemblyError> {
+    let program = Assembler::new(source, false).run()?;
+    match program.main {
+        Some(main) if main as usize != PROGRAM_MEMORY_START => {
+            Ok(Assembler::new(source, true).run()?.bytes)
+        }
+        _ => Ok(program.bytes),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    /// How many macro expansions the token came out of, 0 in the source
+    depth: usize,
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FixupKind {
+    /// The low 12 bits of the opcode at the address
+    Address,
+    /// The two bytes at the address
+    Long,
+}
+
+struct Fixup {
+    address: usize,
+    name: String,
+    kind: FixupKind,
+    line: usize,
+}
+
+enum Flow {
+    /// `if ... begin`, with the address of the jump past the block
+    If { jump: usize },
+    /// `else`, with the address of the jump past the else block
+    Else { jump: usize },
+    /// `loop`, with the addresses of the jumps out of it from `while`
+    Loop { start: usize, exits: Vec<usize> },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Condition {
+    Equal(u8, Operand),
+    NotEqual(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+    Less(u8, Operand),
+    Greater(u8, Operand),
+    LessOrEqual(u8, Operand),
+    GreaterOrEqual(u8, Operand),
+}
+
+struct Program {
+    bytes: Vec<u8>,
+    main: Option<u16>,
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    bytes: Vec<u8>,
+    address: usize,
+    line: usize,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, i32>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    flow: Vec<Flow>,
+    // macro nesting depth of the last token read
+    depth: usize,
+}
+
+impl Assembler {
+    fn new(source: &str, jump_to_main: bool) -> Assembler {
+        let mut tokens = VecDeque::new();
+        for (index, line) in source.lines().enumerate() {
+            let code = line.split('#').next().unwrap_or("");
+            for text in code.split_whitespace() {
+                tokens.push_back(Token {
+                    text: text.to_string(),
+                    line: index + 1,
+                    depth: 0,
+                });
+            }
+        }
+
+        let mut assembler = Assembler {
+            tokens,
+            bytes: Vec::new(),
+            address: PROGRAM_MEMORY_START,
+            line: 1,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            flow: Vec::new(),
+            depth: 0,
+        };
+        if jump_to_main {
+            assembler.fixup("main".to_string(), FixupKind::Address);
+            assembler
+                .instruction(Instruction::Jp(0))
+                .expect("the start of the program is in memory");
+        }
+        assembler
+    }
+
+    fn run(mut self) -> Result<Program, AssemblyError> {
+        while let Some(token) = self.next_token() {
+            self.statement(&token.text)?;
+        }
+
+        match self.flow.last() {
+            Some(Flow::If { .. }) | Some(Flow::Else { .. }) => {
+                return Err(self.error("`begin` without `end`"))
+            }
+            Some(Flow::Loop { .. }) => return Err(self.error("`loop` without `again`")),
+            None => {}
+        }
+
+        for fixup in &self.fixups {
+            let value = *self.labels.get(&fixup.name).ok_or_else(|| AssemblyError {
+                line: fixup.line,
+                message: format!("undefined name `{}`", fixup.name),
+            })?;
+            let offset = fixup.address - PROGRAM_MEMORY_START;
+            match fixup.kind {
+                FixupKind::Address => {
+                    if value > 0xFFF {
+                        return Err(AssemblyError {
+                            line: fixup.line,
+                            message: format!("`{}` is out of reach at {:04x}", fixup.name, value),
+                        });
+                    }
+                    self.bytes[offset] |= (value >> 8) as u8;
+                    self.bytes[offset + 1] = value as u8;
+                }
+                FixupKind::Long => {
+                    self.bytes[offset] = (value >> 8) as u8;
+                    self.bytes[offset + 1] = value as u8;
+                }
+            }
+        }
+
+        Ok(Program {
+            main: self.labels.get("main").copied(),
+            bytes: self.bytes,
+        })
+    }
+
+    fn error(&self, message: impl Into<String>) -> AssemblyError {
+        AssemblyError {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        let token = self.tokens.pop_front()?;
+        self.line = token.line;
+        self.depth = token.depth;
+        Some(token)
+    }
+
+    fn expect_token(&mut self) -> Result<String, AssemblyError> {
+        match self.next_token() {
+            Some(token) => Ok(token.text),
+            None => Err(self.error("unexpected end of program")),
+        }
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AssemblyError> {
+        let text = self.expect_token()?;
+        if text == expected {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{}`, found `{}`", expected, text)))
+        }
+    }
+
+    fn peek(&self, expected: &str) -> bool {
+        self.tokens
+            .front()
+            .is_some_and(|token| token.text == expected)
+    }
+
+    fn statement(&mut self, text: &str) -> Result<(), AssemblyError> {
+        if let Some(x) = self.register_named(text) {
+            return self.assignment(x);
+        }
+
+        match text {
+            ":" => {
+                let name = self.name()?;
+                self.define_label(name)?;
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.value()?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":macro" => self.define_macro()?,
+            ":org" => {
+                let address = self.value()?;
+                if address < PROGRAM_MEMORY_START as i32 || address > 0xFFFF {
+                    return Err(self.error(format!("cannot place code at {:x}", address)));
+                }
+                self.address = address as usize;
+            }
+            ":byte" => {
+                let byte = self.byte()?;
+                self.emit(byte)?;
+            }
+            ":call" => {
+                let address = self.address_operand(FixupKind::Address)?;
+                self.instruction(Instruction::Call(address))?;
+            }
+            "clear" => self.instruction(Instruction::Cls)?,
+            "return" | ";" => self.instruction(Instruction::Ret)?,
+            "exit" => self.instruction(Instruction::Exit)?,
+            "lores" => self.instruction(Instruction::Low)?,
+            "hires" => self.instruction(Instruction::High)?,
+            "scroll-right" => self.instruction(Instruction::ScrollRight)?,
+            "scroll-left" => self.instruction(Instruction::ScrollLeft)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.instruction(Instruction::ScrollDown(n))?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.instruction(Instruction::ScrollUp(n))?;
+            }
+            "plane" => {
+                let n = self.nibble()?;
+                self.instruction(Instruction::Plane(n))?;
+            }
+            "audio" => self.instruction(Instruction::Audio)?,
+            "jump" => {
+                let address = self.address_operand(FixupKind::Address)?;
+                self.instruction(Instruction::Jp(address))?;
+            }
+            "jump0" => {
+                let address = self.address_operand(FixupKind::Address)?;
+                self.instruction(Instruction::JpV0(address))?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.instruction(Instruction::Drw { x, y, n })?;
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.instruction(Instruction::LdB { x })?;
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                let instruction = if self.peek("-") {
+                    self.expect("-")?;
+                    let y = self.register()?;
+                    match text {
+                        "save" => Instruction::SaveRange { x, y },
+                        _ => Instruction::LoadRange { x, y },
+                    }
+                } else {
+                    match text {
+                        "save" => Instruction::LdIVx { x },
+                        _ => Instruction::LdVxI { x },
+                    }
+                };
+                self.instruction(instruction)?;
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.instruction(Instruction::LdRVx { x })?;
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.instruction(Instruction::LdVxR { x })?;
+            }
+            "i" => self.index_assignment()?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.instruction(match text {
+                    "delay" => Instruction::LdDtVx { x },
+                    "buzzer" => Instruction::LdStVx { x },
+                    _ => Instruction::Pitch { x },
+                })?;
+            }
+            "if" => {
+                let condition = self.condition()?;
+                match self.expect_token()?.as_str() {
+                    "then" => self.skip_when(condition, false)?,
+                    "begin" => {
+                        self.skip_when(condition, true)?;
+                        let jump = self.address;
+                        self.instruction(Instruction::Jp(0))?;
+                        self.flow.push(Flow::If { jump });
+                    }
+                    other => {
+                        return Err(
+                            self.error(format!("expected `then` or `begin`, found `{}`", other))
+                        )
+                    }
+                }
+            }
+            "else" => match self.flow.pop() {
+                Some(Flow::If { jump }) => {
+                    let end = self.address;
+                    self.instruction(Instruction::Jp(0))?;
+                    self.patch_jump(jump, self.address)?;
+                    self.flow.push(Flow::Else { jump: end });
+                }
+                _ => return Err(self.error("`else` without `if ... begin`")),
+            },
+            "end" => match self.flow.pop() {
+                Some(Flow::If { jump }) | Some(Flow::Else { jump }) => {
+                    self.patch_jump(jump, self.address)?;
+                }
+                _ => return Err(self.error("`end` without `if ... begin`")),
+            },
+            "loop" => self.flow.push(Flow::Loop {
+                start: self.address,
+                exits: Vec::new(),
+            }),
+            "while" => {
+                let condition = self.condition()?;
+                self.skip_when(condition, true)?;
+                let jump = self.address;
+                self.instruction(Instruction::Jp(0))?;
+                match self.flow.iter_mut().rev().find_map(|flow| match flow {
+                    Flow::Loop { exits, .. } => Some(exits),
+                    _ => None,
+                }) {
+                    Some(exits) => exits.push(jump),
+                    None => return Err(self.error("`while` outside of a loop")),
+                }
+            }
+            "again" => match self.flow.pop() {
+                Some(Flow::Loop { start, exits }) => {
+                    self.instruction(Instruction::Jp(start as u16))?;
+                    for exit in exits {
+                        self.patch_jump(exit, self.address)?;
+                    }
+                }
+                _ => return Err(self.error("`again` without `loop`")),
+            },
+            _ => {
+                let value = parse_number(text).or_else(|| self.constants.get(text).copied());
+                if let Some(value) = value {
+                    let byte = self.to_byte(value)?;
+                    self.emit(byte)?;
+                } else if self.macros.contains_key(text) {
+                    self.expand_macro(text)?;
+                } else if is_name(text) {
+                    // a bare name calls the subroutine
+                    let address = self.name_operand(text.to_string(), FixupKind::Address);
+                    self.instruction(Instruction::Call(address))?;
+                } else {
+                    return Err(self.error(format!("unknown statement `{}`", text)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // vx := ..., vx += ... and the other operators on a register
+    fn assignment(&mut self, x: u8) -> Result<(), AssemblyError> {
+        let operator = self.expect_token()?;
+        let instruction = match operator.as_str() {
+            ":=" => {
+                let source = self.expect_token()?;
+                match source.as_str() {
+                    "random" => {
+                        let byte = self.byte()?;
+                        Instruction::Rnd { x, byte }
+                    }
+                    "delay" => Instruction::LdVxDt { x },
+                    "key" => Instruction::LdVxK { x },
+                    _ => match self.operand(&source)? {
+                        Operand::Register(y) => Instruction::LdReg { x, y },
+                        Operand::Byte(byte) => Instruction::LdByte { x, byte },
+                    },
+                }
+            }
+            "+=" => match self.next_operand()? {
+                Operand::Register(y) => Instruction::AddReg { x, y },
+                Operand::Byte(byte) => Instruction::AddByte { x, byte },
+            },
+            "-=" => match self.next_operand()? {
+                Operand::Register(y) => Instruction::Sub { x, y },
+                Operand::Byte(byte) => Instruction::AddByte {
+                    x,
+                    byte: byte.wrapping_neg(),
+                },
+            },
+            "|=" | "&=" | "^=" | "=-" | ">>=" | "<<=" => {
+                let y = self.register()?;
+                match operator.as_str() {
+                    "|=" => Instruction::Or { x, y },
+                    "&=" => Instruction::And { x, y },
+                    "^=" => Instruction::Xor { x, y },
+                    "=-" => Instruction::Subn { x, y },
+                    ">>=" => Instruction::Shr { x, y },
+                    _ => Instruction::Shl { x, y },
+                }
+            }
+            _ => return Err(self.error(format!("unknown operator `{}`", operator))),
+        };
+
+        self.instruction(instruction)
+    }
+
+    // i := ..., i += vx
+    fn index_assignment(&mut self) -> Result<(), AssemblyError> {
+        let operator = self.expect_token()?;
+        let instruction = match operator.as_str() {
+            ":=" => {
+                if self.peek("hex") || self.peek("bighex") {
+                    let font = self.expect_token()?;
+                    let x = self.register()?;
+                    match font.as_str() {
+                        "hex" => Instruction::LdF { x },
+                        _ => Instruction::LdHf { x },
+                    }
+                } else if self.peek("long") {
+                    self.expect("long")?;
+                    self.instruction(Instruction::LdILong)?;
+                    let address = self.address_operand(FixupKind::Long)?;
+                    self.emit((address >> 8) as u8)?;
+                    return self.emit(address as u8);
+                } else {
+                    Instruction::LdI(self.address_operand(FixupKind::Address)?)
+                }
+            }
+            "+=" => Instruction::AddI {
+                x: self.register()?,
+            },
+            _ => return Err(self.error(format!("unknown operator `i {}`", operator))),
+        };
+
+        self.instruction(instruction)
+    }
+
+    fn condition(&mut self) -> Result<Condition, AssemblyError> {
+        let x = self.register()?;
+        let operator = self.expect_token()?;
+        let condition = match operator.as_str() {
+            "key" => Condition::Key(x),
+            "-key" => Condition::NotKey(x),
+            "==" => Condition::Equal(x, self.next_operand()?),
+            "!=" => Condition::NotEqual(x, self.next_operand()?),
+            "<" => Condition::Less(x, self.next_operand()?),
+            ">" => Condition::Greater(x, self.next_operand()?),
+            "<=" => Condition::LessOrEqual(x, self.next_operand()?),
+            ">=" => Condition::GreaterOrEqual(x, self.next_operand()?),
+            _ => return Err(self.error(format!("unknown comparison `{}`", operator))),
+        };
+
+        Ok(condition)
+    }
+
+    /// Emits the instructions skipping the next one when `condition` is `when`
+    fn skip_when(&mut self, condition: Condition, when: bool) -> Result<(), AssemblyError> {
+        let skip = match condition {
+            Condition::Equal(x, operand) | Condition::NotEqual(x, operand) => {
+                let equal = matches!(condition, Condition::Equal(..)) == when;
+                match (operand, equal) {
+                    (Operand::Byte(byte), true) => Instruction::SeByte { x, byte },
+                    (Operand::Byte(byte), false) => Instruction::SneByte { x, byte },
+                    (Operand::Register(y), true) => Instruction::SeReg { x, y },
+                    (Operand::Register(y), false) => Instruction::SneReg { x, y },
+                }
+            }
+            Condition::Key(x) if when => Instruction::Skp { x },
+            Condition::Key(x) => Instruction::Sknp { x },
+            Condition::NotKey(x) if when => Instruction::Sknp { x },
+            Condition::NotKey(x) => Instruction::Skp { x },
+            Condition::Less(x, operand) => return self.compare(x, operand, false, 0, when),
+            Condition::GreaterOrEqual(x, operand) => {
+                return self.compare(x, operand, false, 1, when)
+            }
+            Condition::Greater(x, operand) => return self.compare(x, operand, true, 0, when),
+            Condition::LessOrEqual(x, operand) => return self.compare(x, operand, true, 1, when),
+        };
+
+        self.instruction(skip)
+    }
+
+    // Sets VF to 1 when vx >= operand, or operand >= vx when `swapped`, then skips the next
+    // instruction when VF == `flag` is `when`
+    fn compare(
+        &mut self,
+        x: u8,
+        operand: Operand,
+        swapped: bool,
+        flag: u8,
+        when: bool,
+    ) -> Result<(), AssemblyError> {
+        if x == 0xF || matches!(operand, Operand::Register(0xF)) {
+            return Err(self.error("vf cannot be compared with <, >, <= or >="));
+        }
+        let (load, subtract) = match (operand, swapped) {
+            // vf := vx, vf -= vy
+            (Operand::Register(y), false) => (Instruction::LdReg { x: 0xF, y: x }, y),
+            // vf := vy, vf -= vx
+            (Operand::Register(y), true) => (Instruction::LdReg { x: 0xF, y }, x),
+            // vf := byte, vf =- vx
+            (Operand::Byte(byte), false) => {
+                self.instruction(Instruction::LdByte { x: 0xF, byte })?;
+                self.instruction(Instruction::Subn { x: 0xF, y: x })?;
+                return self.skip_when(Condition::Equal(0xF, Operand::Byte(flag)), when);
+            }
+            // vf := byte, vf -= vx
+            (Operand::Byte(byte), true) => (Instruction::LdByte { x: 0xF, byte }, x),
+        };
+        self.instruction(load)?;
+        self.instruction(Instruction::Sub {
+            x: 0xF,
+            y: subtract,
+        })?;
+        self.skip_when(Condition::Equal(0xF, Operand::Byte(flag)), when)
+    }
+
+    fn define_label(&mut self, name: String) -> Result<(), AssemblyError> {
+        if self.labels.contains_key(&name) || self.constants.contains_key(&name) {
+            return Err(self.error(format!("`{}` is already defined", name)));
+        }
+        self.labels.insert(name, self.address as u16);
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), AssemblyError> {
+        let name = self.name()?;
+        let mut parameters = Vec::new();
+        loop {
+            let text = self.expect_token()?;
+            if text == "{" {
+                break;
+            }
+            parameters.push(text);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = match self.next_token() {
+                Some(token) => token,
+                None => return Err(self.error(format!("macro `{}` has no closing }}", name))),
+            };
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(name, Macro { parameters, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), AssemblyError> {
+        let depth = self.depth + 1;
+        if depth > MAX_MACRO_DEPTH {
+            return Err(self.error(format!("macro `{}` expands endlessly", name)));
+        }
+        let parameter_count = self.macros[name].parameters.len();
+        let mut arguments = Vec::new();
+        for _ in 0..parameter_count {
+            arguments.push(self.expect_token()?);
+        }
+
+        let line = self.line;
+        let definition = &self.macros[name];
+        for token in definition.body.iter().rev() {
+            let text = match definition.parameters.iter().position(|p| *p == token.text) {
+                Some(index) => arguments[index].clone(),
+                None => token.text.clone(),
+            };
+            self.tokens.push_front(Token { text, line, depth });
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<String, AssemblyError> {
+        let text = self.expect_token()?;
+        if is_name(&text) {
+            Ok(text)
+        } else {
+            Err(self.error(format!("`{}` is not a valid name", text)))
+        }
+    }
+
+    fn register_named(&self, text: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(text) {
+            return Some(register);
+        }
+        let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+        if digit.len() == 1 {
+            u8::from_str_radix(digit, 16).ok()
+        } else {
+            None
+        }
+    }
+
+    fn register(&mut self) -> Result<u8, AssemblyError> {
+        let text = self.expect_token()?;
+        self.register_named(&text)
+            .ok_or_else(|| self.error(format!("expected a register, found `{}`", text)))
+    }
+
+    fn constant(&self, text: &str) -> Option<i32> {
+        parse_number(text)
+            .or_else(|| self.constants.get(text).copied())
+            .or_else(|| self.labels.get(text).map(|&address| address as i32))
+    }
+
+    fn value(&mut self) -> Result<i32, AssemblyError> {
+        let text = self.expect_token()?;
+        self.constant(&text)
+            .ok_or_else(|| self.error(format!("expected a number, found `{}`", text)))
+    }
+
+    fn to_byte(&self, value: i32) -> Result<u8, AssemblyError> {
+        if (-128..=255).contains(&value) {
+            Ok(value as u8)
+        } else {
+            Err(self.error(format!("{} does not fit in a byte", value)))
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, AssemblyError> {
+        let value = self.value()?;
+        self.to_byte(value)
+    }
+
+    fn nibble(&mut self) -> Result<u8, AssemblyError> {
+        let value = self.value()?;
+        if (0..=15).contains(&value) {
+            Ok(value as u8)
+        } else {
+            Err(self.error(format!("{} does not fit in a nibble", value)))
+        }
+    }
+
+    fn operand(&self, text: &str) -> Result<Operand, AssemblyError> {
+        if let Some(register) = self.register_named(text) {
+            return Ok(Operand::Register(register));
+        }
+        match self.constant(text) {
+            Some(value) => Ok(Operand::Byte(self.to_byte(value)?)),
+            None => Err(self.error(format!("expected a register or number, found `{}`", text))),
+        }
+    }
+
+    fn next_operand(&mut self) -> Result<Operand, AssemblyError> {
+        let text = self.expect_token()?;
+        self.operand(&text)
+    }
+
+    // An address that may be a label defined further down
+    fn address_operand(&mut self, kind: FixupKind) -> Result<u16, AssemblyError> {
+        let text = self.expect_token()?;
+        let limit = match kind {
+            FixupKind::Address => 0xFFF,
+            FixupKind::Long => 0xFFFF,
+        };
+        match self.constant(&text) {
+            Some(value) if (0..=limit).contains(&value) => Ok(value as u16),
+            Some(value) => Err(self.error(format!("address {:x} is out of reach", value))),
+            None if is_name(&text) => Ok(self.name_operand(text, kind)),
+            None => Err(self.error(format!("expected an address, found `{}`", text))),
+        }
+    }
+
+    fn name_operand(&mut self, name: String, kind: FixupKind) -> u16 {
+        match self.labels.get(&name) {
+            Some(&address) => address,
+            None => {
+                self.fixup(name, kind);
+                0
+            }
+        }
+    }
+
+    // Fills in the address about to be emitted once `name` is defined, the opcode of a
+    // jump, call or I assignment or the two bytes after F000
+    fn fixup(&mut self, name: String, kind: FixupKind) {
+        self.fixups.push(Fixup {
+            address: self.address,
+            name,
+            kind,
+            line: self.line,
+        });
+    }
+
+    fn patch_jump(&mut self, jump: usize, target: usize) -> Result<(), AssemblyError> {
+        if target > 0xFFF {
+            return Err(self.error(format!("jump target {:x} is out of reach", target)));
+        }
+        let offset = jump - PROGRAM_MEMORY_START;
+        let opcode = Instruction::Jp(target as u16).encode();
+        self.bytes[offset] = (opcode >> 8) as u8;
+        self.bytes[offset + 1] = opcode as u8;
+        Ok(())
+    }
+
+    fn instruction(&mut self, instruction: Instruction) -> Result<(), AssemblyError> {
+        let opcode = instruction.encode();
+        self.emit((opcode >> 8) as u8)?;
+        self.emit(opcode as u8)
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), AssemblyError> {
+        if self.address > 0xFFFF {
+            return Err(self.error("program does not fit in memory"));
+        }
+        let offset = self.address - PROGRAM_MEMORY_START;
+        if self.bytes.len() <= offset {
+            self.bytes.resize(offset + 1, 0);
+        }
+        self.bytes[offset] = byte;
+        self.address += 1;
+        Ok(())
+    }
+}
+
+fn is_name(text: &str) -> bool {
+    let mut characters = text.chars();
+    characters
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && characters.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn parse_number(text: &str) -> Option<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i32::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+#[test]
+fn test_assemble_statements() {
+    let program = assemble(
+        "
+        : main
+            clear
+            v0 := 0x10  v1 := v0  v2 := random 0xFF  v3 := delay  v4 := key
+            v0 += 5  v0 += v1  v0 -= 1  v0 -= v1  v0 =- v1
+            v0 |= v1  v0 &= v1  v0 ^= v1  v0 >>= v1  v0 <<= v1
+            i := 0x300  i += v2  i := hex v3  i := bighex v3
+            delay := v0  buzzer := v0  bcd v0  save v3  load v3  saveflags v1  loadflags v1
+            sprite v0 v1 5  jump0 0x400  return
+        ",
+    )
+    .unwrap();
+
+    let expected = vec![
+        0x00E0, 0x6010, 0x8100, 0xC2FF, 0xF307, 0xF40A, 0x7005, 0x8014, 0x70FF, 0x8015, 0x8017,
+        0x8011, 0x8012, 0x8013, 0x8016, 0x801E, 0xA300, 0xF21E, 0xF329, 0xF330, 0xF015, 0xF018,
+        0xF033, 0xF355, 0xF365, 0xF175, 0xF185, 0xD015, 0xB400, 0x00EE,
+    ];
+    let opcodes: Vec<u16> = program
+        .chunks(2)
+        .map(|word| (word[0] as u16) << 8 | word[1] as u16)
+        .collect();
+    assert_eq!(expected, opcodes);
+}
+
+#[test]
+fn test_assemble_labels_and_data() {
+    // forward references to labels, and sprite data
+    let program = assemble(
+        "
+        : main
+            i := face
+            draw
+            jump main
+        : draw
+            sprite v0 v0 2
+            ;
+        : face
+            0x3C 0b01000010
+        ",
+    )
+    .unwrap();
+
+    assert_eq!(
+        vec![0xA2, 0x0A, 0x22, 0x06, 0x12, 0x00, 0xD0, 0x02, 0x00, 0xEE, 0x3C, 0x42],
+        program
+    );
+}
+
+#[test]
+fn test_assemble_jumps_to_main() {
+    let program = assemble(": data 0xFF : main jump main").unwrap();
+
+    assert_eq!(vec![0x12, 0x03, 0xFF, 0x12, 0x03], program);
+}
+
+#[test]
+fn test_assemble_const_alias_and_macro() {
+    let program = assemble(
+        "
+        :const SPEED 3
+        :alias x v5
+        :macro move register amount { register += amount }
+        : main
+            x := SPEED
+            move x SPEED
+            move v1 -1
+        ",
+    )
+    .unwrap();
+
+    assert_eq!(vec![0x65, 0x03, 0x75, 0x03, 0x71, 0xFF], program);
+}
+
+#[test]
+fn test_assemble_macro_depth() {
+    let source = format!(":macro inc {{ v0 += 1 }} : main {}", "inc ".repeat(1001));
+    let program = assemble(&source).unwrap();
+    assert_eq!(2002, program.len());
+
+    let error = assemble(":macro forever { forever } : main forever").unwrap_err();
+    assert_eq!("macro `forever` expands endlessly", error.message);
+}
+
+#[test]
+fn test_assemble_if() {
+    let program = assemble(
+        "
+        : main
+            if v0 == 1 then v1 := 2
+            if v0 != v1 then v1 := 2
+            if v0 key then clear
+            if v0 begin
+        ",
+    );
+    assert!(program.is_err());
+
+    let program = assemble(
+        "
+        : main
+            if v0 == 1 then v1 := 2
+            if v0 != v1 then v1 := 2
+            if v0 -key then clear
+            if v0 == 2 begin
+                v1 := 1
+            else
+                v1 := 2
+            end
+        ",
+    )
+    .unwrap();
+
+    assert_eq!(
+        vec![
+            0x40, 0x01, 0x61, 0x02, // skip unless v0 == 1
+            0x50, 0x10, 0x61, 0x02, // skip unless v0 != v1
+            0xE0, 0x9E, 0x00, 0xE0, // skip if the key is pressed
+            0x30, 0x02, 0x12, 0x14, // skip the jump to else if v0 == 2
+            0x61, 0x01, 0x12, 0x16, // then jump past else
+            0x61, 0x02,
+        ],
+        program
+    );
+}
+
+#[test]
+fn test_assemble_loop() {
+    let program = assemble(
+        "
+        : main
+            loop
+                v0 += 1
+                while v0 != 10
+            again
+        ",
+    )
+    .unwrap();
+
+    assert_eq!(
+        vec![0x70, 0x01, 0x40, 0x0A, 0x12, 0x08, 0x12, 0x00],
+        program
+    );
+}
+
+#[test]
+fn test_assemble_comparisons() {
+    // v0 < v1 computes vf := v0 - v1, which borrows when true
+    let program = assemble(": main if v0 < v1 then v2 := 1 if v0 > 5 then v2 := 1").unwrap();
+
+    assert_eq!(
+        vec![
+            0x8F, 0x00, 0x8F, 0x15, 0x4F, 0x00, 0x62,
+            0x01, // vf := v0 vf -= v1 skip if vf != 0
+            0x6F, 0x05, 0x8F, 0x05, 0x4F, 0x00, 0x62,
+            0x01, // vf := 5 vf -= v0 skip if vf != 0
+        ],
+        program
+    );
+}
+
+#[test]
+fn test_assemble_xo_chip() {
+    let program = assemble(
+        ": main plane 3 audio pitch := v1 save v1 - v3 load v3 - v1 scroll-up 2 i := long data
+         : data 0xAA",
+    )
+    .unwrap();
+
+    assert_eq!(
+        vec![
+            0xF3, 0x01, 0xF0, 0x02, 0xF1, 0x3A, 0x51, 0x32, 0x53, 0x13, 0x00, 0xD2, 0xF0, 0x00,
+            0x02, 0x10, 0xAA
+        ],
+        program
+    );
+}
+
+#[test]
+fn test_assemble_errors() {
+    assert_eq!(
+        Err(AssemblyError {
+            line: 2,
+            message: "undefined name `missing`".to_string()
+        }),
+        assemble(": main\n  jump missing")
+    );
+    assert_eq!(
+        Err(AssemblyError {
+            line: 1,
+            message: "256 does not fit in a byte".to_string()
+        }),
+        assemble("v0 := 256")
+    );
+    assert!(assemble("sprite v0 v1 16").is_err());
+    assert!(assemble("again").is_err());
+    assert!(assemble("v0 := 1 : x : x").is_err());
+    assert!(assemble(":macro forever { forever } forever").is_err());
+}
+
+#[test]
+fn test_disassembly_round_trip() {
+    use crate::disassembler::{disassemble, Syntax};
+
+    for name in &["knocket-flags", "knocket-skips"] {
+        let path: std::path::PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "roms"]
+            .iter()
+            .collect();
+        let rom = std::fs::read(path.join(format!("{}.ch8", name))).unwrap();
+        let source = disassemble(&rom).to_text(Syntax::Octo);
+
+        assert_eq!(rom, assemble(&source).unwrap(), "{}", name);
+    }
+}
@@ -0,0 +1,172 @@
+/*
+    XO-CHIP sound is a 128 bit pattern, loaded from memory with F002, played back in a loop
+    for as long as the sound timer is non-zero. The playback rate is set with Fx3A, a pitch
+    of 64 plays 4000 bits per second and every 48 steps doubles or halves the rate.
+
+    Other interpreters only have a buzzer, played here as a square wave.
+
+    http://johnearnest.github.io/Octo/docs/XO-ChipSpecification.html
+*/
+
+/// Size of the audio pattern buffer in bytes
+pub const PATTERN_SIZE: usize = 16;
+/// Pitch register value after reset, 4000 bits per second
+pub const DEFAULT_PITCH: u8 = 64;
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+/// Frequency of the buzzer in Hz
+pub const DEFAULT_TONE_FREQUENCY: f64 = 440.0;
+/// Amplitude of the generated samples, a set pattern bit is `VOLUME` and a clear one `-VOLUME`
+pub const VOLUME: f32 = 0.25;
+
+/// Playback rate of the pattern in bits per second for a pitch register value
+pub fn pattern_rate(pitch: u8) -> f64 {
+    4000.0 * 2f64.powf((pitch as f64 - 64.0) / 48.0)
+}
+
+/// Turns the sound state into samples, keeping track of the playback position between calls
+pub struct AudioGenerator {
+    sample_rate: u32,
+    position: f64,
+    tone_frequency: f64,
+    phase: f64,
+}
+
+impl AudioGenerator {
+    pub fn new(sample_rate: u32) -> AudioGenerator {
+        AudioGenerator {
+            sample_rate: sample_rate.max(1),
+            position: 0.0,
+            tone_frequency: DEFAULT_TONE_FREQUENCY,
+            phase: 0.0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate.max(1);
+    }
+
+    pub fn tone_frequency(&self) -> f64 {
+        self.tone_frequency
+    }
+
+    pub fn set_tone_frequency(&mut self, frequency: f64) {
+        self.tone_frequency = frequency;
+    }
+
+    /// Position in the pattern in bits, between 0 and 128
+    pub fn position(&self) -> f64 {
+        self.position
+    }
+
+    /// Fills `samples` with the pattern played at `pitch`, or silence when not `active`.
+    /// Playback resumes where the previous call left off.
+    pub fn render(
+        &mut self,
+        pattern: &[u8; PATTERN_SIZE],
+        pitch: u8,
+        active: bool,
+        samples: &mut [f32],
+    ) {
+        if !active {
+            samples.iter_mut().for_each(|sample| *sample = 0.0);
+            return;
+        }
+
+        let pattern_bits = (PATTERN_SIZE * 8) as f64;
+        let step = pattern_rate(pitch) / self.sample_rate as f64;
+        for sample in samples.iter_mut() {
+            let bit = self.position as usize;
+            let set = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            *sample = if set { VOLUME } else { -VOLUME };
+            self.position = (self.position + step) % pattern_bits;
+        }
+    }
+
+    /// Fills `samples` with a square wave at the tone frequency, or silence when not
+    /// `active`. The wave continues where the previous call left off.
+    pub fn render_tone(&mut self, active: bool, samples: &mut [f32]) {
+        if !active {
+            samples.iter_mut().for_each(|sample| *sample = 0.0);
+            return;
+        }
+
+        let step = self.tone_frequency / self.sample_rate as f64;
+        for sample in samples.iter_mut() {
+            *sample = if self.phase < 0.5 { VOLUME } else { -VOLUME };
+            self.phase = (self.phase + step) % 1.0;
+        }
+    }
+}
+
+impl Default for AudioGenerator {
+    fn default() -> Self {
+        Self::new(DEFAULT_SAMPLE_RATE)
+    }
+}
+
+#[test]
+fn test_pattern_rate() {
+    assert_eq!(4000.0, pattern_rate(DEFAULT_PITCH));
+    assert!((pattern_rate(DEFAULT_PITCH + 48) - 8000.0).abs() < 1e-9);
+    assert!((pattern_rate(DEFAULT_PITCH - 48) - 2000.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_render_pattern() {
+    // one bit per sample
+    let mut generator = AudioGenerator::new(4000);
+    let mut pattern = [0; PATTERN_SIZE];
+    pattern[0] = 0b1010_0000;
+    let mut samples = [0.0; 4];
+
+    generator.render(&pattern, DEFAULT_PITCH, true, &mut samples);
+
+    assert_eq!([VOLUME, -VOLUME, VOLUME, -VOLUME], samples);
+    assert_eq!(4.0, generator.position());
+}
+
+#[test]
+fn test_render_wraps_around() {
+    let mut generator = AudioGenerator::new(4000);
+    let mut pattern = [0; PATTERN_SIZE];
+    pattern[0] = 0x80;
+    let mut samples = [0.0; 129];
+
+    generator.render(&pattern, DEFAULT_PITCH, true, &mut samples);
+
+    assert_eq!(VOLUME, samples[0]);
+    assert_eq!(-VOLUME, samples[127]);
+    assert_eq!(VOLUME, samples[128]);
+}
+
+#[test]
+fn test_render_silent() {
+    let mut generator = AudioGenerator::new(4000);
+    let mut samples = [1.0; 8];
+
+    generator.render(&[0xFF; PATTERN_SIZE], DEFAULT_PITCH, false, &mut samples);
+
+    assert_eq!([0.0; 8], samples);
+    assert_eq!(0.0, generator.position());
+}
+
+#[test]
+fn test_render_tone() {
+    let mut generator = AudioGenerator::new(8);
+    generator.set_tone_frequency(2.0);
+    let mut samples = [0.0; 8];
+
+    generator.render_tone(true, &mut samples);
+
+    assert_eq!(
+        [VOLUME, VOLUME, -VOLUME, -VOLUME, VOLUME, VOLUME, -VOLUME, -VOLUME],
+        samples
+    );
+
+    generator.render_tone(false, &mut samples);
+    assert_eq!([0.0; 8], samples);
+}
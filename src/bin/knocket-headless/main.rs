@@ -0,0 +1,127 @@
+mod script;
+
+use clap::Parser;
+use knocket::display::Palette;
+use knocket::{Chip8, Quirks};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Runs a CHIP-8 ROM without a window and reports the final display, for automated testing
+#[derive(Parser, Debug)]
+#[command(name = "knocket-headless", version)]
+struct Args {
+    /// Path to the ROM to run
+    rom: PathBuf,
+
+    /// Stop after running this many frames, unless the program halts or jumps to itself first
+    #[arg(short, long, default_value_t = 600)]
+    frames: u64,
+
+    /// Number of instructions executed per 60 Hz frame
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    ipf: u32,
+
+    /// Quirk profile: vip, chip48, schip or xochip
+    #[arg(short, long, default_value = "vip", value_parser = parse_quirks)]
+    quirks: Quirks,
+
+    /// Seed for the random number generator
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Hold keys during frames, FRAME=KEYS or FIRST-LAST=KEYS with hex keys joined by `+`
+    #[arg(short, long, value_parser = script::parse_press)]
+    press: Vec<script::Press>,
+
+    /// File with one key press per line, in the format of --press
+    #[arg(long)]
+    script: Option<PathBuf>,
+
+    /// Print the display as text
+    #[arg(long)]
+    text: bool,
+
+    /// Write the display to a PNG file
+    #[arg(long)]
+    png: Option<PathBuf>,
+}
+
+fn parse_quirks(value: &str) -> Result<Quirks, String> {
+    Quirks::preset(value)
+        .ok_or_else(|| format!("`{}` is not one of vip, chip48, schip or xochip", value))
+}
+
+fn write_png(chip8: &Chip8, path: &Path) -> Result<(), Box<dyn Error>> {
+    let file = File::create(path)
+        .map_err(|e| format!("Could not create PNG {}: {}", path.display(), e))?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        chip8.display.width() as u32,
+        chip8.display.height() as u32,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&chip8.display.to_rgba(&Palette::default()))?;
+    Ok(())
+}
+
+fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let mut presses = args.press.clone();
+    if let Some(path) = &args.script {
+        let source = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read script {}: {}", path.display(), e))?;
+        presses.extend(
+            script::parse_script(&source)
+                .map_err(|e| format!("Invalid script {}: {}", path.display(), e))?,
+        );
+    }
+
+    let rom = fs::read(&args.rom)
+        .map_err(|e| format!("Could not read ROM {}: {}", args.rom.display(), e))?;
+    let mut chip8 = Chip8::new(args.quirks);
+    chip8.set_seed(args.seed);
+    chip8.set_instructions_per_frame(args.ipf);
+    chip8
+        .load_program(&rom)
+        .map_err(|e| format!("Could not load ROM {}: {}", args.rom.display(), e))?;
+
+    let mut frame = 0;
+    let stopped = loop {
+        if chip8.halted() {
+            break "halted";
+        }
+        if chip8.jumping_to_self() {
+            break "jump to self";
+        }
+        if frame >= args.frames {
+            break "frame limit";
+        }
+        chip8.run_frame(&script::keypad(&presses, frame))?;
+        frame += 1;
+    };
+
+    if args.text {
+        print!("{}", chip8.display.to_text());
+    }
+    if let Some(path) = &args.png {
+        write_png(&chip8, path)?;
+    }
+    println!("frames: {}", frame);
+    println!("stopped: {}", stopped);
+    println!("hash: {:016x}", chip8.display.hash());
+
+    Ok(())
+}
+
+fn main() {
+    let args = Args::parse();
+
+    if let Err(e) = run(args) {
+        eprintln!("knocket-headless: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,115 @@
+/*
+    Key presses are given as `FRAME=KEYS` or `FIRST-LAST=KEYS`, holding the hex keys in KEYS,
+    separated by `+`, from frame FIRST to LAST inclusive. Frames count from 0.
+
+    10=5          hold 5 during frame 10
+    30-45=4+6     hold 4 and 6 from frame 30 to 45
+*/
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Press {
+    first: u64,
+    last: u64,
+    keys: [bool; 16],
+}
+
+pub fn parse_press(value: &str) -> Result<Press, String> {
+    let (frames, keys) = value
+        .split_once('=')
+        .ok_or_else(|| format!("`{}` is not FRAME=KEYS", value))?;
+    let parse_frame = |frame: &str| {
+        frame
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| format!("`{}` is not a frame number", frame.trim()))
+    };
+    let (first, last) = match frames.split_once('-') {
+        Some((first, last)) => (parse_frame(first)?, parse_frame(last)?),
+        None => {
+            let frame = parse_frame(frames)?;
+            (frame, frame)
+        }
+    };
+    if last < first {
+        return Err(format!(
+            "frame range `{}` ends before it starts",
+            frames.trim()
+        ));
+    }
+
+    let mut pressed = [false; 16];
+    for key in keys.split('+') {
+        let index = u8::from_str_radix(key.trim(), 16)
+            .ok()
+            .filter(|&index| index < 16)
+            .ok_or_else(|| format!("`{}` is not a key between 0 and F", key.trim()))?;
+        pressed[index as usize] = true;
+    }
+
+    Ok(Press {
+        first,
+        last,
+        keys: pressed,
+    })
+}
+
+/// Parses a script file with one press per line, # starts a comment
+pub fn parse_script(source: &str) -> Result<Vec<Press>, String> {
+    source
+        .lines()
+        .enumerate()
+        .map(|(number, line)| (number, line.split('#').next().unwrap_or("").trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(number, line)| parse_press(line).map_err(|e| format!("line {}: {}", number + 1, e)))
+        .collect()
+}
+
+/// The keys held during `frame`
+pub fn keypad(presses: &[Press], frame: u64) -> [bool; 16] {
+    let mut keypad = [false; 16];
+    for press in presses
+        .iter()
+        .filter(|press| (press.first..=press.last).contains(&frame))
+    {
+        for (down, &pressed) in keypad.iter_mut().zip(press.keys.iter()) {
+            *down |= pressed;
+        }
+    }
+    keypad
+}
+
+#[test]
+fn test_parse_press() {
+    let press = parse_press("30-45=4+c").unwrap();
+
+    assert_eq!(30, press.first);
+    assert_eq!(45, press.last);
+    assert!(press.keys[4] && press.keys[0xC]);
+    assert_eq!(2, press.keys.iter().filter(|&&down| down).count());
+
+    let press = parse_press("10=F").unwrap();
+    assert_eq!((10, 10), (press.first, press.last));
+    assert!(press.keys[0xF]);
+}
+
+#[test]
+fn test_parse_press_errors() {
+    assert!(parse_press("10").is_err());
+    assert!(parse_press("x=1").is_err());
+    assert!(parse_press("10=G").is_err());
+    assert!(parse_press("10=10").is_err());
+    assert!(parse_press("20-10=1").is_err());
+}
+
+#[test]
+fn test_parse_script_and_keypad() {
+    let presses = parse_script("# start the game\n5 = 1\n\n4-6=2 # move\n").unwrap();
+
+    assert_eq!([false; 16], keypad(&presses, 3));
+    assert!(keypad(&presses, 4)[2] && !keypad(&presses, 4)[1]);
+    assert!(keypad(&presses, 5)[1] && keypad(&presses, 5)[2]);
+    assert_eq!(
+        Err("line 2: `x` is not a key between 0 and F".to_string()),
+        parse_script("1=1\n1=x")
+    );
+}
@@ -0,0 +1,22 @@
+use crate::cli;
+use knocket::assembler;
+use std::error::Error;
+use std::fs;
+
+/// Assembles the source and writes the ROM
+pub fn run(args: &cli::AsmArgs) -> Result<(), Box<dyn Error>> {
+    let source = fs::read_to_string(&args.source)
+        .map_err(|e| format!("Could not read {}: {}", args.source.display(), e))?;
+    let program =
+        assembler::assemble(&source).map_err(|e| format!("{}: {}", args.source.display(), e))?;
+
+    let output = match &args.output {
+        Some(path) => path.clone(),
+        None => args.source.with_extension("ch8"),
+    };
+    fs::write(&output, &program)
+        .map_err(|e| format!("Could not write ROM {}: {}", output.display(), e))?;
+    log::info!("Wrote {} bytes to {}", program.len(), output.display());
+
+    Ok(())
+}
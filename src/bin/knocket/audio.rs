@@ -0,0 +1,236 @@
+use crate::cli;
+use knocket::chip8::FRAME_RATE;
+use knocket::Chip8;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Destination for the samples rendered after every frame
+pub trait AudioSink {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Box<dyn Error>>;
+
+    fn finish(self: Box<Self>) -> Result<(), Box<dyn Error>>;
+}
+
+/// Writes the audio to a mono 16 bit WAV file
+pub struct WavSink {
+    writer: hound::WavWriter<BufWriter<File>>,
+}
+
+impl WavSink {
+    pub fn create(path: &Path, sample_rate: u32) -> Result<WavSink, Box<dyn Error>> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let writer = hound::WavWriter::create(path, spec)
+            .map_err(|e| format!("Could not create WAV file {}: {}", path.display(), e))?;
+
+        Ok(WavSink { writer })
+    }
+}
+
+impl AudioSink for WavSink {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Box<dyn Error>> {
+        for &sample in samples {
+            self.writer
+                .write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), Box<dyn Error>> {
+        self.writer.finalize()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "audio")]
+mod live {
+    use super::AudioSink;
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use std::collections::VecDeque;
+    use std::error::Error;
+    use std::sync::{Arc, Mutex};
+
+    /// Plays the audio on the default output device. Samples are queued by the emulator
+    /// and drained by the audio thread, which plays silence when the queue runs dry.
+    pub struct LiveSink {
+        _stream: cpal::Stream,
+        queue: Arc<Mutex<VecDeque<f32>>>,
+        max_queued: usize,
+        sample_rate: u32,
+    }
+
+    impl LiveSink {
+        pub fn open() -> Result<LiveSink, Box<dyn Error>> {
+            let device = cpal::default_host()
+                .default_output_device()
+                .ok_or("No audio output device")?;
+            let supported = device.default_output_config()?;
+            let sample_format = supported.sample_format();
+            let config: cpal::StreamConfig = supported.into();
+            let queue = Arc::new(Mutex::new(VecDeque::new()));
+
+            let stream = match sample_format {
+                cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config, &queue)?,
+                cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config, &queue)?,
+                cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config, &queue)?,
+                format => return Err(format!("Unsupported sample format {}", format).into()),
+            };
+            stream.play()?;
+
+            Ok(LiveSink {
+                _stream: stream,
+                queue,
+                // keep the latency below a fifth of a second
+                max_queued: config.sample_rate.0 as usize / 5,
+                sample_rate: config.sample_rate.0,
+            })
+        }
+
+        pub fn sample_rate(&self) -> u32 {
+            self.sample_rate
+        }
+    }
+
+    fn build_stream<T>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        queue: &Arc<Mutex<VecDeque<f32>>>,
+    ) -> Result<cpal::Stream, cpal::BuildStreamError>
+    where
+        T: cpal::SizedSample + cpal::FromSample<f32>,
+    {
+        let channels = config.channels as usize;
+        let queue = Arc::clone(queue);
+
+        device.build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let mut queue = queue.lock().unwrap();
+                for frame in data.chunks_mut(channels) {
+                    let sample = T::from_sample(queue.pop_front().unwrap_or(0.0));
+                    frame.iter_mut().for_each(|channel| *channel = sample);
+                }
+            },
+            |e| log::error!("Audio stream error: {}", e),
+            None,
+        )
+    }
+
+    impl AudioSink for LiveSink {
+        fn write(&mut self, samples: &[f32]) -> Result<(), Box<dyn Error>> {
+            let mut queue = self.queue.lock().unwrap();
+            queue.extend(samples);
+            let excess = queue.len().saturating_sub(self.max_queued);
+            queue.drain(..excess);
+            Ok(())
+        }
+
+        fn finish(self: Box<Self>) -> Result<(), Box<dyn Error>> {
+            Ok(())
+        }
+    }
+}
+
+/// Renders the sound of every frame and hands it to the live and WAV sinks
+pub struct AudioOutput {
+    sinks: Vec<Box<dyn AudioSink>>,
+    frame: u64,
+    buffer: Vec<f32>,
+}
+
+impl AudioOutput {
+    /// Opens the WAV file given with --wav and, when `live`, the default output device.
+    /// The emulator's sample rate is set to the one of the device.
+    pub fn open(
+        chip8: &mut Chip8,
+        args: &cli::Args,
+        live: bool,
+    ) -> Result<AudioOutput, Box<dyn Error>> {
+        let mut sinks: Vec<Box<dyn AudioSink>> = Vec::new();
+
+        if live && !args.mute {
+            open_live(chip8, &mut sinks);
+        }
+        if let Some(path) = &args.wav {
+            sinks.push(Box::new(WavSink::create(path, chip8.sample_rate())?));
+        }
+
+        Ok(AudioOutput {
+            sinks,
+            frame: 0,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Renders the audio for the frame just run
+    pub fn frame(&mut self, chip8: &mut Chip8) -> Result<(), Box<dyn Error>> {
+        if self.sinks.is_empty() {
+            return Ok(());
+        }
+
+        // spread the remainder over the frames when the rate isn't a multiple of 60
+        let sample_rate = chip8.sample_rate() as u64;
+        let frame_rate = FRAME_RATE as u64;
+        let start = self.frame * sample_rate / frame_rate;
+        let end = (self.frame + 1) * sample_rate / frame_rate;
+        self.frame += 1;
+
+        self.buffer.resize((end - start) as usize, 0.0);
+        chip8.render_audio(&mut self.buffer);
+        for sink in self.sinks.iter_mut() {
+            sink.write(&self.buffer)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        for sink in self.sinks {
+            sink.finish()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "audio")]
+fn open_live(chip8: &mut Chip8, sinks: &mut Vec<Box<dyn AudioSink>>) {
+    match live::LiveSink::open() {
+        Ok(sink) => {
+            chip8.set_sample_rate(sink.sample_rate());
+            sinks.push(Box::new(sink));
+        }
+        Err(e) => log::warn!(
+            "Could not open audio output, continuing without sound: {}",
+            e
+        ),
+    }
+}
+
+#[cfg(not(feature = "audio"))]
+fn open_live(_chip8: &mut Chip8, _sinks: &mut Vec<Box<dyn AudioSink>>) {
+    log::info!("knocket was built without the `audio` feature, playing no sound");
+}
+
+#[test]
+fn test_wav_sink() {
+    let path = std::env::temp_dir().join(format!("knocket-test-{}.wav", std::process::id()));
+    let mut sink = Box::new(WavSink::create(&path, 8000).unwrap());
+    sink.write(&[0.0, 1.0, -1.0]).unwrap();
+    sink.write(&[0.5]).unwrap();
+    sink.finish().unwrap();
+
+    let mut reader = hound::WavReader::open(&path).unwrap();
+    let spec = reader.spec();
+    let samples: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap()).collect();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(1, spec.channels);
+    assert_eq!(8000, spec.sample_rate);
+    assert_eq!(vec![0, i16::MAX, -i16::MAX, i16::MAX / 2], samples);
+}
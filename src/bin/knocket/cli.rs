@@ -0,0 +1,271 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use knocket::disassembler::Syntax;
+use knocket::Quirks;
+use log::LevelFilter;
+use std::path::{Path, PathBuf};
+
+const VALID_SCALES: [u8; 6] = [1, 2, 4, 8, 16, 32];
+
+/// A CHIP-8 emulator
+#[derive(Parser, Debug)]
+#[command(
+    name = "knocket",
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path to the ROM to run
+    #[arg(required = true)]
+    pub rom: Option<PathBuf>,
+
+    /// Window scale factor (1, 2, 4, 8, 16 or 32)
+    #[arg(short, long, default_value_t = 8, value_parser = parse_scale)]
+    pub scale: u8,
+
+    /// Number of instructions executed per 60 Hz frame
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    pub ipf: u32,
+
+    /// Clock speed in Hz, overrides --ipf (e.g. 600 runs 10 instructions per frame)
+    #[arg(short, long, conflicts_with = "ipf", value_parser = clap::value_parser!(u32).range(60..))]
+    pub clock: Option<u32>,
+
+    /// Quirk profile matching the interpreter the ROM was written for
+    #[arg(short, long, value_enum, default_value_t = QuirkProfile::Vip)]
+    pub quirks: QuirkProfile,
+
+    /// File mapping keyboard keys to CHIP-8 keys, one `Key = hex` pair per line
+    #[arg(short, long)]
+    pub keymap: Option<PathBuf>,
+
+    /// Log level (off, error, warn, info, debug or trace)
+    #[arg(short, long, default_value_t = LevelFilter::Error)]
+    pub log_level: LevelFilter,
+
+    /// Run without opening a window, until --frames or the --play movie runs out
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Stop after running this many frames
+    #[arg(short, long)]
+    pub frames: Option<u64>,
+
+    /// Seed for the random number generator, picked from the clock when not given
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Record the input of every frame to a movie file
+    #[arg(long, conflicts_with = "play")]
+    pub record: Option<PathBuf>,
+
+    /// Play back a movie recorded with --record instead of reading the keyboard
+    #[arg(long)]
+    pub play: Option<PathBuf>,
+
+    /// Write the emitted audio to a WAV file
+    #[arg(long)]
+    pub wav: Option<PathBuf>,
+
+    /// Disable live audio playback
+    #[arg(long)]
+    pub mute: bool,
+
+    /// Start paused and read debugger commands from standard input
+    #[arg(long, conflicts_with_all = ["record", "play"])]
+    pub debug: bool,
+
+    /// Start paused and wait for a GDB remote protocol debugger to connect on this port
+    #[arg(long, conflicts_with_all = ["record", "play", "debug"])]
+    pub gdb: Option<u16>,
+
+    /// Debug in a full-screen terminal UI instead of a window
+    #[arg(long, conflicts_with_all = ["headless", "record", "play", "debug", "gdb"])]
+    pub tui: bool,
+}
+
+impl Args {
+    /// The ROM to run, always given when there is no subcommand
+    pub fn rom(&self) -> &Path {
+        self.rom
+            .as_deref()
+            .expect("the ROM is required without a subcommand")
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// List the code reachable from the start of a ROM, with its data
+    Disasm(DisasmArgs),
+    /// Assemble an Octo program into a ROM
+    Asm(AsmArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DisasmArgs {
+    /// Path to the ROM to disassemble
+    pub rom: PathBuf,
+
+    /// Mnemonics to list the program with
+    #[arg(short, long, value_enum, default_value_t = ListingSyntax::Octo)]
+    pub syntax: ListingSyntax,
+
+    /// Write the listing to a file instead of standard output
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct AsmArgs {
+    /// Path to the Octo source
+    pub source: PathBuf,
+
+    /// Path to write the ROM to, defaults to the source with the extension .ch8
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ListingSyntax {
+    /// Octo assembly, which assembles back into the ROM
+    Octo,
+    /// The mnemonics of Cowgod's technical reference
+    Cowgod,
+}
+
+impl ListingSyntax {
+    pub fn syntax(self) -> Syntax {
+        match self {
+            ListingSyntax::Octo => Syntax::Octo,
+            ListingSyntax::Cowgod => Syntax::Cowgod,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum QuirkProfile {
+    /// The original COSMAC VIP interpreter
+    Vip,
+    /// CHIP-48 on the HP-48 calculators
+    Chip48,
+    /// SUPER-CHIP 1.1
+    Schip,
+    /// XO-CHIP as implemented by Octo
+    Xochip,
+}
+
+impl QuirkProfile {
+    /// The preset named like the profile, the same lookup knocket-headless uses
+    pub fn quirks(self) -> Quirks {
+        let value = self.to_possible_value().expect("every profile has a name");
+        Quirks::preset(value.get_name()).expect("every profile names a preset")
+    }
+}
+
+fn parse_scale(value: &str) -> Result<u8, String> {
+    let scale: u8 = value
+        .parse()
+        .map_err(|_| format!("`{}` is not a number", value))?;
+
+    if VALID_SCALES.contains(&scale) {
+        Ok(scale)
+    } else {
+        Err(format!("scale must be one of {:?}", VALID_SCALES))
+    }
+}
+
+#[test]
+fn test_parse_args() {
+    let args =
+        Args::try_parse_from(["knocket", "--scale", "4", "--ipf", "20", "game.ch8"]).unwrap();
+
+    assert_eq!(Path::new("game.ch8"), args.rom());
+    assert!(args.command.is_none());
+    assert_eq!(4, args.scale);
+    assert_eq!(20, args.ipf);
+    assert_eq!(LevelFilter::Error, args.log_level);
+    assert_eq!(QuirkProfile::Vip, args.quirks);
+    assert!(!args.headless);
+}
+
+#[test]
+fn test_parse_args_requires_rom() {
+    assert!(Args::try_parse_from(["knocket"]).is_err());
+}
+
+#[test]
+fn test_parse_args_invalid_scale() {
+    assert!(Args::try_parse_from(["knocket", "--scale", "3", "game.ch8"]).is_err());
+}
+
+#[test]
+fn test_parse_args_quirks() {
+    let args = Args::try_parse_from(["knocket", "--quirks", "schip", "game.ch8"]).unwrap();
+
+    assert_eq!(QuirkProfile::Schip, args.quirks);
+    assert_eq!(Quirks::SCHIP_1_1, args.quirks.quirks());
+    for profile in QuirkProfile::value_variants() {
+        profile.quirks();
+    }
+}
+
+#[test]
+fn test_parse_args_clock_conflicts_with_ipf() {
+    let args = Args::try_parse_from(["knocket", "--clock", "1200", "game.ch8"]).unwrap();
+    assert_eq!(Some(1200), args.clock);
+
+    assert!(
+        Args::try_parse_from(["knocket", "--clock", "1200", "--ipf", "5", "game.ch8"]).is_err()
+    );
+}
+
+#[test]
+fn test_parse_args_disasm() {
+    let args =
+        Args::try_parse_from(["knocket", "disasm", "--syntax", "cowgod", "game.ch8"]).unwrap();
+
+    match args.command {
+        Some(Command::Disasm(disasm)) => {
+            assert_eq!(PathBuf::from("game.ch8"), disasm.rom);
+            assert_eq!(ListingSyntax::Cowgod, disasm.syntax);
+            assert_eq!(None, disasm.output);
+        }
+        _ => panic!("expected the disasm subcommand"),
+    }
+    assert!(Args::try_parse_from(["knocket", "--ipf", "5", "disasm", "game.ch8"]).is_err());
+}
+
+#[test]
+fn test_parse_args_asm() {
+    let args = Args::try_parse_from(["knocket", "asm", "game.8o"]).unwrap();
+
+    match args.command {
+        Some(Command::Asm(asm)) => {
+            assert_eq!(PathBuf::from("game.8o"), asm.source);
+            assert_eq!(None, asm.output);
+        }
+        _ => panic!("expected the asm subcommand"),
+    }
+}
+
+#[test]
+fn test_parse_args_debug_conflicts_with_movies() {
+    let args = Args::try_parse_from(["knocket", "--debug", "game.ch8"]).unwrap();
+    assert!(args.debug);
+
+    assert!(
+        Args::try_parse_from(["knocket", "--debug", "--play", "run.movie", "game.ch8"]).is_err()
+    );
+}
+
+#[test]
+fn test_parse_args_tui_conflicts_with_headless() {
+    let args = Args::try_parse_from(["knocket", "--tui", "game.ch8"]).unwrap();
+    assert!(args.tui);
+
+    assert!(Args::try_parse_from(["knocket", "--tui", "--headless", "game.ch8"]).is_err());
+}
@@ -0,0 +1,712 @@
+use knocket::condition::parse_condition;
+use knocket::debugger::{Debugger, Register, Stop};
+use knocket::disassembler::{self, Syntax};
+use knocket::gdb::GdbStub;
+use knocket::instruction::decode;
+use knocket::mem::{Access, Watchpoint};
+use knocket::Chip8;
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::net::TcpListener;
+#[cfg(feature = "window")]
+use std::sync::mpsc::{self, Receiver};
+#[cfg(feature = "window")]
+use std::thread;
+use std::time::Duration;
+
+/// Most instructions `continue`, `next` and `until` run before pausing again, so a program
+/// that never reaches the breakpoint does not hang the console
+pub const RUN_LIMIT: u64 = 10_000_000;
+const PROMPT: &str = "(knocket) ";
+const BANNER: &str = "Paused at the start of the program, type help for the debugger commands";
+
+const HELP: &str = "\
+Addresses are hexadecimal, with or without 0x.
+  s, step [N]              run N instructions, 1 by default
+  n, next                  run one instruction, or a whole subroutine when it is a call
+  c, continue              run until a breakpoint or watch
+  p, pause                 stop running
+  u, until ADDR            run until the program counter reaches ADDR
+  b, break [ADDR [if C]]   set a breakpoint at ADDR, or list the breakpoints
+  d, delete ADDR           remove the breakpoint at ADDR
+  w, watch [REG]           stop when REG (V0-VF or I) changes, or list the watches
+  w, watch ADDR[-END] [r|w|rw]
+                           stop on reads, writes (the default) or both of memory
+  unwatch REG|ADDR[-END] [r|w|rw]
+                           remove a watch
+  r, regs                  show the registers, timers and stack
+  x, mem ADDR [LEN]        show LEN bytes of memory at ADDR, 64 by default
+  l, list [ADDR] [N]       disassemble N instructions at ADDR, the program counter by default
+  display                  show the display
+  q, quit                  exit knocket
+Conditions compare V0-VF, I, PC, DT, ST, [ADDR] and numbers with == != < <= > >=,
+joined with && and ||, e.g. break 204 if V3 == 0x10 && I > 0x300
+";
+
+enum Watch {
+    Register(Register),
+    Memory(Watchpoint),
+}
+
+pub enum Response {
+    Output(String),
+    Quit,
+}
+
+/// Parses debugger commands and runs them against the machine
+pub struct Repl {
+    pub debugger: Debugger,
+    /// In a window `continue` resumes the frame loop instead of running to a stop
+    windowed: bool,
+}
+
+impl Repl {
+    /// Creates a console with the machine paused
+    pub fn new(windowed: bool) -> Repl {
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        Repl { debugger, windowed }
+    }
+
+    pub fn execute(&mut self, line: &str, chip8: &mut Chip8, keypad: &[bool; 16]) -> Response {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let output = match words.split_first() {
+            Some((command, arguments)) => self.command(command, arguments, chip8, keypad),
+            None => return Response::Output(String::new()),
+        };
+
+        match output {
+            Ok(Some(output)) => Response::Output(output),
+            Ok(None) => Response::Quit,
+            Err(e) => Response::Output(format!("error: {}\n", e)),
+        }
+    }
+
+    fn command(
+        &mut self,
+        command: &str,
+        arguments: &[&str],
+        chip8: &mut Chip8,
+        keypad: &[bool; 16],
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let output = match command {
+            "h" | "help" | "?" => HELP.to_string(),
+            "s" | "step" => {
+                let count = match arguments.first() {
+                    Some(count) => count.parse().map_err(|_| "expected a count")?,
+                    None => 1,
+                };
+                let mut stop = Stop::Done;
+                for _ in 0..count {
+                    stop = self.debugger.step(chip8, keypad)?;
+                    if stop != Stop::Done {
+                        break;
+                    }
+                }
+                describe(stop, chip8)
+            }
+            "n" | "next" => {
+                let stop = self.debugger.step_over(chip8, keypad, RUN_LIMIT)?;
+                describe(stop, chip8)
+            }
+            "c" | "continue" if self.windowed => {
+                self.debugger.resume();
+                "Continuing\n".to_string()
+            }
+            "c" | "continue" => {
+                let stop = self.debugger.run(chip8, keypad, RUN_LIMIT)?;
+                describe(stop, chip8)
+            }
+            "p" | "pause" => {
+                self.debugger.pause();
+                describe(Stop::Done, chip8)
+            }
+            "u" | "until" => {
+                let address = address_argument(arguments.first())?;
+                let stop = self.debugger.run_to(chip8, keypad, address, RUN_LIMIT)?;
+                describe(stop, chip8)
+            }
+            "b" | "break" if arguments.is_empty() => {
+                let breakpoints: Vec<String> = self
+                    .debugger
+                    .breakpoints()
+                    .map(
+                        |address| match self.debugger.breakpoint_condition(address) {
+                            Some(condition) => format!("{:03x} if {}\n", address, condition),
+                            None => format!("{:03x}\n", address),
+                        },
+                    )
+                    .collect();
+                if breakpoints.is_empty() {
+                    "No breakpoints\n".to_string()
+                } else {
+                    breakpoints.concat()
+                }
+            }
+            "b" | "break" => {
+                let address = address_argument(arguments.first())?;
+                match arguments.get(1) {
+                    Some(&"if") => {
+                        let condition = parse_condition(&arguments[2..].join(" "))?;
+                        let output = format!("Breakpoint at {:03x} if {}\n", address, condition);
+                        self.debugger.add_conditional_breakpoint(address, condition);
+                        output
+                    }
+                    Some(_) => return Err("expected `if` after the address".into()),
+                    None => {
+                        self.debugger.add_breakpoint(address);
+                        format!("Breakpoint at {:03x}\n", address)
+                    }
+                }
+            }
+            "d" | "delete" => {
+                let address = address_argument(arguments.first())?;
+                if !self.debugger.remove_breakpoint(address) {
+                    return Err(format!("no breakpoint at {:03x}", address).into());
+                }
+                format!("Deleted breakpoint at {:03x}\n", address)
+            }
+            "w" | "watch" if arguments.is_empty() => {
+                let mut watches: Vec<String> = self
+                    .debugger
+                    .watched_registers()
+                    .map(|register| format!("{}\n", register_name(register)))
+                    .collect();
+                for watchpoint in chip8.mem.watchpoints() {
+                    watches.push(format!("{}\n", watchpoint_name(watchpoint)));
+                }
+                if watches.is_empty() {
+                    "No watches\n".to_string()
+                } else {
+                    watches.concat()
+                }
+            }
+            "w" | "watch" => match watch_arguments(arguments)? {
+                Watch::Register(register) => {
+                    self.debugger.watch_register(register);
+                    format!("Watching {}\n", register_name(register))
+                }
+                Watch::Memory(watchpoint) => {
+                    let output = format!("Watching {}\n", watchpoint_name(&watchpoint));
+                    chip8.mem.add_watchpoint(watchpoint);
+                    output
+                }
+            },
+            "unwatch" => {
+                let (removed, name) = match watch_arguments(arguments)? {
+                    Watch::Register(register) => (
+                        self.debugger.unwatch_register(register),
+                        register_name(register),
+                    ),
+                    Watch::Memory(watchpoint) => (
+                        chip8.mem.remove_watchpoint(&watchpoint),
+                        watchpoint_name(&watchpoint),
+                    ),
+                };
+                if !removed {
+                    return Err(format!("not watching {}", name).into());
+                }
+                format!("Stopped watching {}\n", name)
+            }
+            "r" | "regs" => registers(chip8),
+            "x" | "mem" => {
+                let address = address_argument(arguments.first())?;
+                let length = match arguments.get(1) {
+                    Some(length) => length.parse().map_err(|_| "expected a length")?,
+                    None => 64,
+                };
+                memory(chip8, address, length)
+            }
+            "l" | "list" => {
+                let address = match arguments.first() {
+                    Some(_) => address_argument(arguments.first())?,
+                    None => chip8.cpu.program_counter(),
+                };
+                let count = match arguments.get(1) {
+                    Some(count) => count.parse().map_err(|_| "expected a count")?,
+                    None => 10,
+                };
+                self.list(chip8, address, count)
+            }
+            "display" => chip8.display.to_text(),
+            "q" | "quit" => return Ok(None),
+            _ => return Err(format!("unknown command `{}`, try help", command).into()),
+        };
+
+        Ok(Some(output))
+    }
+
+    fn list(&self, chip8: &Chip8, address: u16, count: usize) -> String {
+        let mut output = String::new();
+        let mut address = address as usize;
+
+        for _ in 0..count {
+            let (opcode, text, size) = match instruction_at(chip8, address) {
+                Some(instruction) => instruction,
+                None => break,
+            };
+            let marker = if address == chip8.cpu.program_counter() as usize {
+                "=>"
+            } else if self.debugger.breakpoints().any(|b| b as usize == address) {
+                " *"
+            } else {
+                "  "
+            };
+            output += &format!("{} {:03x}: {:04x}  {}\n", marker, address, opcode, text);
+            address += size;
+        }
+
+        output
+    }
+}
+
+fn address_argument(argument: Option<&&str>) -> Result<u16, Box<dyn Error>> {
+    let text = argument.ok_or("expected an address")?;
+    let digits = text.trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("`{}` is not an address", text).into())
+}
+
+fn watch_arguments(arguments: &[&str]) -> Result<Watch, Box<dyn Error>> {
+    let target = arguments
+        .first()
+        .ok_or("expected a register or an address")?;
+    let register = match target.to_ascii_uppercase().as_str() {
+        "I" => Some(Register::I),
+        name if name.len() == 2 && name.starts_with('V') => {
+            u8::from_str_radix(&name[1..], 16).ok().map(Register::V)
+        }
+        _ => None,
+    };
+    if let Some(register) = register {
+        if arguments.len() > 1 {
+            return Err("registers are only watched for changes".into());
+        }
+        return Ok(Watch::Register(register));
+    }
+
+    let (start, end) = match target.split_once('-') {
+        Some((start, end)) => (
+            address_argument(Some(&start))?,
+            address_argument(Some(&end))?,
+        ),
+        None => {
+            let address = address_argument(Some(target))?;
+            (address, address)
+        }
+    };
+    if end < start {
+        return Err(format!("`{}` ends before it starts", target).into());
+    }
+    let access = match arguments.get(1) {
+        Some(&"r") => Access::Read,
+        Some(&"w") | None => Access::Write,
+        Some(&"rw") => Access::ReadWrite,
+        Some(access) => return Err(format!("`{}` is not r, w or rw", access).into()),
+    };
+
+    Ok(Watch::Memory(Watchpoint {
+        range: start as usize..=end as usize,
+        access,
+    }))
+}
+
+fn register_name(register: Register) -> String {
+    match register {
+        Register::V(register) => format!("V{:X}", register),
+        Register::I => "I".to_string(),
+    }
+}
+
+fn watchpoint_name(watchpoint: &Watchpoint) -> String {
+    let access = match watchpoint.access {
+        Access::Read => "reads of",
+        Access::Write => "writes to",
+        Access::ReadWrite => "accesses to",
+    };
+    let (start, end) = (watchpoint.range.start(), watchpoint.range.end());
+    if start == end {
+        format!("{} {:03x}", access, start)
+    } else {
+        format!("{} {:03x}-{:03x}", access, start, end)
+    }
+}
+
+/// The opcode at `address`, its disassembly and the size of the instruction in bytes.
+/// Opcodes that are not instructions are shown as data.
+pub fn instruction_at(chip8: &Chip8, address: usize) -> Option<(u16, String, usize)> {
+    let opcode = chip8.mem.fetch_opcode(address).ok()?;
+    let instruction = match decode(opcode) {
+        Ok(instruction) => instruction,
+        Err(_) => {
+            let text = format!("0x{:02X} 0x{:02X}", opcode >> 8, opcode & 0xFF);
+            return Some((opcode, text, 2));
+        }
+    };
+    let long = chip8.mem.fetch_opcode(address + 2).unwrap_or(0);
+    let text = disassembler::format_instruction(instruction, long, Syntax::Octo);
+    Some((opcode, text, instruction.size() as usize))
+}
+
+/// Why execution stopped, then the instruction it stopped at
+pub fn describe(stop: Stop, chip8: &Chip8) -> String {
+    let program_counter = chip8.cpu.program_counter();
+    let reason = match stop {
+        Stop::Done => String::new(),
+        Stop::Breakpoint(address) => format!("Breakpoint at {:03x}\n", address),
+        Stop::Halted => return "The program has exited\n".to_string(),
+        Stop::Limit => format!("Still running after {} instructions\n", RUN_LIMIT),
+        Stop::Watchpoint { address, hit } => {
+            let access = match hit.access {
+                Access::Read => "Read of",
+                _ => "Write of",
+            };
+            format!(
+                "{} {:02x} at {:03x} by the instruction at {:03x}\n",
+                access, hit.value, hit.address, address
+            )
+        }
+        Stop::RegisterChanged {
+            address,
+            register,
+            old,
+            new,
+        } => format!(
+            "{} changed from {:02x} to {:02x} by the instruction at {:03x}\n",
+            register_name(register),
+            old,
+            new,
+            address
+        ),
+    };
+    let instruction = match instruction_at(chip8, program_counter as usize) {
+        Some((opcode, text, _)) => format!("{:04x}  {}", opcode, text),
+        None => "outside of memory".to_string(),
+    };
+
+    format!("{}=> {:03x}: {}\n", reason, program_counter, instruction)
+}
+
+fn registers(chip8: &Chip8) -> String {
+    let cpu = &chip8.cpu;
+    let mut output = String::new();
+    for row in cpu.registers().chunks(8).enumerate() {
+        let (row, values) = row;
+        let cells: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(column, value)| format!("V{:X} {:02x}", row * 8 + column, value))
+            .collect();
+        output += &cells.join("  ");
+        output += "\n";
+    }
+    output += &format!(
+        "I {:04x}  PC {:04x}  DT {:02x}  ST {:02x}\n",
+        cpu.index(),
+        cpu.program_counter(),
+        cpu.delay_timer(),
+        cpu.sound_timer()
+    );
+    let stack: Vec<String> = chip8
+        .mem
+        .stack()
+        .iter()
+        .map(|address| format!("{:03x}", address))
+        .collect();
+    if stack.is_empty() {
+        output += "stack: empty\n";
+    } else {
+        output += &format!("stack: {}\n", stack.join(" "));
+    }
+
+    output
+}
+
+fn memory(chip8: &Chip8, address: u16, length: usize) -> String {
+    let memory = chip8.mem.memory();
+    let start = (address as usize).min(memory.len());
+    let end = (start + length).min(memory.len());
+    let mut output = String::new();
+
+    for (row, bytes) in memory[start..end].chunks(16).enumerate() {
+        let cells: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        output += &format!("{:04x}: {}\n", start + row * 16, cells.join(" "));
+    }
+
+    output
+}
+
+fn print_prompt() {
+    print!("{}", PROMPT);
+    io::stdout().flush().ok();
+}
+
+/// Prints the output of a command followed by the prompt for the next
+fn print_response(output: &str) {
+    print!("{}", output);
+    print_prompt();
+}
+
+/// The debugger of a window, reading commands from standard input on another thread so the
+/// window keeps drawing while it waits for them
+#[cfg(feature = "window")]
+pub struct WindowConsole {
+    repl: Repl,
+    commands: Receiver<String>,
+}
+
+#[cfg(feature = "window")]
+impl WindowConsole {
+    pub fn open() -> WindowConsole {
+        let (sender, commands) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        println!("{}", BANNER);
+        print_prompt();
+
+        WindowConsole {
+            repl: Repl::new(true),
+            commands,
+        }
+    }
+
+    /// Runs the commands typed since the last poll, returns false once one of them quit
+    pub fn poll(&mut self, chip8: &mut Chip8, keypad: &[bool; 16]) -> bool {
+        while let Ok(line) = self.commands.try_recv() {
+            match self.repl.execute(&line, chip8, keypad) {
+                Response::Output(output) => print_response(&output),
+                Response::Quit => return false,
+            }
+        }
+        true
+    }
+
+    /// Runs the rest of the frame unless paused, returns true when the frame completed
+    pub fn run_frame(&mut self, chip8: &mut Chip8, keypad: &[bool; 16]) -> bool {
+        if self.repl.debugger.paused() {
+            return false;
+        }
+        match self.repl.debugger.run_frame(chip8, keypad) {
+            Ok(None) => return true,
+            Ok(Some(stop)) => print_response(&format!("\n{}", describe(stop, chip8))),
+            Err(e) => print_response(&format!("\nerror: {}\n", e)),
+        }
+        false
+    }
+
+    /// Call after the machine jumped to a state saved between frames
+    pub fn restart_frame(&mut self) {
+        self.repl.debugger.restart_frame();
+    }
+}
+
+/// A debugger driving the window's frame loop, from standard input or over GDB's protocol
+#[cfg(feature = "window")]
+pub enum Debugging {
+    Console(WindowConsole),
+    Gdb(GdbStub),
+}
+
+#[cfg(feature = "window")]
+impl Debugging {
+    /// Opens the debugger asked for on the command line, if any
+    pub fn open(args: &crate::cli::Args) -> Result<Option<Debugging>, Box<dyn Error>> {
+        if args.debug {
+            Ok(Some(Debugging::Console(WindowConsole::open())))
+        } else if let Some(port) = args.gdb {
+            Ok(Some(Debugging::Gdb(accept_gdb(port)?)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Handles the commands received since the last poll, returns false once the debugger
+    /// quit or disconnected
+    pub fn poll(&mut self, chip8: &mut Chip8, keypad: &[bool; 16]) -> Result<bool, Box<dyn Error>> {
+        match self {
+            Debugging::Console(console) => Ok(console.poll(chip8, keypad)),
+            Debugging::Gdb(stub) => Ok(stub.poll(chip8, keypad)?),
+        }
+    }
+
+    /// Runs the rest of the frame unless paused, returns true when the frame completed
+    pub fn run_frame(
+        &mut self,
+        chip8: &mut Chip8,
+        keypad: &[bool; 16],
+    ) -> Result<bool, Box<dyn Error>> {
+        match self {
+            Debugging::Console(console) => Ok(console.run_frame(chip8, keypad)),
+            Debugging::Gdb(stub) => Ok(stub.run_frame(chip8, keypad)?),
+        }
+    }
+
+    pub fn restart_frame(&mut self) {
+        match self {
+            Debugging::Console(console) => console.restart_frame(),
+            Debugging::Gdb(stub) => stub.restart_frame(),
+        }
+    }
+}
+
+/// Waits for a debugger to connect on `port` of the loopback interface
+fn accept_gdb(port: u16) -> Result<GdbStub, Box<dyn Error>> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .map_err(|e| format!("Could not listen on port {}: {}", port, e))?;
+    println!(
+        "Waiting for a debugger on {}, e.g. gdb -ex 'target remote {}'",
+        listener.local_addr()?,
+        listener.local_addr()?
+    );
+    let (stream, address) = listener.accept()?;
+    log::info!("Debugger connected from {}", address);
+    Ok(GdbStub::new(stream))
+}
+
+/// Serves a GDB remote protocol debugger without a window, until it detaches
+pub fn run_gdb(chip8: &mut Chip8, port: u16) -> Result<(), Box<dyn Error>> {
+    let mut stub = accept_gdb(port)?;
+    let keypad = [false; 16];
+
+    while stub.poll(chip8, &keypad)? {
+        // nothing to run while the debugger has the program stopped
+        if !stub.run_frame(chip8, &keypad)? {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+    Ok(())
+}
+
+/// Runs the debugger on standard input without a window, until quit or end of input
+pub fn run_console(chip8: &mut Chip8) -> Result<(), Box<dyn Error>> {
+    let mut repl = Repl::new(false);
+    let keypad = [false; 16];
+    println!("{}", BANNER);
+    print_prompt();
+
+    for line in io::stdin().lock().lines() {
+        match repl.execute(&line?, chip8, &keypad) {
+            Response::Output(output) => print_response(&output),
+            Response::Quit => return Ok(()),
+        }
+    }
+
+    println!();
+    Ok(())
+}
+
+#[cfg(test)]
+fn run_commands(repl: &mut Repl, chip8: &mut Chip8, commands: &[&str]) -> String {
+    let mut output = String::new();
+    for command in commands {
+        match repl.execute(command, chip8, &[false; 16]) {
+            Response::Output(text) => output += &text,
+            Response::Quit => output += "quit\n",
+        }
+    }
+    output
+}
+
+#[test]
+fn test_repl_step_and_registers() {
+    let mut chip8 = Chip8::default();
+    // V3 := 0x10, I := 0x300, call 0x208, exit, return
+    chip8
+        .load_program(&[0x63, 0x10, 0xA3, 0x00, 0x22, 0x08, 0x00, 0xFD, 0x00, 0xEE])
+        .unwrap();
+    let mut repl = Repl::new(false);
+
+    let output = run_commands(&mut repl, &mut chip8, &["step 3", "regs"]);
+
+    assert!(output.contains("=> 208: 00ee  return\n"));
+    assert!(output.contains("V3 10"));
+    assert!(output.contains("I 0300  PC 0208"));
+    assert!(output.contains("stack: 204\n"));
+}
+
+#[test]
+fn test_repl_breakpoints() {
+    let mut chip8 = Chip8::default();
+    // V0 += 1, loop
+    chip8.load_program(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+    let mut repl = Repl::new(false);
+
+    let output = run_commands(
+        &mut repl,
+        &mut chip8,
+        &["break 0x202", "continue", "continue", "break", "delete 202"],
+    );
+
+    assert!(output.contains("Breakpoint at 202\n=> 202: 1200  jump 0x200\n"));
+    assert!(output.contains("\n202\nDeleted breakpoint at 202\n"));
+    assert_eq!(2, chip8.cpu.registers()[0]);
+}
+
+#[test]
+fn test_repl_list_and_memory() {
+    let mut chip8 = Chip8::default();
+    chip8.load_program(&[0x00, 0xE0, 0x12, 0x00]).unwrap();
+    let mut repl = Repl::new(false);
+
+    let output = run_commands(&mut repl, &mut chip8, &["b 202", "list 200 2", "x 200 4"]);
+
+    assert!(output.contains("=> 200: 00e0  clear\n * 202: 1200  jump 0x200\n"));
+    assert!(output.contains("0200: 00 e0 12 00\n"));
+}
+
+#[test]
+fn test_repl_errors_and_quit() {
+    let mut chip8 = Chip8::default();
+    let mut repl = Repl::new(false);
+
+    let output = run_commands(&mut repl, &mut chip8, &["frobnicate", "break zz", "quit"]);
+
+    assert!(output.contains("error: unknown command `frobnicate`, try help\n"));
+    assert!(output.contains("error: `zz` is not an address\n"));
+    assert!(output.ends_with("quit\n"));
+}
+
+#[test]
+fn test_repl_conditions_and_watches() {
+    let mut chip8 = Chip8::default();
+    // V0 += 1, I := 0x300, save v0, loop
+    chip8
+        .load_program(&[0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00])
+        .unwrap();
+    let mut repl = Repl::new(false);
+
+    let output = run_commands(
+        &mut repl,
+        &mut chip8,
+        &["break 200 if V0 == 2 && I > 0x2ff", "break", "continue"],
+    );
+    assert!(output.contains("200 if V0 == 0x2 && I > 0x2ff\n"));
+    assert!(output.contains("Breakpoint at 200\n=> 200: 7001  v0 += 0x01\n"));
+    assert_eq!(2, chip8.cpu.registers()[0]);
+
+    let output = run_commands(
+        &mut repl,
+        &mut chip8,
+        &[
+            "delete 200",
+            "watch 300-30f w",
+            "watch vf",
+            "watch",
+            "continue",
+        ],
+    );
+    assert!(output.contains("VF\nwrites to 300-30f\n"));
+    assert!(output.contains("Write of 03 at 300 by the instruction at 204\n=> 206:"));
+
+    let output = run_commands(
+        &mut repl,
+        &mut chip8,
+        &["unwatch 300-30f", "unwatch 300-30f", "watch v0", "continue"],
+    );
+    assert!(output.contains("error: not watching writes to 300-30f\n"));
+    assert!(output.contains("V0 changed from 03 to 04 by the instruction at 200\n"));
+}
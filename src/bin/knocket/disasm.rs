@@ -0,0 +1,20 @@
+use crate::cli;
+use knocket::disassembler;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+
+/// Prints or writes the listing of the ROM
+pub fn run(args: &cli::DisasmArgs) -> Result<(), Box<dyn Error>> {
+    let program = fs::read(&args.rom)
+        .map_err(|e| format!("Could not read ROM {}: {}", args.rom.display(), e))?;
+    let listing = disassembler::disassemble(&program).to_text(args.syntax.syntax());
+
+    match &args.output {
+        Some(path) => fs::write(path, listing)
+            .map_err(|e| format!("Could not write {}: {}", path.display(), e))?,
+        None => io::stdout().write_all(listing.as_bytes())?,
+    }
+
+    Ok(())
+}
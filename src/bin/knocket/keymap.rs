@@ -0,0 +1,205 @@
+use minifb::Key;
+use std::collections::HashMap;
+
+pub type KeyMap = HashMap<Key, usize>;
+
+/// Held to rewind, so it can't be bound to the keypad
+pub const REWIND_KEY: Key = Key::Backspace;
+
+/// F1-F9 load the save state in slot 1-9, holding shift saves to it
+pub const SLOT_KEYS: [Key; 9] = [
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+];
+
+/*
+    The default layout maps the left side of a QWERTY keyboard onto the hex keypad:
+
+    1 2 3 4        1 2 3 C
+    Q W E R   ->   4 5 6 D
+    A S D F        7 8 9 E
+    Z X C V        A 0 B F
+*/
+const DEFAULT_LAYOUT: [(Key, usize); 16] = [
+    (Key::Key1, 0x1),
+    (Key::Key2, 0x2),
+    (Key::Key3, 0x3),
+    (Key::Key4, 0xC),
+    (Key::Q, 0x4),
+    (Key::W, 0x5),
+    (Key::E, 0x6),
+    (Key::R, 0xD),
+    (Key::A, 0x7),
+    (Key::S, 0x8),
+    (Key::D, 0x9),
+    (Key::F, 0xE),
+    (Key::Z, 0xA),
+    (Key::X, 0x0),
+    (Key::C, 0xB),
+    (Key::V, 0xF),
+];
+
+pub fn default_keymap() -> KeyMap {
+    DEFAULT_LAYOUT.iter().cloned().collect()
+}
+
+/// Parses a key map file where every line has the form `Key = hex`, e.g. `W = 5`.
+/// Empty lines and lines starting with `#` are ignored, and the rewind and save slot keys
+/// are rejected.
+pub fn parse_keymap(source: &str) -> Result<KeyMap, String> {
+    let mut key_map = KeyMap::new();
+
+    for (line_number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected `Key = hex`", line_number + 1))?;
+
+        let key = parse_key(name.trim())
+            .ok_or_else(|| format!("line {}: unknown key `{}`", line_number + 1, name.trim()))?;
+        if key == REWIND_KEY || SLOT_KEYS.contains(&key) {
+            return Err(format!(
+                "line {}: `{}` is reserved for rewinding and save states",
+                line_number + 1,
+                name.trim()
+            ));
+        }
+
+        let keypad_index = usize::from_str_radix(value.trim(), 16)
+            .ok()
+            .filter(|index| *index < 16)
+            .ok_or_else(|| {
+                format!(
+                    "line {}: `{}` is not a CHIP-8 key (0-F)",
+                    line_number + 1,
+                    value.trim()
+                )
+            })?;
+
+        key_map.insert(key, keypad_index);
+    }
+
+    Ok(key_map)
+}
+
+fn parse_key(name: &str) -> Option<Key> {
+    let key = match name.to_ascii_uppercase().as_str() {
+        "0" => Key::Key0,
+        "1" => Key::Key1,
+        "2" => Key::Key2,
+        "3" => Key::Key3,
+        "4" => Key::Key4,
+        "5" => Key::Key5,
+        "6" => Key::Key6,
+        "7" => Key::Key7,
+        "8" => Key::Key8,
+        "9" => Key::Key9,
+        "A" => Key::A,
+        "B" => Key::B,
+        "C" => Key::C,
+        "D" => Key::D,
+        "E" => Key::E,
+        "F" => Key::F,
+        "G" => Key::G,
+        "H" => Key::H,
+        "I" => Key::I,
+        "J" => Key::J,
+        "K" => Key::K,
+        "L" => Key::L,
+        "M" => Key::M,
+        "N" => Key::N,
+        "O" => Key::O,
+        "P" => Key::P,
+        "Q" => Key::Q,
+        "R" => Key::R,
+        "S" => Key::S,
+        "T" => Key::T,
+        "U" => Key::U,
+        "V" => Key::V,
+        "W" => Key::W,
+        "X" => Key::X,
+        "Y" => Key::Y,
+        "Z" => Key::Z,
+        "NUMPAD0" => Key::NumPad0,
+        "NUMPAD1" => Key::NumPad1,
+        "NUMPAD2" => Key::NumPad2,
+        "NUMPAD3" => Key::NumPad3,
+        "NUMPAD4" => Key::NumPad4,
+        "NUMPAD5" => Key::NumPad5,
+        "NUMPAD6" => Key::NumPad6,
+        "NUMPAD7" => Key::NumPad7,
+        "NUMPAD8" => Key::NumPad8,
+        "NUMPAD9" => Key::NumPad9,
+        "UP" => Key::Up,
+        "DOWN" => Key::Down,
+        "LEFT" => Key::Left,
+        "RIGHT" => Key::Right,
+        "SPACE" => Key::Space,
+        "ENTER" => Key::Enter,
+        "TAB" => Key::Tab,
+        "BACKSPACE" => Key::Backspace,
+        "F1" => Key::F1,
+        "F2" => Key::F2,
+        "F3" => Key::F3,
+        "F4" => Key::F4,
+        "F5" => Key::F5,
+        "F6" => Key::F6,
+        "F7" => Key::F7,
+        "F8" => Key::F8,
+        "F9" => Key::F9,
+        "F10" => Key::F10,
+        "F11" => Key::F11,
+        "F12" => Key::F12,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+#[test]
+fn test_default_keymap() {
+    let key_map = default_keymap();
+
+    assert_eq!(16, key_map.len());
+    assert_eq!(Some(&0x5), key_map.get(&Key::W));
+    assert_eq!(Some(&0x0), key_map.get(&Key::X));
+}
+
+#[test]
+fn test_parse_keymap() {
+    let key_map = parse_keymap("# arrows\nUp = 5\n\nleft=7\nSpace = a\n").unwrap();
+
+    assert_eq!(3, key_map.len());
+    assert_eq!(Some(&0x5), key_map.get(&Key::Up));
+    assert_eq!(Some(&0x7), key_map.get(&Key::Left));
+    assert_eq!(Some(&0xA), key_map.get(&Key::Space));
+}
+
+#[test]
+fn test_parse_keymap_errors() {
+    assert!(parse_keymap("W 5").is_err());
+    assert!(parse_keymap("Hyper = 5").is_err());
+    assert!(parse_keymap("W = 10").is_err());
+}
+
+#[test]
+fn test_parse_keymap_reserved_keys() {
+    assert_eq!(
+        Err("line 2: `Backspace` is reserved for rewinding and save states".to_string()),
+        parse_keymap("W = 5\nBackspace = 0")
+    );
+    assert!(parse_keymap("F1 = 0").is_err());
+    assert!(parse_keymap("f9 = 0").is_err());
+    assert_eq!(Some(&0x0), parse_keymap("F10 = 0").unwrap().get(&Key::F10));
+}
@@ -0,0 +1,131 @@
+mod asm;
+mod audio;
+mod cli;
+mod debug;
+mod disasm;
+#[cfg(feature = "window")]
+mod keymap;
+mod movie;
+#[cfg(feature = "tui")]
+mod tui;
+#[cfg(feature = "window")]
+mod window;
+
+use clap::Parser;
+use knocket::Chip8;
+use simple_logger::SimpleLogger;
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, process};
+
+fn load_rom(chip8: &mut Chip8, args: &cli::Args) -> Result<(), Box<dyn Error>> {
+    let rom = args.rom();
+    let buffer =
+        fs::read(rom).map_err(|e| format!("Could not read ROM {}: {}", rom.display(), e))?;
+
+    chip8
+        .load_program(&buffer)
+        .map_err(|e| format!("Could not load ROM {}: {}", rom.display(), e))?;
+
+    Ok(())
+}
+
+fn run_headless(chip8: &mut Chip8, args: &cli::Args) -> Result<(), Box<dyn Error>> {
+    if args.debug {
+        return debug::run_console(chip8);
+    }
+    if let Some(port) = args.gdb {
+        return debug::run_gdb(chip8, port);
+    }
+
+    if args.frames.is_none() && args.play.is_none() {
+        return Err("--headless needs --frames or --play to know when to stop".into());
+    }
+
+    let mut frame: u64 = 0;
+    let mut session = movie::Session::open(chip8, args)?;
+    let mut audio = audio::AudioOutput::open(chip8, args, false)?;
+    let mut result = Ok(());
+
+    while !chip8.halted() && !chip8.jumping_to_self() && args.frames != Some(frame) {
+        let keypad = match session.keypad(&[false; 16]) {
+            Some(keypad) => keypad,
+            None => break,
+        };
+        result = chip8.run_frame(&keypad);
+        if result.is_err() {
+            break;
+        }
+        audio.frame(chip8)?;
+        frame += 1;
+    }
+
+    // a movie ending in an emulator error is the one worth keeping
+    session.finish(args)?;
+    audio.finish()?;
+    Ok(result?)
+}
+
+#[cfg(feature = "tui")]
+fn run_tui(chip8: &mut Chip8) -> Result<(), Box<dyn Error>> {
+    tui::run(chip8)
+}
+
+#[cfg(not(feature = "tui"))]
+fn run_tui(_chip8: &mut Chip8) -> Result<(), Box<dyn Error>> {
+    Err("knocket was built without the `tui` feature".into())
+}
+
+#[cfg(feature = "window")]
+fn run_window(chip8: &mut Chip8, args: &cli::Args) -> Result<(), Box<dyn Error>> {
+    window::run(chip8, args)
+}
+
+#[cfg(not(feature = "window"))]
+fn run_window(_chip8: &mut Chip8, _args: &cli::Args) -> Result<(), Box<dyn Error>> {
+    Err("knocket was built without the `window` feature, use --headless".into())
+}
+
+fn run(args: cli::Args) -> Result<(), Box<dyn Error>> {
+    SimpleLogger::new().with_level(args.log_level).init()?;
+    match &args.command {
+        Some(cli::Command::Disasm(disasm_args)) => return disasm::run(disasm_args),
+        Some(cli::Command::Asm(asm_args)) => return asm::run(asm_args),
+        None => {}
+    }
+
+    let mut chip8 = Chip8::new(args.quirks.quirks());
+    let seed = args.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64)
+    });
+    log::info!(
+        "Random seed {}, pass --seed {} to repeat this run",
+        seed,
+        seed
+    );
+    chip8.set_seed(seed);
+    match args.clock {
+        Some(hz) => chip8.set_clock_speed(hz),
+        None => chip8.set_instructions_per_frame(args.ipf),
+    }
+    load_rom(&mut chip8, &args)?;
+
+    if args.tui {
+        run_tui(&mut chip8)
+    } else if args.headless {
+        run_headless(&mut chip8, &args)
+    } else {
+        run_window(&mut chip8, &args)
+    }
+}
+
+fn main() {
+    let args = cli::Args::parse();
+
+    if let Err(e) = run(args) {
+        eprintln!("knocket: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,65 @@
+use crate::cli;
+use knocket::movie::Movie;
+use knocket::Chip8;
+use std::error::Error;
+use std::fs;
+
+/// Records the keypad of every frame with --record, or replaces it with a movie with --play
+pub struct Session {
+    recording: Option<Movie>,
+    playback: Option<Movie>,
+    frame: usize,
+}
+
+impl Session {
+    /// Must be called with the machine at power-on, right after loading the ROM
+    pub fn open(chip8: &mut Chip8, args: &cli::Args) -> Result<Session, Box<dyn Error>> {
+        let playback = match &args.play {
+            Some(path) => {
+                let data = fs::read(path)
+                    .map_err(|e| format!("Could not read movie {}: {}", path.display(), e))?;
+                let movie = Movie::from_bytes(&data)
+                    .and_then(|movie| movie.start_playback(chip8).map(|_| movie))
+                    .map_err(|e| format!("Could not play movie {}: {}", path.display(), e))?;
+                Some(movie)
+            }
+            None => None,
+        };
+        let recording = args.record.as_ref().map(|_| Movie::new(chip8));
+
+        Ok(Session {
+            recording,
+            playback,
+            frame: 0,
+        })
+    }
+
+    /// True while recording or playing back, when loading states or rewinding would break
+    /// the movie
+    #[cfg_attr(not(feature = "window"), allow(dead_code))]
+    pub fn active(&self) -> bool {
+        self.recording.is_some() || self.playback.is_some()
+    }
+
+    /// The keypad to run the next frame with, None once the movie has been played back
+    pub fn keypad(&mut self, keypad: &[bool; 16]) -> Option<[bool; 16]> {
+        let keypad = match &self.playback {
+            Some(movie) => movie.keypad(self.frame)?,
+            None => *keypad,
+        };
+        if let Some(movie) = self.recording.as_mut() {
+            movie.record(&keypad);
+        }
+        self.frame += 1;
+        Some(keypad)
+    }
+
+    /// Writes the recording, if any
+    pub fn finish(self, args: &cli::Args) -> Result<(), Box<dyn Error>> {
+        if let (Some(movie), Some(path)) = (self.recording, &args.record) {
+            fs::write(path, movie.to_bytes())
+                .map_err(|e| format!("Could not write movie {}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+}
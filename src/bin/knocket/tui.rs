@@ -0,0 +1,656 @@
+/*
+    Full-screen terminal debugger. Everything is drawn as text, so it works over SSH:
+
+    ┌─ Display ───────────────┐┌─ Registers ─┐
+    │    ▄▀▀▄                 ││ V0 00  V8 00│
+    └─────────────────────────┘└─────────────┘
+    ┌─ Code ─────────┐┌─ Memory ─────────────┐
+    │*=> 200: 00e0   ││0300 00 00 00 00 ...  │
+    └────────────────┘└──────────────────────┘
+    status
+    keys
+
+    Each character cell shows two pixels on top of each other. When that does not fit, e.g. on
+    an 80x24 terminal, each cell shows 2x4 pixels as braille dots, and hires frames are halved
+    further if needed. Terminals only report key presses, so a CHIP-8 key stays held for a few
+    frames after its key was pressed, and key repeat keeps it held.
+*/
+use crate::debug;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::Print;
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use knocket::chip8::FRAME_RATE;
+use knocket::debugger::{Debugger, Stop};
+use knocket::display::Display;
+use knocket::{Chip8, EmulatorError};
+use std::error::Error;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+const KEY_HOLD_FRAMES: u8 = 6;
+const REGISTERS_WIDTH: usize = 28;
+// rows the register panel needs without the call stack
+const REGISTER_ROWS: usize = 6;
+const CODE_WIDTH: usize = 40;
+// the code panel shows at least this many instructions
+const CODE_ROWS: usize = 4;
+/// Bytes PageUp and PageDown scroll the memory view by
+const MEMORY_PAGE: u16 = 0x80;
+/// Instructions listed above the cursor
+const CODE_CONTEXT: u16 = 4;
+// fits an 80 column terminal, Home is in the README
+const HELP: &str =
+    "F5 run/pause  F7 step  F8 over  F9 break  Up/Down code  PgUp/PgDn mem  Esc quit";
+
+// The same layout as the window's default key map
+const KEYPAD_LAYOUT: [(char, usize); 16] = [
+    ('1', 0x1),
+    ('2', 0x2),
+    ('3', 0x3),
+    ('4', 0xC),
+    ('q', 0x4),
+    ('w', 0x5),
+    ('e', 0x6),
+    ('r', 0xD),
+    ('a', 0x7),
+    ('s', 0x8),
+    ('d', 0x9),
+    ('f', 0xE),
+    ('z', 0xA),
+    ('x', 0x0),
+    ('c', 0xB),
+    ('v', 0xF),
+];
+
+/// How the display is drawn, from the largest to the smallest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scale {
+    /// 1x2 pixels per character as half blocks
+    HalfBlocks,
+    /// 2x4 pixels per character as braille dots
+    Braille,
+    /// 4x8 pixels per character as braille dots, each dot lit when any of its 2x2 pixels is
+    Halved,
+}
+
+impl Scale {
+    /// Pixels per character, across and down
+    fn cell(self) -> (usize, usize) {
+        match self {
+            Scale::HalfBlocks => (1, 2),
+            Scale::Braille => (2, 4),
+            Scale::Halved => (4, 8),
+        }
+    }
+}
+
+struct Tui {
+    debugger: Debugger,
+    /// Address breakpoints are toggled at, moves to the program counter on every stop
+    cursor: u16,
+    /// First address of the memory view, `None` to follow I
+    memory_start: Option<u16>,
+    /// Frames left that each CHIP-8 key is held for
+    held: [u8; 16],
+    status: String,
+    quit: bool,
+}
+
+impl Tui {
+    fn new(chip8: &Chip8) -> Tui {
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        Tui {
+            debugger,
+            cursor: chip8.cpu.program_counter(),
+            memory_start: None,
+            held: [0; 16],
+            status: "Paused at the start of the program, F5 runs it".to_string(),
+            quit: false,
+        }
+    }
+
+    fn keypad(&self) -> [bool; 16] {
+        let mut keypad = [false; 16];
+        for (key, &frames) in keypad.iter_mut().zip(self.held.iter()) {
+            *key = frames > 0;
+        }
+        keypad
+    }
+
+    /// Runs a frame unless paused, returns false when there was nothing to run
+    fn frame(&mut self, chip8: &mut Chip8) -> bool {
+        let keypad = self.keypad();
+        for frames in self.held.iter_mut() {
+            *frames = frames.saturating_sub(1);
+        }
+        if self.debugger.paused() {
+            return false;
+        }
+        match self.debugger.run_frame(chip8, &keypad) {
+            Ok(None) => {}
+            Ok(Some(stop)) => self.stopped(Ok(stop), chip8),
+            Err(e) => self.stopped(Err(e), chip8),
+        }
+        true
+    }
+
+    fn key(&mut self, key: KeyEvent, chip8: &mut Chip8) {
+        let keypad = self.keypad();
+        match key.code {
+            KeyCode::Esc => self.quit = true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+            KeyCode::F(5) if self.debugger.paused() => {
+                self.debugger.resume();
+                self.status = "Running, F5 pauses".to_string();
+            }
+            KeyCode::F(5) => {
+                self.debugger.pause();
+                self.stopped(Ok(Stop::Done), chip8);
+            }
+            KeyCode::F(7) => {
+                let result = self.debugger.step(chip8, &keypad);
+                self.stopped(result, chip8);
+            }
+            KeyCode::F(8) => {
+                let result = self.debugger.step_over(chip8, &keypad, debug::RUN_LIMIT);
+                self.stopped(result, chip8);
+            }
+            KeyCode::F(9) => {
+                self.status = if self.debugger.remove_breakpoint(self.cursor) {
+                    format!("Removed the breakpoint at {:03x}", self.cursor)
+                } else {
+                    self.debugger.add_breakpoint(self.cursor);
+                    format!("Breakpoint at {:03x}", self.cursor)
+                };
+            }
+            KeyCode::Up => self.cursor = self.cursor.saturating_sub(2),
+            KeyCode::Down => {
+                let size = debug::instruction_at(chip8, self.cursor as usize)
+                    .map_or(2, |(_, _, size)| size);
+                self.cursor = self.cursor.saturating_add(size as u16);
+            }
+            KeyCode::PageUp => {
+                let start = self.memory_view_start(chip8);
+                self.memory_start = Some(start.saturating_sub(MEMORY_PAGE));
+            }
+            KeyCode::PageDown => {
+                let last_row = (chip8.mem.size() - 0x10) as u16;
+                let start = self.memory_view_start(chip8);
+                self.memory_start = Some(start.saturating_add(MEMORY_PAGE).min(last_row));
+            }
+            KeyCode::Home => self.memory_start = None,
+            KeyCode::Char(character) => {
+                let character = character.to_ascii_lowercase();
+                if let Some(&(_, key)) = KEYPAD_LAYOUT.iter().find(|&&(c, _)| c == character) {
+                    self.held[key] = KEY_HOLD_FRAMES;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn stopped(&mut self, result: Result<Stop, EmulatorError>, chip8: &Chip8) {
+        self.status = match result {
+            Ok(stop) => debug::describe(stop, chip8)
+                .lines()
+                .next()
+                .unwrap_or("")
+                .to_string(),
+            Err(e) => format!("Error: {}", e),
+        };
+        self.cursor = chip8.cpu.program_counter();
+    }
+
+    fn memory_view_start(&self, chip8: &Chip8) -> u16 {
+        self.memory_start.unwrap_or(chip8.cpu.index() & !0xF)
+    }
+
+    /// The screen as lines of at most `width` characters. The display is drawn smaller on
+    /// small terminals so the registers, code and memory stay visible.
+    fn render(&self, chip8: &Chip8, width: usize, height: usize) -> Vec<String> {
+        // the status and help lines always fit below the panels
+        let body_height = height.saturating_sub(2);
+        let display_size = |scale: Scale| {
+            let (across, down) = scale.cell();
+            (
+                chip8.display.width() / across,
+                chip8.display.height() / down,
+            )
+        };
+        let scale = [Scale::HalfBlocks, Scale::Braille]
+            .iter()
+            .copied()
+            .find(|&scale| {
+                let (columns, rows) = display_size(scale);
+                columns + 2 + REGISTERS_WIDTH <= width
+                    && rows.max(REGISTER_ROWS) + CODE_ROWS + 4 <= body_height
+            })
+            .unwrap_or(Scale::Halved);
+
+        let state = if self.debugger.paused() {
+            "paused"
+        } else {
+            "running"
+        };
+        let pixels = display_rows(&chip8.display, scale);
+        let top_height = pixels.len().max(REGISTER_ROWS) + 2;
+        let display = panel(
+            &format!("Display, {}", state),
+            &pixels,
+            display_size(scale).0 + 2,
+            top_height,
+        );
+        let registers = panel(
+            "Registers",
+            &register_lines(chip8),
+            REGISTERS_WIDTH,
+            top_height,
+        );
+
+        let bottom_height = body_height.saturating_sub(top_height).max(3);
+        let rows = bottom_height - 2;
+        // an address and 16 bytes per row, or 8 when that does not fit
+        let bytes_per_row = if width >= CODE_WIDTH + memory_width(16) {
+            16
+        } else {
+            8
+        };
+        let code = panel(
+            "Code",
+            &self.code_lines(chip8, rows),
+            CODE_WIDTH,
+            bottom_height,
+        );
+        let memory = panel(
+            "Memory",
+            &self.memory_lines(chip8, rows, bytes_per_row),
+            memory_width(bytes_per_row),
+            bottom_height,
+        );
+
+        let mut lines = beside(display, registers);
+        lines.extend(beside(code, memory));
+        lines.truncate(body_height);
+        lines.push(self.status.clone());
+        lines.push(HELP.to_string());
+        lines
+            .iter()
+            .map(|line| line.chars().take(width).collect())
+            .collect()
+    }
+
+    fn code_lines(&self, chip8: &Chip8, rows: usize) -> Vec<String> {
+        let program_counter = chip8.cpu.program_counter();
+        // follow the program while it runs
+        let focus = if self.debugger.paused() {
+            self.cursor
+        } else {
+            program_counter
+        };
+        let mut address = focus.saturating_sub(CODE_CONTEXT * 2) as usize;
+        let mut lines = Vec::new();
+
+        while lines.len() < rows {
+            let (opcode, text, size) = match debug::instruction_at(chip8, address) {
+                Some(instruction) => instruction,
+                None => break,
+            };
+            let breakpoint = if self.debugger.breakpoints().any(|b| b as usize == address) {
+                '*'
+            } else {
+                ' '
+            };
+            let pointer = if address == program_counter as usize {
+                "=>"
+            } else if address == self.cursor as usize {
+                "> "
+            } else {
+                "  "
+            };
+            lines.push(format!(
+                "{}{} {:03x}: {:04x}  {}",
+                breakpoint, pointer, address, opcode, text
+            ));
+            address += size;
+        }
+        lines
+    }
+
+    fn memory_lines(&self, chip8: &Chip8, rows: usize, bytes_per_row: usize) -> Vec<String> {
+        let memory = chip8.mem.memory();
+        let start = self.memory_view_start(chip8) as usize;
+        memory[start.min(memory.len())..]
+            .chunks(bytes_per_row)
+            .take(rows)
+            .enumerate()
+            .map(|(row, bytes)| {
+                let cells: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                format!("{:04x} {}", start + row * bytes_per_row, cells.join(" "))
+            })
+            .collect()
+    }
+}
+
+// the memory panel, borders included
+fn memory_width(bytes_per_row: usize) -> usize {
+    bytes_per_row * 3 + 6
+}
+
+fn display_rows(display: &Display, scale: Scale) -> Vec<String> {
+    let (across, down) = scale.cell();
+    // a halved display has one dot for every 2x2 pixels
+    let dot_size = if scale == Scale::Halved { 2 } else { 1 };
+    let lit = |x: usize, y: usize| {
+        (0..dot_size).any(|dy| {
+            (0..dot_size).any(|dx| {
+                let (x, y) = (x * dot_size + dx, y * dot_size + dy);
+                x < display.width() && y < display.height() && display.pixel(x, y) != 0
+            })
+        })
+    };
+
+    (0..display.height())
+        .step_by(down)
+        .map(|top| {
+            (0..display.width())
+                .step_by(across)
+                .map(|left| {
+                    let (x, y) = (left / dot_size, top / dot_size);
+                    match scale {
+                        Scale::HalfBlocks => match (lit(x, y), lit(x, y + 1)) {
+                            (true, true) => '█',
+                            (true, false) => '▀',
+                            (false, true) => '▄',
+                            (false, false) => ' ',
+                        },
+                        Scale::Braille | Scale::Halved => braille(|dx, dy| lit(x + dx, y + dy)),
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// The braille character for a 2x4 cell of dots, a space when none are lit
+fn braille(lit: impl Fn(usize, usize) -> bool) -> char {
+    // the bit of each dot, by column and row
+    const DOTS: [(usize, usize, u32); 8] = [
+        (0, 0, 0x01),
+        (0, 1, 0x02),
+        (0, 2, 0x04),
+        (1, 0, 0x08),
+        (1, 1, 0x10),
+        (1, 2, 0x20),
+        (0, 3, 0x40),
+        (1, 3, 0x80),
+    ];
+    let bits = DOTS
+        .iter()
+        .filter(|&&(x, y, _)| lit(x, y))
+        .fold(0, |bits, &(_, _, bit)| bits | bit);
+    match bits {
+        0 => ' ',
+        bits => std::char::from_u32(0x2800 + bits).unwrap_or(' '),
+    }
+}
+
+fn register_lines(chip8: &Chip8) -> Vec<String> {
+    let cpu = &chip8.cpu;
+    let registers = cpu.registers();
+    let mut lines = vec![(0..8).map(|column| format!(" +{:X}", column)).collect()];
+    for row in (0..16).step_by(8) {
+        let values: String = registers[row..row + 8]
+            .iter()
+            .map(|value| format!(" {:02x}", value))
+            .collect();
+        lines.push(format!("V{:X}{}", row, values));
+    }
+    lines.push(format!(
+        "I  {:04x}    PC {:04x}",
+        cpu.index(),
+        cpu.program_counter()
+    ));
+    lines.push(format!(
+        "DT {:02x}      ST {:02x}",
+        cpu.delay_timer(),
+        cpu.sound_timer()
+    ));
+    // the innermost call first, five to a line
+    let stack: Vec<String> = chip8
+        .mem
+        .stack()
+        .iter()
+        .rev()
+        .map(|address| format!("{:03x}", address))
+        .collect();
+    lines.push(format!("Stack {}", stack.len()));
+    for calls in stack.chunks(5) {
+        lines.push(format!("  {}", calls.join(" ")));
+    }
+    lines
+}
+
+/// Truncates or pads `text` to `width` characters
+fn fit(text: &str, width: usize, fill: char) -> String {
+    let mut fitted: String = text.chars().take(width).collect();
+    let length = fitted.chars().count();
+    fitted.extend(std::iter::repeat(fill).take(width - length));
+    fitted
+}
+
+/// A box `width` by `height` characters, borders included, with a title
+fn panel(title: &str, lines: &[String], width: usize, height: usize) -> Vec<String> {
+    let inner = width.saturating_sub(2);
+    let mut panel = vec![format!("┌{}┐", fit(&format!("─ {} ", title), inner, '─'))];
+    for row in 0..height.saturating_sub(2) {
+        let line = lines.get(row).map_or("", String::as_str);
+        panel.push(format!("│{}│", fit(line, inner, ' ')));
+    }
+    panel.push(format!("└{}┘", "─".repeat(inner)));
+    panel
+}
+
+fn beside(left: Vec<String>, right: Vec<String>) -> Vec<String> {
+    let width = left.first().map_or(0, |line| line.chars().count());
+    (0..left.len().max(right.len()))
+        .map(|row| {
+            let left = left.get(row).map_or(" ".repeat(width), String::clone);
+            format!("{}{}", left, right.get(row).map_or("", String::as_str))
+        })
+        .collect()
+}
+
+// Restores the terminal when dropped, also when leaving with an error
+struct Terminal;
+
+impl Terminal {
+    fn enter() -> io::Result<Terminal> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Terminal)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        execute!(io::stdout(), Show, LeaveAlternateScreen).ok();
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+fn draw(out: &mut impl Write, lines: &[String]) -> io::Result<()> {
+    for (row, line) in lines.iter().enumerate() {
+        queue!(
+            out,
+            MoveTo(0, row as u16),
+            Print(line),
+            Clear(ClearType::UntilNewLine)
+        )?;
+    }
+    queue!(out, Clear(ClearType::FromCursorDown))?;
+    out.flush()
+}
+
+/// Runs the debugger in the terminal until Esc is pressed
+pub fn run(chip8: &mut Chip8) -> Result<(), Box<dyn Error>> {
+    let mut tui = Tui::new(chip8);
+    let _terminal = Terminal::enter()?;
+    let mut out = io::stdout();
+    let frame_time = Duration::from_secs(1) / FRAME_RATE;
+    let mut next_frame = Instant::now();
+    let mut redraw = true;
+
+    while !tui.quit {
+        let now = Instant::now();
+        if now >= next_frame {
+            redraw |= tui.frame(chip8);
+            // after falling behind, run the next frame on time rather than catching up
+            next_frame = (next_frame + frame_time).max(now);
+        }
+        if redraw {
+            let (width, height) = terminal::size()?;
+            draw(
+                &mut out,
+                &tui.render(chip8, width as usize, height as usize),
+            )?;
+            redraw = false;
+        }
+        if event::poll(next_frame.saturating_duration_since(Instant::now()))? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Release {
+                    tui.key(key, chip8);
+                }
+            }
+            redraw = true;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn press(tui: &mut Tui, chip8: &mut Chip8, code: KeyCode) {
+    tui.key(KeyEvent::new(code, KeyModifiers::NONE), chip8);
+}
+
+#[test]
+fn test_display_rows() {
+    let mut display = Display::new();
+    display.set(0, 0, true);
+    display.set(1, 1, true);
+    display.set(2, 0, true);
+    display.set(2, 1, true);
+
+    let rows = display_rows(&display, Scale::HalfBlocks);
+    assert_eq!(16, rows.len());
+    assert!(rows[0].starts_with("▀▄█ "));
+    assert_eq!(64, rows[0].chars().count());
+
+    let rows = display_rows(&display, Scale::Braille);
+    assert_eq!(8, rows.len());
+    assert!(rows[0].starts_with("⠑⠃ "));
+    assert_eq!(32, rows[0].chars().count());
+
+    let rows = display_rows(&display, Scale::Halved);
+    assert_eq!(4, rows.len());
+    assert!(rows[0].starts_with("⠉ "));
+}
+
+#[cfg(test)]
+fn stepped_into_call() -> (Tui, Chip8) {
+    let mut chip8 = Chip8::default();
+    // V3 := 0x10, I := 0x300, call 0x208, return
+    chip8
+        .load_program(&[0x63, 0x10, 0xA3, 0x00, 0x22, 0x08, 0x00, 0x00, 0x00, 0xEE])
+        .unwrap();
+    chip8.mem.memory_mut()[0x300] = 0xAB;
+    let mut tui = Tui::new(&chip8);
+    for _ in 0..3 {
+        press(&mut tui, &mut chip8, KeyCode::F(7));
+    }
+    (tui, chip8)
+}
+
+#[test]
+fn test_render() {
+    let (tui, chip8) = stepped_into_call();
+
+    let lines = tui.render(&chip8, 120, 40);
+    let screen = lines.join("\n");
+
+    assert_eq!(40, lines.len());
+    assert!(lines[0].starts_with("┌─ Display, paused ─"));
+    assert!(screen.contains("││V0 00 00 00 10 00 00 00 00"));
+    assert!(screen.contains("│I  0300    PC 0208"));
+    assert!(screen.contains("│Stack 1"));
+    assert!(screen.contains("│  204"));
+    assert!(screen.contains("│    202: a300  i := 0x300"));
+    assert!(screen.contains("│ => 208: 00ee  return"));
+    assert!(screen.contains("│0300 ab 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00"));
+    assert_eq!("=> 208: 00ee  return", lines[38]);
+    assert_eq!(HELP, lines[39]);
+    assert!(tui
+        .render(&chip8, 30, 10)
+        .iter()
+        .all(|l| l.chars().count() <= 30));
+}
+
+#[test]
+fn test_render_on_a_small_terminal() {
+    let (tui, mut chip8) = stepped_into_call();
+
+    for &hires in [false, true].iter() {
+        chip8.display.set_hires(hires);
+        let lines = tui.render(&chip8, 80, 24);
+        let screen = lines.join("\n");
+
+        assert_eq!(24, lines.len());
+        assert!(lines.iter().all(|line| line.chars().count() <= 80));
+        assert!(screen.contains("│I  0300    PC 0208"));
+        assert!(screen.contains("│  204"));
+        assert!(screen.contains("│    202: a300  i := 0x300"));
+        assert!(screen.contains("│ => 208: 00ee  return"));
+        assert!(screen.contains("│0300 ab 00 00 00 00 00 00 00│"));
+        assert_eq!(HELP, lines[23]);
+    }
+}
+
+#[test]
+fn test_breakpoint_and_run_keys() {
+    let mut chip8 = Chip8::default();
+    // V0 += 1, loop
+    chip8.load_program(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+    let mut tui = Tui::new(&chip8);
+
+    press(&mut tui, &mut chip8, KeyCode::Down);
+    press(&mut tui, &mut chip8, KeyCode::F(9));
+    assert_eq!("Breakpoint at 202", tui.status);
+    assert!(!tui.frame(&mut chip8));
+
+    press(&mut tui, &mut chip8, KeyCode::F(5));
+    assert!(tui.frame(&mut chip8));
+    assert_eq!("Breakpoint at 202", tui.status);
+    assert!(tui.debugger.paused());
+    assert_eq!(1, chip8.cpu.registers()[0]);
+
+    press(&mut tui, &mut chip8, KeyCode::F(9));
+    assert_eq!("Removed the breakpoint at 202", tui.status);
+    press(&mut tui, &mut chip8, KeyCode::Esc);
+    assert!(tui.quit);
+}
+
+#[test]
+fn test_keypad_keys_are_held() {
+    let mut chip8 = Chip8::default();
+    let mut tui = Tui::new(&chip8);
+
+    press(&mut tui, &mut chip8, KeyCode::Char('W'));
+    assert!(tui.keypad()[0x5]);
+    for _ in 0..KEY_HOLD_FRAMES {
+        tui.frame(&mut chip8);
+    }
+    assert!(!tui.keypad()[0x5]);
+}
@@ -0,0 +1,199 @@
+use crate::audio;
+use crate::cli;
+use crate::debug;
+use crate::keymap;
+use crate::movie;
+use knocket::chip8::FRAME_RATE;
+use knocket::display::{Palette, HEIGHT, WIDTH};
+use knocket::rewind::Rewind;
+use knocket::Chip8;
+use minifb::{Key, KeyRepeat, Scale, ScaleMode, Window, WindowOptions};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use keymap::{REWIND_KEY, SLOT_KEYS};
+// Holding the rewind key steps back through the last REWIND_SECONDS, one frame at a time
+const REWIND_SECONDS: u32 = 10;
+
+// Several keys can be bound to the same CHIP-8 key, which is down while any of them is
+fn update_keymap(
+    mapping: &keymap::KeyMap,
+    is_key_down: impl Fn(Key) -> bool,
+    key_map: &mut [bool; 16],
+) {
+    *key_map = [false; 16];
+    for (key, keymap_index) in mapping.iter() {
+        key_map[*keymap_index] |= is_key_down(*key);
+    }
+}
+
+fn window_scale(scale: u8) -> Scale {
+    match scale {
+        1 => Scale::X1,
+        2 => Scale::X2,
+        4 => Scale::X4,
+        16 => Scale::X16,
+        32 => Scale::X32,
+        _ => Scale::X8,
+    }
+}
+
+/// Save states are kept next to the ROM, e.g. `pong.ch8` saves slot 1 to `pong.1.state`
+fn state_path(rom: &Path, slot: usize) -> PathBuf {
+    rom.with_extension(format!("{}.state", slot))
+}
+
+fn save_state(chip8: &Chip8, path: &Path) -> Result<(), Box<dyn Error>> {
+    fs::write(path, chip8.save_state())?;
+    Ok(())
+}
+
+fn load_state(chip8: &mut Chip8, path: &Path) -> Result<(), Box<dyn Error>> {
+    let data = fs::read(path)?;
+    chip8.load_state(&data)?;
+    Ok(())
+}
+
+/// Returns true when a state was loaded
+fn handle_state_keys(window: &Window, chip8: &mut Chip8, args: &cli::Args) -> bool {
+    let mut loaded = false;
+    let saving = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+    for (index, key) in SLOT_KEYS.iter().enumerate() {
+        if !window.is_key_pressed(*key, KeyRepeat::No) {
+            continue;
+        }
+        let slot = index + 1;
+        let path = state_path(args.rom(), slot);
+        if saving {
+            match save_state(chip8, &path) {
+                Ok(()) => log::info!("Saved state to slot {}", slot),
+                Err(e) => log::error!("Could not save state to {}: {}", path.display(), e),
+            }
+        } else {
+            match load_state(chip8, &path) {
+                Ok(()) => {
+                    log::info!("Loaded state from slot {}", slot);
+                    loaded = true;
+                }
+                Err(e) => log::error!("Could not load state from {}: {}", path.display(), e),
+            }
+        }
+    }
+    loaded
+}
+
+fn load_keymap(args: &cli::Args) -> Result<keymap::KeyMap, Box<dyn Error>> {
+    match &args.keymap {
+        Some(path) => {
+            let source = fs::read_to_string(path)
+                .map_err(|e| format!("Could not read key map {}: {}", path.display(), e))?;
+            let key_map = keymap::parse_keymap(&source)
+                .map_err(|e| format!("Invalid key map {}: {}", path.display(), e))?;
+            Ok(key_map)
+        }
+        None => Ok(keymap::default_keymap()),
+    }
+}
+
+pub fn run(chip8: &mut Chip8, args: &cli::Args) -> Result<(), Box<dyn Error>> {
+    let key_map = load_keymap(args)?;
+    let mut keypad = [false; 16];
+    let mut frame: u64 = 0;
+    let palette = Palette::default();
+    let mut audio = audio::AudioOutput::open(chip8, args, true)?;
+    let mut rewind = Rewind::new((FRAME_RATE * REWIND_SECONDS) as usize);
+    let mut session = movie::Session::open(chip8, args)?;
+    let mut debugging = debug::Debugging::open(args)?;
+
+    // The window is sized for the 64x32 display. Each frame is passed at the display's current
+    // resolution and minifb stretches the buffer over the whole window, so 128x64 hires frames
+    // fill the same window with pixels half as wide and high.
+    let window_options = WindowOptions {
+        scale: window_scale(args.scale),
+        scale_mode: ScaleMode::Stretch,
+        ..Default::default()
+    };
+
+    let title = format!("knocket - {} - ESC to exit", args.rom().display());
+    let mut window = Window::new(&title, WIDTH, HEIGHT, window_options)
+        .map_err(|e| format!("Could not open window: {}", e))?;
+
+    // Limit to max ~60 fps update rate
+    window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
+
+    let mut result = Ok(());
+    // While debugging the window stays open after the program exits, to inspect it
+    while window.is_open()
+        && !window.is_key_down(Key::Escape)
+        && (debugging.is_some() || !chip8.halted())
+        && args.frames != Some(frame)
+    {
+        update_keymap(&key_map, |key| window.is_key_down(key), &mut keypad);
+        let mut jumped = false;
+        // jumping around in time would desynchronise a movie
+        if !session.active() {
+            jumped = handle_state_keys(&window, chip8, args);
+        }
+        if let Some(debugging) = &mut debugging {
+            if !debugging.poll(chip8, &keypad)? {
+                break;
+            }
+        }
+
+        if window.is_key_down(REWIND_KEY) && !session.active() {
+            rewind.rewind(chip8)?;
+            jumped = true;
+        } else {
+            let keypad = match session.keypad(&keypad) {
+                Some(keypad) => keypad,
+                None => break,
+            };
+            let completed = match &mut debugging {
+                Some(debugging) => debugging.run_frame(chip8, &keypad)?,
+                None => {
+                    result = chip8.run_frame(&keypad);
+                    if result.is_err() {
+                        break;
+                    }
+                    true
+                }
+            };
+            if completed {
+                rewind.push(chip8);
+                audio.frame(chip8)?;
+                frame += 1;
+            }
+        }
+        if jumped {
+            if let Some(debugging) = &mut debugging {
+                debugging.restart_frame();
+            }
+        }
+
+        let buffer = chip8.display.to_argb(&palette);
+        window.update_with_buffer(&buffer, chip8.display.width(), chip8.display.height())?;
+    }
+
+    // a movie ending in an emulator error is the one worth keeping
+    session.finish(args)?;
+    audio.finish()?;
+    Ok(result?)
+}
+
+#[test]
+fn test_update_keymap_many_keys_to_one() {
+    let mapping = keymap::parse_keymap("W = 5\nUp = 5\nS = 8").unwrap();
+    let mut keypad = [true; 16];
+
+    update_keymap(&mapping, |key| key == Key::W, &mut keypad);
+    let mut expected = [false; 16];
+    expected[5] = true;
+    assert_eq!(expected, keypad);
+
+    update_keymap(&mapping, |key| key == Key::Up, &mut keypad);
+    assert_eq!(expected, keypad);
+
+    update_keymap(&mapping, |_| false, &mut keypad);
+    assert_eq!([false; 16], keypad);
+}
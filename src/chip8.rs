@@ -1,38 +1,532 @@
+use crate::audio;
 use crate::cpu;
+use crate::disassembler::{self, Syntax};
+use crate::display;
+use crate::error::{EmulatorError, StateError};
 use crate::mem;
+use crate::quirks::Quirks;
+use crate::state::{self, StateReader, StateWriter};
+
+/// Rate at which frames are run and the timers count down
+pub const FRAME_RATE: u32 = 60;
+const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 10;
 
 pub struct Chip8 {
     pub cpu: cpu::Cpu,
     pub mem: mem::Mem,
+    pub display: display::Display,
+    program: Vec<u8>,
+    rom_hash: u64,
+    seed: u64,
+    quirks: Quirks,
+    instructions_per_frame: u32,
+    audio: audio::AudioGenerator,
 }
 
 impl Chip8 {
-    pub fn new() -> Chip8 {
+    pub fn new(quirks: Quirks) -> Chip8 {
         Chip8 {
-            mem: mem::Mem::new(),
-            cpu: cpu::Cpu::new(),
+            mem: memory_for(quirks),
+            cpu: cpu::Cpu::with_quirks(quirks),
+            display: display::Display::new(),
+            program: Vec::new(),
+            rom_hash: state::rom_hash(&[]),
+            seed: 0,
+            quirks,
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            audio: audio::AudioGenerator::default(),
         }
     }
 }
 
+// XO-CHIP programs can address 64 KiB of memory
+fn memory_for(quirks: Quirks) -> mem::Mem {
+    if quirks.xo_chip {
+        mem::Mem::with_size(mem::XO_CHIP_MEMORY_SIZE)
+    } else {
+        mem::Mem::new()
+    }
+}
+
 impl Default for Chip8 {
     fn default() -> Self {
-        Self::new()
+        Self::new(Quirks::default())
     }
 }
 
 impl Chip8 {
     pub fn load_program(&mut self, program: &[u8]) -> Result<(), &'static str> {
-        let result = self.mem.load_program(program);
+        self.mem.load_program(program)?;
+        self.program = program.to_vec();
+        self.rom_hash = state::rom_hash(program);
+
+        if log::log_enabled!(log::Level::Trace) {
+            let listing = disassembler::disassemble(program).to_text(Syntax::Cowgod);
+            for line in listing.lines() {
+                log::trace!("{}", line);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Restores the machine to its power-on state with the last loaded program in memory.
+    /// Memory watchpoints are kept.
+    pub fn reset(&mut self) {
+        self.cpu = cpu::Cpu::with_quirks(self.quirks);
+        self.cpu.seed_rng(self.seed);
+        let mut mem = memory_for(self.quirks);
+        mem.keep_watchpoints(&mut self.mem);
+        self.mem = mem;
+        self.display = display::Display::new();
+        let mut audio = audio::AudioGenerator::new(self.audio.sample_rate());
+        audio.set_tone_frequency(self.audio.tone_frequency());
+        self.audio = audio;
+        self.mem
+            .load_program(&self.program)
+            .expect("program was validated when it was loaded");
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Seeds the random number generator used by Cxkk, 0 by default. Runs with the same
+    /// seed, program and input are identical.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.cpu.seed_rng(seed);
+    }
+
+    /// Hash of the loaded program, save states only load into a machine running the same ROM
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    /// Serializes the CPU, memory and display into a versioned blob tagged with the ROM hash.
+    /// Settings such as the quirks and the instructions per frame are not part of the state.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.bytes(state::MAGIC);
+        writer.u16(state::VERSION);
+        writer.u64(self.rom_hash);
+        self.cpu.save_state(&mut writer);
+        self.mem.save_state(&mut writer);
+        self.display.save_state(&mut writer);
+        writer.into_bytes()
+    }
+
+    /// Restores a state made by `save_state`. The machine is left untouched if the state is
+    /// invalid or was made with another ROM. Memory watchpoints are kept.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(data);
+        if reader.bytes(state::MAGIC.len()).ok() != Some(&state::MAGIC[..]) {
+            return Err(StateError::InvalidMagic);
+        }
+        let version = reader.u16()?;
+        if version != state::VERSION {
+            return Err(StateError::UnsupportedVersion { version });
+        }
+        let hash = reader.u64()?;
+        if hash != self.rom_hash {
+            return Err(StateError::RomMismatch {
+                expected: self.rom_hash,
+                found: hash,
+            });
+        }
+
+        let mut cpu = cpu::Cpu::with_quirks(self.quirks);
+        let mut mem = memory_for(self.quirks);
+        let mut display = display::Display::new();
+        cpu.load_state(&mut reader)?;
+        mem.load_state(&mut reader)?;
+        display.load_state(&mut reader)?;
+
+        mem.keep_watchpoints(&mut self.mem);
+        self.cpu = cpu;
+        self.mem = mem;
+        self.display = display;
+        Ok(())
+    }
+
+    pub fn run_cycle(&mut self, keypad: &[bool; 16]) -> Result<(), EmulatorError> {
+        self.cpu
+            .execute_cycle(&mut self.mem, &mut self.display, keypad)
+    }
+
+    /// Counts the delay and sound timers down, call this at 60 Hz regardless of how many
+    /// cycles are run in between.
+    pub fn tick_timers(&mut self) {
+        self.cpu.tick_timers();
+    }
 
-        for index in (0x200..0x200 + program.len()).step_by(2) {
-            log::trace!("{}", format!("opcode {:04x}", self.mem.fetch_opcode(index)));
+    pub fn sound_active(&self) -> bool {
+        self.cpu.sound_active()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.audio.sample_rate()
+    }
+
+    /// Sets the sample rate `render_audio` produces samples at, 44100 Hz by default
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.audio.set_sample_rate(sample_rate);
+    }
+
+    /// Fills `samples` with mono audio for the current sound state, call this with
+    /// `sample_rate() / FRAME_RATE` samples after every frame for continuous playback.
+    /// XO-CHIP plays the audio pattern, everything else a square wave buzzer.
+    pub fn render_audio(&mut self, samples: &mut [f32]) {
+        let active = self.cpu.sound_active();
+        if self.quirks.xo_chip {
+            self.audio
+                .render(self.cpu.audio_pattern(), self.cpu.pitch(), active, samples);
+        } else {
+            self.audio.render_tone(active, samples);
         }
+    }
+
+    /// Sets the frequency of the square wave buzzer used outside XO-CHIP mode
+    pub fn set_tone_frequency(&mut self, frequency: f64) {
+        self.audio.set_tone_frequency(frequency);
+    }
+
+    pub fn instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
+    }
+
+    /// Sets how many instructions `run_frame` executes, at least one is always run.
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: u32) {
+        self.instructions_per_frame = instructions_per_frame.max(1);
+    }
+
+    /// Sets the number of instructions per frame from a clock speed in Hz, e.g. 600 Hz
+    /// runs 10 instructions per frame.
+    pub fn set_clock_speed(&mut self, hz: u32) {
+        self.set_instructions_per_frame(hz / FRAME_RATE);
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// True once the program has exited with 00FD
+    pub fn halted(&self) -> bool {
+        self.cpu.halted()
+    }
+
+    /// True when the next instruction jumps to itself, the usual way for a program to stop
+    pub fn jumping_to_self(&self) -> bool {
+        let program_counter = self.cpu.program_counter();
+        self.mem.fetch_opcode(program_counter as usize) == Ok(0x1000 | program_counter)
+    }
+
+    /// Runs one 60 Hz frame: the configured number of instructions followed by a single
+    /// timer tick. Frontends should call this once per displayed frame. With the display
+    /// wait quirk the frame ends early once a sprite has been drawn.
+    pub fn run_frame(&mut self, keypad: &[bool; 16]) -> Result<(), EmulatorError> {
+        for _ in 0..self.instructions_per_frame {
+            self.run_cycle(keypad)?;
+            if self.cpu.waiting_for_vblank() || self.cpu.halted() {
+                break;
+            }
+        }
+        self.tick_timers();
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_run_cycle_invalid_opcode() {
+    let mut chip8 = Chip8::new(Quirks::default());
+    let keypad = [false; 16];
+
+    chip8.load_program(&[0xFF, 0xFF]).unwrap();
+
+    assert_eq!(
+        Err(EmulatorError::InvalidOpcode {
+            opcode: 0xFFFF,
+            address: 0x200
+        }),
+        chip8.run_cycle(&keypad)
+    );
+}
+
+#[test]
+fn test_reset() {
+    let mut chip8 = Chip8::new(Quirks::default());
+    let keypad = [false; 16];
 
-        result
+    chip8
+        .load_program(&[0x60, 0x42, 0xA3, 0x00, 0xF0, 0x55])
+        .unwrap();
+    for _ in 0..3 {
+        chip8.run_cycle(&keypad).unwrap();
     }
+    assert_eq!(Ok(0x42), chip8.mem.fetch(0x300));
+
+    chip8.reset();
+
+    assert_eq!(Ok(0x00), chip8.mem.fetch(0x300));
+    assert_eq!(Ok(0x6042), chip8.mem.fetch_opcode(0x200));
+}
+
+#[test]
+fn test_delay_timer_wait_loop() {
+    let mut chip8 = Chip8::new(Quirks::default());
+    let keypad = [false; 16];
 
-    pub fn run_cycle(&mut self, keypad: &[bool; 16]) {
-        self.cpu.execute_cycle(&mut self.mem, keypad);
+    // v0 = 3, delay = v0, loop: v1 = delay, skip if v1 == 0, jump loop, halt: jump halt
+    chip8
+        .load_program(&[
+            0x60, 0x03, 0xF0, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x04, 0x12, 0x0A,
+        ])
+        .unwrap();
+
+    for _ in 0..3 {
+        for _ in 0..10 {
+            chip8.run_cycle(&keypad).unwrap();
+        }
+        assert_ne!(0x20A, chip8.cpu.program_counter());
+        chip8.tick_timers();
     }
+
+    for _ in 0..10 {
+        chip8.run_cycle(&keypad).unwrap();
+    }
+    assert_eq!(0x20A, chip8.cpu.program_counter());
+}
+
+#[test]
+fn test_set_clock_speed() {
+    let mut chip8 = Chip8::new(Quirks::default());
+
+    chip8.set_clock_speed(1200);
+    assert_eq!(20, chip8.instructions_per_frame());
+
+    chip8.set_clock_speed(0);
+    assert_eq!(1, chip8.instructions_per_frame());
+}
+
+#[test]
+fn test_run_frame() {
+    let mut chip8 = Chip8::new(Quirks::default());
+    let keypad = [false; 16];
+
+    // v0 = 2, delay = v0, then count v1 up forever
+    chip8
+        .load_program(&[0x60, 0x02, 0xF0, 0x15, 0x71, 0x01, 0x12, 0x04])
+        .unwrap();
+    chip8.set_instructions_per_frame(4);
+
+    chip8.run_frame(&keypad).unwrap();
+    assert_eq!(0x204, chip8.cpu.program_counter());
+    assert_eq!(1, chip8.cpu.delay_timer());
+
+    chip8.run_frame(&keypad).unwrap();
+    assert_eq!(0x204, chip8.cpu.program_counter());
+    assert_eq!(0, chip8.cpu.delay_timer());
+}
+
+#[test]
+fn test_run_frame_display_wait() {
+    let keypad = [false; 16];
+
+    // draw, then count v1 up forever
+    let program = [0xD0, 0x01, 0x71, 0x01, 0x12, 0x02];
+
+    let mut chip8 = Chip8::new(Quirks::COSMAC_VIP);
+    chip8.load_program(&program).unwrap();
+    chip8.run_frame(&keypad).unwrap();
+    assert_eq!(0x202, chip8.cpu.program_counter());
+
+    let mut chip8 = Chip8::new(Quirks::SCHIP_1_1);
+    chip8.load_program(&program).unwrap();
+    chip8.run_frame(&keypad).unwrap();
+    assert_ne!(0x202, chip8.cpu.program_counter());
+}
+
+#[test]
+fn test_xo_chip_memory_size() {
+    let mut chip8 = Chip8::new(Quirks::XO_CHIP);
+    assert_eq!(mem::XO_CHIP_MEMORY_SIZE, chip8.mem.size());
+
+    chip8.load_program(&[0; 0x1000]).unwrap();
+    chip8.reset();
+    assert_eq!(mem::XO_CHIP_MEMORY_SIZE, chip8.mem.size());
+    assert_eq!(mem::MEMORY_SIZE, Chip8::default().mem.size());
+}
+
+#[test]
+fn test_render_audio() {
+    let mut chip8 = Chip8::new(Quirks::XO_CHIP);
+    let keypad = [false; 16];
+    // I = 0x210, load the pattern, V0 = 2, sound timer = V0, loop
+    chip8
+        .load_program(&[
+            0xA2, 0x10, 0xF0, 0x02, 0x60, 0x02, 0xF0, 0x18, 0x12, 0x08, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0xFF, 0x00,
+        ])
+        .unwrap();
+    chip8.set_sample_rate(4000);
+    let mut samples = [0.0; 16];
+
+    chip8.run_frame(&keypad).unwrap();
+    chip8.render_audio(&mut samples);
+
+    assert!(samples[..8].iter().all(|&sample| sample == audio::VOLUME));
+    assert!(samples[8..].iter().all(|&sample| sample == -audio::VOLUME));
+
+    chip8.run_frame(&keypad).unwrap();
+    chip8.render_audio(&mut samples);
+
+    assert!(samples.iter().all(|&sample| sample == 0.0));
+}
+
+#[test]
+fn test_render_audio_buzzer() {
+    let mut chip8 = Chip8::new(Quirks::default());
+    let keypad = [false; 16];
+    // V0 = 2, sound timer = V0, loop
+    chip8
+        .load_program(&[0x60, 0x02, 0xF0, 0x18, 0x12, 0x04])
+        .unwrap();
+    chip8.set_sample_rate(8);
+    chip8.set_tone_frequency(2.0);
+    let mut samples = [0.0; 4];
+
+    chip8.render_audio(&mut samples);
+    assert_eq!([0.0; 4], samples);
+
+    chip8.run_frame(&keypad).unwrap();
+    chip8.render_audio(&mut samples);
+    assert_eq!(
+        [audio::VOLUME, audio::VOLUME, -audio::VOLUME, -audio::VOLUME],
+        samples
+    );
+}
+
+#[test]
+fn test_save_and_load_state() {
+    let mut chip8 = Chip8::new(Quirks::default());
+    let keypad = [false; 16];
+    // V0 = 5, I = 0x1AF, draw, V0 += 1, loop
+    let program = [0x60, 0x05, 0xA1, 0xAF, 0xD0, 0x05, 0x70, 0x01, 0x12, 0x06];
+    chip8.load_program(&program).unwrap();
+    chip8.run_frame(&keypad).unwrap();
+    let state = chip8.save_state();
+    let pixels = chip8.display.to_argb(&display::Palette::default());
+    let program_counter = chip8.cpu.program_counter();
+
+    chip8.run_frame(&keypad).unwrap();
+    chip8.reset();
+    chip8.load_state(&state).unwrap();
+
+    assert_eq!(state, chip8.save_state());
+    assert_eq!(pixels, chip8.display.to_argb(&display::Palette::default()));
+    assert_eq!(program_counter, chip8.cpu.program_counter());
+}
+
+#[test]
+fn test_watchpoints_survive_reset_and_load_state() {
+    let mut chip8 = Chip8::new(Quirks::default());
+    let keypad = [false; 16];
+    // V0 = 1, I = 0x300, store V0, loop
+    chip8
+        .load_program(&[0x60, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06])
+        .unwrap();
+    let state = chip8.save_state();
+    chip8.mem.add_watchpoint(mem::Watchpoint {
+        range: 0x300..=0x300,
+        access: mem::Access::Write,
+    });
+
+    chip8.reset();
+    chip8.load_state(&state).unwrap();
+    assert_eq!(1, chip8.mem.watchpoints().len());
+
+    chip8.run_frame(&keypad).unwrap();
+    assert_eq!(
+        Some(0x300),
+        chip8.mem.take_watch_hit().map(|hit| hit.address)
+    );
+}
+
+#[test]
+fn test_load_state_errors() {
+    let mut chip8 = Chip8::new(Quirks::default());
+    chip8.load_program(&[0x12, 0x00]).unwrap();
+    let state = chip8.save_state();
+
+    assert_eq!(Err(StateError::InvalidMagic), chip8.load_state(b"nope"));
+    assert_eq!(
+        Err(StateError::Truncated),
+        chip8.load_state(&state[..state.len() - 1])
+    );
+
+    let mut newer = state.clone();
+    newer[4] = 0xFF;
+    assert_eq!(
+        Err(StateError::UnsupportedVersion {
+            version: state::VERSION | 0xFF
+        }),
+        chip8.load_state(&newer)
+    );
+
+    let mut other = Chip8::new(Quirks::default());
+    other.load_program(&[0x12, 0x02]).unwrap();
+    assert_eq!(
+        Err(StateError::RomMismatch {
+            expected: other.rom_hash(),
+            found: chip8.rom_hash()
+        }),
+        other.load_state(&state)
+    );
+
+    let mut xo_chip = Chip8::new(Quirks::XO_CHIP);
+    xo_chip.load_program(&[0x12, 0x00]).unwrap();
+    assert_eq!(
+        Err(StateError::MemorySizeMismatch {
+            expected: mem::XO_CHIP_MEMORY_SIZE,
+            found: mem::MEMORY_SIZE
+        }),
+        xo_chip.load_state(&state)
+    );
+}
+
+#[test]
+fn test_seed_is_deterministic() {
+    let keypad = [false; 16];
+    // V0 = rand, V1 = rand, V2 = rand, loop
+    let program = [0xC0, 0xFF, 0xC1, 0xFF, 0xC2, 0xFF, 0x12, 0x06];
+    let run = |seed| {
+        let mut chip8 = Chip8::default();
+        chip8.set_seed(seed);
+        chip8.load_program(&program).unwrap();
+        chip8.run_frame(&keypad).unwrap();
+        chip8.save_state()
+    };
+
+    assert_eq!(run(1), run(1));
+    assert_ne!(run(1), run(2));
+
+    let mut chip8 = Chip8::default();
+    chip8.set_seed(1);
+    chip8.load_program(&program).unwrap();
+    chip8.run_frame(&keypad).unwrap();
+    chip8.reset();
+    chip8.run_frame(&keypad).unwrap();
+    assert_eq!(run(1), chip8.save_state());
+}
+
+#[test]
+fn test_jumping_to_self() {
+    let mut chip8 = Chip8::default();
+    let keypad = [false; 16];
+    chip8.load_program(&[0x60, 0x01, 0x12, 0x02]).unwrap();
+
+    assert!(!chip8.jumping_to_self());
+    chip8.run_cycle(&keypad).unwrap();
+    assert!(chip8.jumping_to_self());
 }
@@ -0,0 +1,258 @@
+/*
+    Conditions for breakpoints compare registers, timers and memory, joined with && and ||
+    where && binds tighter:
+
+        V3 == 0x10 && I > 0x300
+        [0x3A0] != 0 || VF == 1
+
+    Operands are V0-VF, I, PC, DT, ST, [ADDR] for the byte in memory at ADDR, or numbers,
+    decimal or hexadecimal with 0x. Comparisons are ==, !=, <, <=, > and >=.
+*/
+use crate::chip8::Chip8;
+use crate::error::ConditionError;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(u8),
+    Index,
+    ProgramCounter,
+    DelayTimer,
+    SoundTimer,
+    Memory(u16),
+    Number(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+// two character operators first, so `<=` is not read as `<`
+const OPERATORS: [(&str, Operator); 6] = [
+    ("==", Operator::Equal),
+    ("!=", Operator::NotEqual),
+    ("<=", Operator::LessOrEqual),
+    (">=", Operator::GreaterOrEqual),
+    ("<", Operator::Less),
+    (">", Operator::Greater),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Comparison {
+    pub left: Operand,
+    pub operator: Operator,
+    pub right: Operand,
+}
+
+/// Comparisons joined with && and ||
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    /// True when all the comparisons of any group are
+    groups: Vec<Vec<Comparison>>,
+}
+
+pub fn parse_condition(source: &str) -> Result<Condition, ConditionError> {
+    let groups = source
+        .split("||")
+        .map(|group| group.split("&&").map(parse_comparison).collect())
+        .collect::<Result<_, _>>()?;
+    Ok(Condition { groups })
+}
+
+fn parse_comparison(source: &str) -> Result<Comparison, ConditionError> {
+    let (index, text, operator) = OPERATORS
+        .iter()
+        .filter_map(|&(text, operator)| Some((source.find(text)?, text, operator)))
+        .min_by_key(|&(index, text, _)| (index, usize::MAX - text.len()))
+        .ok_or_else(|| error(format!("expected a comparison in `{}`", source.trim())))?;
+
+    Ok(Comparison {
+        left: parse_operand(&source[..index])?,
+        operator,
+        right: parse_operand(&source[index + text.len()..])?,
+    })
+}
+
+fn parse_operand(source: &str) -> Result<Operand, ConditionError> {
+    let text = source.trim();
+    if text.is_empty() {
+        return Err(error("missing operand".to_string()));
+    }
+    if let Some(address) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        return parse_number(address.trim()).map(Operand::Memory);
+    }
+
+    let operand = match text.to_ascii_uppercase().as_str() {
+        "I" => Operand::Index,
+        "PC" => Operand::ProgramCounter,
+        "DT" => Operand::DelayTimer,
+        "ST" => Operand::SoundTimer,
+        name if name.len() == 2 && name.starts_with('V') => {
+            match u8::from_str_radix(&name[1..], 16) {
+                Ok(register) => Operand::Register(register),
+                Err(_) => return Err(error(format!("unknown register `{}`", text))),
+            }
+        }
+        _ => Operand::Number(parse_number(text)?),
+    };
+    Ok(operand)
+}
+
+fn parse_number(text: &str) -> Result<u16, ConditionError> {
+    let number = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(digits) => u16::from_str_radix(digits, 16),
+        None => text.parse(),
+    };
+    number.map_err(|_| error(format!("`{}` is not a number or register", text)))
+}
+
+fn error(message: String) -> ConditionError {
+    ConditionError { message }
+}
+
+impl Operand {
+    pub fn value(self, chip8: &Chip8) -> u16 {
+        match self {
+            Operand::Register(register) => chip8.cpu.registers()[register as usize] as u16,
+            Operand::Index => chip8.cpu.index(),
+            Operand::ProgramCounter => chip8.cpu.program_counter(),
+            Operand::DelayTimer => chip8.cpu.delay_timer() as u16,
+            Operand::SoundTimer => chip8.cpu.sound_timer() as u16,
+            // read directly rather than with `fetch`, which would trigger read watchpoints
+            Operand::Memory(address) => chip8
+                .mem
+                .memory()
+                .get(address as usize)
+                .map_or(0, |&byte| byte as u16),
+            Operand::Number(number) => number,
+        }
+    }
+}
+
+impl Comparison {
+    pub fn evaluate(&self, chip8: &Chip8) -> bool {
+        let left = self.left.value(chip8);
+        let right = self.right.value(chip8);
+        match self.operator {
+            Operator::Equal => left == right,
+            Operator::NotEqual => left != right,
+            Operator::Less => left < right,
+            Operator::LessOrEqual => left <= right,
+            Operator::Greater => left > right,
+            Operator::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+impl Condition {
+    pub fn evaluate(&self, chip8: &Chip8) -> bool {
+        self.groups
+            .iter()
+            .any(|group| group.iter().all(|comparison| comparison.evaluate(chip8)))
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Register(register) => write!(f, "V{:X}", register),
+            Operand::Index => write!(f, "I"),
+            Operand::ProgramCounter => write!(f, "PC"),
+            Operand::DelayTimer => write!(f, "DT"),
+            Operand::SoundTimer => write!(f, "ST"),
+            Operand::Memory(address) => write!(f, "[0x{:x}]", address),
+            Operand::Number(number) => write!(f, "0x{:x}", number),
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operator = OPERATORS
+            .iter()
+            .find(|&&(_, operator)| operator == self.operator)
+            .map_or("?", |&(text, _)| text);
+        write!(f, "{} {} {}", self.left, operator, self.right)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let groups: Vec<String> = self
+            .groups
+            .iter()
+            .map(|group| {
+                let comparisons: Vec<String> = group.iter().map(|c| c.to_string()).collect();
+                comparisons.join(" && ")
+            })
+            .collect();
+        write!(f, "{}", groups.join(" || "))
+    }
+}
+
+#[test]
+fn test_parse_condition() {
+    let condition = parse_condition("V3 == 0x10 && I > 0x300").unwrap();
+
+    assert_eq!(
+        vec![vec![
+            Comparison {
+                left: Operand::Register(3),
+                operator: Operator::Equal,
+                right: Operand::Number(0x10),
+            },
+            Comparison {
+                left: Operand::Index,
+                operator: Operator::Greater,
+                right: Operand::Number(0x300),
+            },
+        ]],
+        condition.groups
+    );
+    assert_eq!("V3 == 0x10 && I > 0x300", condition.to_string());
+    assert_eq!(
+        "[0x3a0] <= 0x5 || VF != DT",
+        parse_condition("[0x3A0]<=5||vf!=dt").unwrap().to_string()
+    );
+}
+
+#[test]
+fn test_parse_condition_errors() {
+    assert_eq!(
+        Err(error("expected a comparison in `V3`".to_string())),
+        parse_condition("V3")
+    );
+    assert_eq!(
+        Err(error("unknown register `VG`".to_string())),
+        parse_condition("VG == 1").map(|_| ())
+    );
+    assert!(parse_condition("V3 == ").is_err());
+    assert!(parse_condition("V3 == 1 &&").is_err());
+}
+
+#[test]
+fn test_evaluate_condition() {
+    let mut chip8 = Chip8::default();
+    // V3 := 0x10, I := 0x301, save v0
+    chip8
+        .load_program(&[0x63, 0x10, 0xA3, 0x01, 0xF3, 0x55])
+        .unwrap();
+    let condition = parse_condition("V3 == 0x10 && I > 0x300").unwrap();
+    let memory = parse_condition("[0x304] == 16 || PC == 0x200").unwrap();
+
+    assert!(!condition.evaluate(&chip8));
+    assert!(memory.evaluate(&chip8));
+    for _ in 0..2 {
+        chip8.run_cycle(&[false; 16]).unwrap();
+    }
+    assert!(condition.evaluate(&chip8));
+    assert!(!memory.evaluate(&chip8));
+    chip8.run_cycle(&[false; 16]).unwrap();
+    assert!(memory.evaluate(&chip8));
+}
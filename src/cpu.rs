@@ -1,318 +1,520 @@
 #![allow(arithmetic_overflow)]
 
+use crate::audio;
+use crate::display::{Display, PLANE_1, PLANE_2};
+use crate::error::{EmulatorError, StateError};
+use crate::instruction::{decode, Instruction};
 use crate::mem;
+use crate::quirks::Quirks;
+use crate::rng::Rng;
+use crate::state::{StateReader, StateWriter};
 
 const REGISTER_COUNT: usize = 16;
+const RPL_FLAG_COUNT: usize = 16;
 
 pub struct Cpu {
     registers: [u8; REGISTER_COUNT],
     index: u16,
     program_counter: u16,
     delay_timer: u8,
+    sound_timer: u8,
+    waiting_for_vblank: bool,
+    halted: bool,
+    rpl_flags: [u8; RPL_FLAG_COUNT],
+    audio_pattern: [u8; audio::PATTERN_SIZE],
+    pitch: u8,
+    rng: Rng,
+    quirks: Quirks,
 }
 
 impl Cpu {
     pub fn new() -> Cpu {
+        Cpu::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Cpu {
         Cpu {
             registers: [0; REGISTER_COUNT],
             index: 0,
             program_counter: 0x200,
             delay_timer: 0,
+            sound_timer: 0,
+            waiting_for_vblank: false,
+            halted: false,
+            rpl_flags: [0; RPL_FLAG_COUNT],
+            audio_pattern: [0; audio::PATTERN_SIZE],
+            pitch: audio::DEFAULT_PITCH,
+            rng: Rng::default(),
+            quirks,
         }
     }
 
+    /// Counts the delay and sound timers down by one. Must be called at 60 Hz, independently
+    /// of how many instructions are executed in between. This also marks the vertical blank.
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+        self.waiting_for_vblank = false;
+    }
+
+    /// True after a sprite was drawn with the display wait quirk, until the next timer tick
+    pub fn waiting_for_vblank(&self) -> bool {
+        self.waiting_for_vblank
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// True once the program has exited with 00FD, no further instructions are executed
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// The buzzer sounds for as long as the sound timer is non-zero
+    pub fn sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    /// The XO-CHIP audio pattern loaded with F002
+    pub fn audio_pattern(&self) -> &[u8; audio::PATTERN_SIZE] {
+        &self.audio_pattern
+    }
+
+    /// The XO-CHIP pitch register set with Fx3A
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// Restarts the random number generator used by Cxkk from `seed`
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// V0 to VF
+    pub fn registers(&self) -> &[u8; REGISTER_COUNT] {
+        &self.registers
+    }
+
+    /// The I register
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn set_index(&mut self, index: u16) {
+        self.index = index;
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    pub fn set_program_counter(&mut self, address: u16) {
+        self.program_counter = address;
+    }
+
     pub fn increase_program_counter(&mut self, count: u16) {
-        self.program_counter += count;
+        self.program_counter = self.program_counter.wrapping_add(count);
     }
 
-    pub fn set_register_value(&mut self, register_index: usize, value: u8) {
-        if register_index > REGISTER_COUNT {
-            panic!(
-                "Cannot set register value on index above {}",
-                REGISTER_COUNT - 1
-            );
+    // Skips the next instruction, on XO-CHIP the four byte F000 NNNN is skipped as a whole
+    fn skip_next_instruction(&mut self, mem: &mem::Mem) {
+        let next = mem.fetch_opcode(self.program_counter.wrapping_add(2) as usize);
+        if self.quirks.xo_chip && next == Ok(0xF000) {
+            self.increase_program_counter(6);
+        } else {
+            self.increase_program_counter(4);
         }
-        self.registers[register_index] = value;
     }
 
-    pub fn execute_cycle(&mut self, mem: &mut mem::Mem, keypad: &[bool; 16]) {
-        let opcode: u16 = mem.fetch_opcode(self.program_counter as usize);
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.registers);
+        writer.u16(self.index);
+        writer.u16(self.program_counter);
+        writer.u8(self.delay_timer);
+        writer.u8(self.sound_timer);
+        writer.bool(self.waiting_for_vblank);
+        writer.bool(self.halted);
+        writer.bytes(&self.rpl_flags);
+        writer.bytes(&self.audio_pattern);
+        writer.u8(self.pitch);
+        writer.u64(self.rng.state());
+    }
+
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_into(&mut self.registers)?;
+        self.index = reader.u16()?;
+        self.program_counter = reader.u16()?;
+        self.delay_timer = reader.u8()?;
+        self.sound_timer = reader.u8()?;
+        self.waiting_for_vblank = reader.bool()?;
+        self.halted = reader.bool()?;
+        reader.read_into(&mut self.rpl_flags)?;
+        reader.read_into(&mut self.audio_pattern)?;
+        self.pitch = reader.u8()?;
+        self.rng = Rng::from_state(reader.u64()?);
+        Ok(())
+    }
+
+    pub fn set_register_value(
+        &mut self,
+        register_index: usize,
+        value: u8,
+    ) -> Result<(), EmulatorError> {
+        let register =
+            self.registers
+                .get_mut(register_index)
+                .ok_or(EmulatorError::InvalidRegister {
+                    register: register_index,
+                })?;
+        *register = value;
+        Ok(())
+    }
+
+    /// Fetches, decodes and executes the instruction at the program counter
+    pub fn execute_cycle(
+        &mut self,
+        mem: &mut mem::Mem,
+        display: &mut Display,
+        keypad: &[bool; 16],
+    ) -> Result<(), EmulatorError> {
+        if self.halted {
+            return Ok(());
+        }
+
+        let opcode: u16 = mem.fetch_opcode(self.program_counter as usize)?;
         log::trace!("opcode: {:04x}", opcode);
-        match opcode & 0xF000 {
-            0x0000 => {
-                match opcode & 0x000F {
-                    0x000 => {
-                        mem.clear_graphics();
-                        self.increase_program_counter(2);
-                    } // clear screen,
-                    0x00e => {
-                        self.program_counter = mem.pop();
-                        self.increase_program_counter(2);
-                    } // exit subroutine
-                    x => log::error!("Unrecognized opcode {}", x),
-                }
+        let instruction = decode(opcode).map_err(|e| EmulatorError::InvalidOpcode {
+            opcode: e.opcode,
+            address: self.program_counter,
+        })?;
+        self.execute(instruction, mem, display, keypad)
+    }
+
+    /// Executes a decoded instruction as if it was fetched from the program counter.
+    /// SUPER-CHIP and XO-CHIP instructions fail with `InvalidOpcode` unless their quirk is
+    /// set, and instructions naming registers above VF fail with `InvalidRegister`.
+    pub fn execute(
+        &mut self,
+        instruction: Instruction,
+        mem: &mut mem::Mem,
+        display: &mut Display,
+        keypad: &[bool; 16],
+    ) -> Result<(), EmulatorError> {
+        if self.halted {
+            return Ok(());
+        }
+        if (instruction.is_schip() && !self.quirks.schip)
+            || (instruction.is_xo_chip() && !self.quirks.xo_chip)
+        {
+            return Err(EmulatorError::InvalidOpcode {
+                opcode: instruction.encode(),
+                address: self.program_counter,
+            });
+        }
+        if let Some(register) = instruction.highest_register() {
+            if register as usize >= REGISTER_COUNT {
+                return Err(EmulatorError::InvalidRegister {
+                    register: register as usize,
+                });
             }
+        }
 
-            0x1000 => {
-                // jump to
-                self.program_counter = opcode & 0x0FFF;
+        match instruction {
+            Instruction::ScrollDown(n) => display.scroll_down(n as usize),
+            Instruction::ScrollUp(n) => display.scroll_up(n as usize),
+            Instruction::Cls => display.clear(),
+            Instruction::Ret => self.program_counter = mem.pop()?,
+            Instruction::ScrollRight => display.scroll_right(4),
+            Instruction::ScrollLeft => display.scroll_left(4),
+            Instruction::Exit => {
+                self.halted = true;
+                return Ok(());
             }
-            0x2000 => {
-                // call subroutine at
-                mem.push(self.program_counter);
-                self.program_counter = opcode & 0x0FFF;
+            Instruction::Low => display.set_hires(false),
+            Instruction::High => display.set_hires(true),
+            Instruction::Jp(address) => {
+                self.program_counter = address;
+                return Ok(());
             }
-            0x3000 => {
-                // skip next if reg index by 2nd byte is eq to 3rd and 4th byte
-                let register_value = self.registers[(opcode >> 8 & 0xF) as usize];
-                let value = (opcode & 0x00FF) as u8;
-
-                if value == register_value {
-                    self.increase_program_counter(4);
-                } else {
-                    self.increase_program_counter(2);
-                }
+            Instruction::Call(address) => {
+                mem.push(self.program_counter)?;
+                self.program_counter = address;
+                return Ok(());
             }
-            0x4000 => {
-                // skip next if reg index by 2nd byte is neq to 3rd and 4th byte
-                let register_value = self.registers[(opcode >> 8 & 0xF) as usize];
-                let value = (opcode & 0x00FF) as u8;
-                if value != register_value {
-                    self.increase_program_counter(4);
-                } else {
-                    self.increase_program_counter(2);
+            Instruction::SeByte { x, byte } => {
+                return self.skip_if(self.registers[x as usize] == byte, mem);
+            }
+            Instruction::SneByte { x, byte } => {
+                return self.skip_if(self.registers[x as usize] != byte, mem);
+            }
+            Instruction::SeReg { x, y } => {
+                let equal = self.registers[x as usize] == self.registers[y as usize];
+                return self.skip_if(equal, mem);
+            }
+            Instruction::SaveRange { x, y } => {
+                let values: Vec<u8> = register_range(x, y)
+                    .map(|register_index| self.registers[register_index])
+                    .collect();
+                mem.store_bytes(self.index as usize, &values)?;
+            }
+            Instruction::LoadRange { x, y } => {
+                let mut values = vec![0; register_range(x, y).count()];
+                mem.fetch_bytes(self.index as usize, &mut values)?;
+                for (register_index, value) in register_range(x, y).zip(values) {
+                    self.registers[register_index] = value;
                 }
             }
-            0x5000 => {
-                // skip next if reg at index 2nd byte is eq 3rd byte
-                let register_value1 = self.registers[(opcode >> 8 & 0x0F) as usize];
-                let register_value2 = self.registers[(opcode >> 4 & 0x0F) as usize];
-                if register_value1 == register_value2 {
-                    self.increase_program_counter(4);
-                } else {
-                    self.increase_program_counter(2)
+            Instruction::LdByte { x, byte } => self.registers[x as usize] = byte,
+            Instruction::AddByte { x, byte } => {
+                // carry not modified
+                self.registers[x as usize] = self.registers[x as usize].wrapping_add(byte);
+            }
+            Instruction::LdReg { x, y } => self.registers[x as usize] = self.registers[y as usize],
+            Instruction::Or { x, y } => {
+                self.registers[x as usize] |= self.registers[y as usize];
+                self.reset_flag_after_logic();
+            }
+            Instruction::And { x, y } => {
+                self.registers[x as usize] &= self.registers[y as usize];
+                self.reset_flag_after_logic();
+            }
+            Instruction::Xor { x, y } => {
+                self.registers[x as usize] ^= self.registers[y as usize];
+                self.reset_flag_after_logic();
+            }
+            Instruction::AddReg { x, y } => {
+                // carry is set if overflow
+                let (result, carry) =
+                    self.registers[x as usize].overflowing_add(self.registers[y as usize]);
+                self.registers[x as usize] = result;
+                self.registers[0xF] = carry as u8;
+            }
+            Instruction::Sub { x, y } => {
+                // vf is 0 if there is a borrow and 1 otherwise
+                let (result, borrow) =
+                    self.registers[x as usize].overflowing_sub(self.registers[y as usize]);
+                self.registers[x as usize] = result;
+                self.registers[0xF] = !borrow as u8;
+            }
+            Instruction::Shr { x, y } => {
+                // set carry if lsb is 1
+                if self.quirks.shift_uses_vy {
+                    self.registers[x as usize] = self.registers[y as usize];
                 }
+                let register_value = self.registers[x as usize];
+                self.registers[x as usize] = register_value >> 1;
+                self.registers[0xF] = register_value & 0x01;
             }
-            0x6000 => {
-                // set register at byte 2 to the value of 3 and 4.
-                let register_index = (opcode >> 8 & 0x0F) as usize;
-                let value = (opcode & 0x00FF) as u8;
-                self.set_register_value(register_index, value);
-                self.increase_program_counter(2)
-            }
-            0x7000 => {
-                // vx = vx + byte 3 and, carry not modified
-                let register_index = (opcode >> 8 & 0x0F) as usize;
-                let register_value = self.registers[register_index];
-                let value = (opcode & 0x00FF) as u8;
-                self.set_register_value(
-                    register_index,
-                    ((register_value as u32 + value as u32) & 0xFF) as u8,
-                );
-                self.increase_program_counter(2);
-            }
-            0x8000 => {
-                let register_index_x = (opcode >> 8 & 0x0F) as usize;
-                let register_index_y = (opcode >> 4 & 0x0F) as usize;
-                match opcode & 0x000F {
-                    0x0000 => {
-                        // vx = vy
-                        self.set_register_value(register_index_x, self.registers[register_index_y]);
-                    }
-                    0x0001 => self.set_register_value(
-                        // vx = vx OR vy
-                        register_index_x,
-                        self.registers[register_index_x] | self.registers[register_index_y],
-                    ),
-                    0x0002 => self.set_register_value(
-                        // vx = vx AND vy
-                        register_index_x,
-                        self.registers[register_index_x] & self.registers[register_index_y],
-                    ),
-                    0x0003 => self.set_register_value(
-                        // vx = vx XOR vy
-                        register_index_x,
-                        self.registers[register_index_x] ^ self.registers[register_index_y],
-                    ),
-                    0x0004 => {
-                        // vx = vx - vy, carry is set if overflow
-                        let result: u32 = self.registers[register_index_x] as u32
-                            + self.registers[register_index_y] as u32;
-                        let carry = result > 0xFF;
-                        self.set_register_value(register_index_x, (result & 0xFF) as u8);
-                        self.set_register_value(0xF, carry as u8);
-                    }
-                    0x0005 => {
-                        // vx = vx - vy, carry is 1 if borrow
-                        let result: i32 = self.registers[register_index_x] as i32
-                            - self.registers[register_index_y] as i32;
-                        let carry = result < 0;
-                        self.set_register_value(register_index_x, (result & 0xFF) as u8);
-
-                        self.set_register_value(0xF, carry as u8);
-                    }
-                    0x0006 => {
-                        // rsf vx, set carry if lsb is 1
-                        self.set_register_value(15, self.registers[register_index_x] & 0x01);
-                        self.set_register_value(
-                            register_index_x,
-                            self.registers[register_index_x] >> 1,
-                        );
-                    }
-                    0x0007 => {
-                        // vx = vy - vx, set carry if borrow
-                        let result: i32 = self.registers[register_index_y] as i32
-                            - self.registers[register_index_x] as i32;
-                        let carry = result < 0;
-                        self.set_register_value(register_index_x, (result & 0xFF) as u8);
-                        self.set_register_value(0xF, carry as u8);
-                    }
-                    0x000E => {
-                        // lsf vx, set carry if msb is 1
-                        self.set_register_value(15, (self.registers[register_index_x] & 0x80) >> 7);
-                        self.set_register_value(
-                            register_index_x,
-                            self.registers[register_index_x] << 1,
-                        );
-                    }
-                    x => {
-                        log::error!("Unknown subcode {}", x);
-                    }
+            Instruction::Subn { x, y } => {
+                // vf is 0 if there is a borrow and 1 otherwise
+                let (result, borrow) =
+                    self.registers[y as usize].overflowing_sub(self.registers[x as usize]);
+                self.registers[x as usize] = result;
+                self.registers[0xF] = !borrow as u8;
+            }
+            Instruction::Shl { x, y } => {
+                // set carry if msb is 1
+                if self.quirks.shift_uses_vy {
+                    self.registers[x as usize] = self.registers[y as usize];
                 }
-                self.increase_program_counter(2);
-            }
-            0x9000 => {
-                // skip next instruction if vx == vy
-                let register_index_x = (opcode >> 8 & 0x0F) as usize;
-                let register_index_y = (opcode >> 4 & 0x0F) as usize;
-                if self.registers[register_index_x] == self.registers[register_index_y] {
-                    self.increase_program_counter(2);
+                let register_value = self.registers[x as usize];
+                self.registers[x as usize] = register_value << 1;
+                self.registers[0xF] = (register_value & 0x80) >> 7;
+            }
+            Instruction::SneReg { x, y } => {
+                let different = self.registers[x as usize] != self.registers[y as usize];
+                return self.skip_if(different, mem);
+            }
+            Instruction::LdI(address) => self.index = address,
+            Instruction::JpV0(address) => {
+                // jump to nnn plus v0, or vx where x is the top nibble of nnn
+                let register_index = if self.quirks.jump_uses_vx {
+                    (address >> 8 & 0x0F) as usize
                 } else {
-                    self.increase_program_counter(4);
-                }
+                    0
+                };
+                self.program_counter = address.wrapping_add(self.registers[register_index] as u16);
+                return Ok(());
             }
-            0xA000 => {
-                // set index to byte 2,3,4
-                self.index = opcode & 0x0FFF;
-                self.increase_program_counter(2);
-            }
-            0xB000 => {
-                // jump to byte 2,3,4
-                let address = opcode & 0x0FFF;
-                self.program_counter = address + self.registers[0] as u16;
-            }
-            0xC000 => {
-                // set vx to rand with AND from byte 3,4
-                let rand = rand::random::<u8>();
-                let register_index_x = (opcode >> 8 & 0x0F) as usize;
-                self.registers[register_index_x] = rand & (opcode & 0x00FF) as u8;
-                self.increase_program_counter(2);
-            }
-            0xD000 => {
-                let register_index_x = (opcode >> 8 & 0x0F) as usize;
-                let register_index_y = (opcode >> 4 & 0x0F) as usize;
-                let x = self.registers[register_index_x];
-                let y = self.registers[register_index_y];
-                let height: u8 = (opcode & 0x0F) as u8;
-                let mut pixel: u8;
-
-                self.registers[15] = 0;
-                for yline in 0..height {
-                    pixel = mem.fetch((self.index + yline as u16) as usize);
-                    for xline in 0..8 {
-                        let x_coord_index = (x as u16 + xline) as usize;
-                        let y_coord_index = (y as u16 + yline as u16) as usize;
-                        if (pixel & (0x80 >> xline)) != 0 {
-                            let current_pixel = mem.fetch_graphics(x_coord_index, y_coord_index);
-                            if current_pixel == 1 {
-                                self.registers[0xF] = 1;
-                            }
-                            mem.store_graphics(x_coord_index, y_coord_index, current_pixel ^ 0x01)
-                        }
-                    }
-                }
-                self.increase_program_counter(2);
-            }
-            0xE000 => {
-                let key_index = self.registers[(opcode >> 8 & 0x0F) as usize] as usize;
-                match opcode & 0x00FF {
-                    0x009E => {
-                        // Skip next if key is pressed
-                        if keypad[key_index] {
-                            self.increase_program_counter(4);
-                        }
-                    }
-                    0x00A1 => {
-                        // skip next if key not pressed
-                        if !keypad[key_index] {
-                            self.increase_program_counter(4);
-                        }
-                    }
-                    x => {
-                        log::error!("Unrecognized subcode {}", x);
-                    }
-                }
+            Instruction::Rnd { x, byte } => {
+                self.registers[x as usize] = self.rng.next_u8() & byte;
             }
-            0xF000 => match opcode & 0xFF {
-                0x0007 => {
-                    let register_index = (opcode >> 8 & 0x0F) as usize;
-                    self.set_register_value(register_index, self.delay_timer);
-                    self.increase_program_counter(2);
-                }
-                0x000A => {
-                    if let Some(key) = keypad.iter().position(|&x| x) {
-                        let register_index = (opcode >> 8 & 0xF) as usize;
-                        self.set_register_value(register_index, key as u8);
-                        self.increase_program_counter(2);
-                    }
-                }
-                0x0015 => {
-                    let register_index = (opcode >> 8 & 0x0F) as usize;
-                    self.delay_timer = self.registers[register_index];
-                    self.increase_program_counter(2);
-                }
-                0x0018 => {}
-                0x001E => {
-                    let register_index = (opcode >> 8 & 0x0F) as usize;
-                    self.index += self.registers[register_index] as u16;
-                    self.increase_program_counter(2);
-                }
-                0x0029 => {
-                    let register_index = (opcode >> 8 & 0x0F) as usize;
-                    self.index = mem.get_address_for_digit(self.registers[register_index]);
-                    self.increase_program_counter(2);
+            Instruction::Drw { x, y, n } => self.draw(x, y, n, mem, display)?,
+            Instruction::Skp { x } => {
+                // only the low nibble selects a key, like Octo
+                let key_index = (self.registers[x as usize] & 0xF) as usize;
+                return self.skip_if(keypad[key_index], mem);
+            }
+            Instruction::Sknp { x } => {
+                let key_index = (self.registers[x as usize] & 0xF) as usize;
+                return self.skip_if(!keypad[key_index], mem);
+            }
+            Instruction::LdILong => {
+                // load I with the 16 bit address following the instruction
+                self.index = mem.fetch_opcode(self.program_counter as usize + 2)?;
+                self.increase_program_counter(4);
+                return Ok(());
+            }
+            Instruction::Plane(n) => display.select_planes(n),
+            Instruction::Audio => {
+                let mut pattern = [0; audio::PATTERN_SIZE];
+                mem.fetch_bytes(self.index as usize, &mut pattern)?;
+                self.audio_pattern = pattern;
+            }
+            Instruction::LdVxDt { x } => self.registers[x as usize] = self.delay_timer,
+            Instruction::LdVxK { x } => match keypad.iter().position(|&x| x) {
+                Some(key) => self.registers[x as usize] = key as u8,
+                // execute the instruction again until a key is pressed
+                None => return Ok(()),
+            },
+            Instruction::LdDtVx { x } => self.delay_timer = self.registers[x as usize],
+            Instruction::LdStVx { x } => self.sound_timer = self.registers[x as usize],
+            Instruction::AddI { x } => {
+                self.index = self.index.wrapping_add(self.registers[x as usize] as u16);
+            }
+            Instruction::LdF { x } => {
+                self.index = mem.get_address_for_digit(self.registers[x as usize]);
+            }
+            Instruction::LdHf { x } => {
+                self.index = mem.get_address_for_big_digit(self.registers[x as usize]);
+            }
+            Instruction::LdB { x } => {
+                let register_value = self.registers[x as usize];
+                let digits = [
+                    register_value / 100,
+                    (register_value / 10) % 10,
+                    register_value % 10,
+                ];
+                mem.store_bytes(self.index as usize, &digits)?;
+            }
+            Instruction::Pitch { x } => self.pitch = self.registers[x as usize],
+            Instruction::LdIVx { x } => {
+                mem.store_bytes(self.index as usize, &self.registers[..=x as usize])?;
+                if self.quirks.load_store_increments_index {
+                    self.index = self.index.wrapping_add(x as u16 + 1);
                 }
-                0x0033 => {
-                    let register_index = (opcode >> 8 & 0x0F) as usize;
-                    let register_value = self.registers[register_index];
-                    mem.store(self.index as usize, (register_value / 100) % 10);
-                    mem.store(self.index as usize + 1, (register_value / 10) % 10);
-                    mem.store(self.index as usize + 2, register_value % 10);
-                    self.increase_program_counter(2);
+            }
+            Instruction::LdVxI { x } => {
+                let mut values = [0; REGISTER_COUNT];
+                mem.fetch_bytes(self.index as usize, &mut values[..=x as usize])?;
+                self.registers[..=x as usize].copy_from_slice(&values[..=x as usize]);
+                if self.quirks.load_store_increments_index {
+                    self.index = self.index.wrapping_add(x as u16 + 1);
                 }
-                0x0055 => {
-                    let register_index = opcode >> 8 & 0x0F;
-                    for (index, addr) in (self.index..self.index + register_index + 1).enumerate() {
-                        mem.store(addr as usize, self.registers[index])
-                    }
+            }
+            Instruction::LdRVx { x } => {
+                let x = x as usize;
+                self.rpl_flags[..=x].copy_from_slice(&self.registers[..=x]);
+            }
+            Instruction::LdVxR { x } => {
+                let x = x as usize;
+                self.registers[..=x].copy_from_slice(&self.rpl_flags[..=x]);
+            }
+        }
 
-                    self.index += register_index + 1;
-                    self.increase_program_counter(2);
-                }
-                0x0065 => {
-                    let register_index = opcode >> 8 & 0x0F;
-                    for index in 0..register_index + 1 {
-                        self.registers[index as usize] = mem.fetch((self.index + index) as usize)
+        self.increase_program_counter(2);
+        Ok(())
+    }
+
+    fn skip_if(&mut self, condition: bool, mem: &mem::Mem) -> Result<(), EmulatorError> {
+        if condition {
+            self.skip_next_instruction(mem);
+        } else {
+            self.increase_program_counter(2);
+        }
+        Ok(())
+    }
+
+    fn reset_flag_after_logic(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    fn draw(
+        &mut self,
+        register_index_x: u8,
+        register_index_y: u8,
+        n: u8,
+        mem: &mem::Mem,
+        display: &mut Display,
+    ) -> Result<(), EmulatorError> {
+        let width = display.width();
+        let height = display.height();
+        // the starting position always wraps, the sprite itself is clipped or wrapped at the
+        // edges depending on the quirks
+        let x = self.registers[register_index_x as usize] as usize % width;
+        let y = self.registers[register_index_y as usize] as usize % height;
+        // with SUPER-CHIP Dxy0 draws a 16x16 sprite stored as two bytes per row, on CHIP-8 it
+        // draws nothing
+        let (rows, columns) = match n {
+            0 if self.quirks.schip => (16, 16),
+            n => (n as usize, 8),
+        };
+        let bytes_per_row = columns / 8;
+        // with both XO-CHIP planes selected the sprite for the second plane follows the one
+        // for the first
+        let selected_planes = display.selected_planes();
+        let planes: Vec<u8> = [PLANE_1, PLANE_2]
+            .iter()
+            .copied()
+            .filter(|&plane| selected_planes & plane != 0)
+            .collect();
+        let sprite_size = rows * bytes_per_row;
+        // read the whole sprite first, so a failing read leaves the display untouched
+        let mut sprites = vec![0; sprite_size * planes.len()];
+        mem.fetch_bytes(self.index as usize, &mut sprites)?;
+
+        self.registers[0xF] = 0;
+        if sprite_size == 0 {
+            return Ok(());
+        }
+        for (&plane, sprite) in planes.iter().zip(sprites.chunks(sprite_size)) {
+            for yline in 0..rows {
+                let row = &sprite[yline * bytes_per_row..];
+                let pixel = if bytes_per_row == 2 {
+                    (row[0] as u16) << 8 | row[1] as u16
+                } else {
+                    (row[0] as u16) << 8
+                };
+                for xline in 0..columns {
+                    let mut x_coord_index = x + xline;
+                    let mut y_coord_index = y + yline;
+                    if self.quirks.wrap_sprites {
+                        x_coord_index %= width;
+                        y_coord_index %= height;
+                    } else if x_coord_index >= width || y_coord_index >= height {
+                        continue;
+                    }
+                    if (pixel & (0x8000 >> xline)) != 0
+                        && display.toggle_plane(x_coord_index, y_coord_index, plane)
+                    {
+                        self.registers[0xF] = 1;
                     }
-                    self.index += register_index + 1;
-                    self.increase_program_counter(2);
-                }
-                x => {
-                    log::error!("Unrecognized subcode {}", x);
                 }
-            },
-            x => log::error!("Unrecognized opcode {}", x),
+            }
         }
+        self.waiting_for_vblank = self.quirks.display_wait;
+        Ok(())
+    }
+}
+
+/// Vx to Vy inclusive, in descending order when x > y
+fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = usize>> {
+    let (x, y) = (x as usize, y as usize);
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
     }
 }
 
@@ -335,10 +537,11 @@ fn test_increase_program_counter() {
 fn test_execute_cycle_0xa0ff() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
     mem.load_program(&[0xA0, 0xFF]).unwrap();
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0x00FF, cpu.index);
 }
@@ -347,10 +550,11 @@ fn test_execute_cycle_0xa0ff() {
 fn test_execute_cycle_0x2xxx() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
     mem.load_program(&[0x20, 0x01]).unwrap();
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0x0001, cpu.program_counter);
 }
@@ -359,11 +563,12 @@ fn test_execute_cycle_0x2xxx() {
 fn test_execute_cycle_0x3xxx_equal() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
     mem.load_program(&[0x30, 0x00]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0x204, cpu.program_counter);
 }
@@ -372,11 +577,12 @@ fn test_execute_cycle_0x3xxx_equal() {
 fn test_execute_cycle_0x3xxx_not_equal() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
     mem.load_program(&[0x30, 0x01]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0x202, cpu.program_counter);
 }
@@ -385,11 +591,12 @@ fn test_execute_cycle_0x3xxx_not_equal() {
 fn test_execute_cycle_0x4xxx_equal() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
     mem.load_program(&[0x40, 0x00]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0x202, cpu.program_counter);
 }
@@ -398,11 +605,12 @@ fn test_execute_cycle_0x4xxx_equal() {
 fn test_execute_cycle_0x4xxx_not_equal() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
     mem.load_program(&[0x40, 0x01]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0x204, cpu.program_counter);
 }
@@ -411,13 +619,14 @@ fn test_execute_cycle_0x4xxx_not_equal() {
 fn test_execute_cycle_0x5xxx_equal() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
     mem.load_program(&[0x51, 0x20]).unwrap();
-    cpu.set_register_value(1, 0x01);
-    cpu.set_register_value(2, 0x01);
+    cpu.set_register_value(1, 0x01).unwrap();
+    cpu.set_register_value(2, 0x01).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0x204, cpu.program_counter);
 }
@@ -426,13 +635,14 @@ fn test_execute_cycle_0x5xxx_equal() {
 fn test_execute_cycle_0x5xxx_not_equal() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
     mem.load_program(&[0x51, 0x20]).unwrap();
-    cpu.set_register_value(1, 0x01);
-    cpu.set_register_value(2, 0x02);
+    cpu.set_register_value(1, 0x01).unwrap();
+    cpu.set_register_value(2, 0x02).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0x202, cpu.program_counter);
 }
@@ -441,11 +651,12 @@ fn test_execute_cycle_0x5xxx_not_equal() {
 fn test_execute_cycle_0x6xxx() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
     mem.load_program(&[0x61, 0x01]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0x01, cpu.registers[1]);
 }
@@ -454,13 +665,14 @@ fn test_execute_cycle_0x6xxx() {
 fn test_execute_cycle_0x7xxx() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(1, 0x10);
+    cpu.set_register_value(1, 0x10).unwrap();
 
     mem.load_program(&[0x71, 0x01]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0x11, cpu.registers[1]);
 }
@@ -469,13 +681,14 @@ fn test_execute_cycle_0x7xxx() {
 fn test_execute_cycle_0x8000() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(2, 0x10);
+    cpu.set_register_value(2, 0x10).unwrap();
 
     mem.load_program(&[0x81, 0x20]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0x10, cpu.registers[1]);
 }
@@ -484,13 +697,14 @@ fn test_execute_cycle_0x8000() {
 fn test_execute_cycle_0x8001() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(1, 0x01);
-    cpu.set_register_value(2, 0x10);
+    cpu.set_register_value(1, 0x01).unwrap();
+    cpu.set_register_value(2, 0x10).unwrap();
     mem.load_program(&[0x81, 0x21]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0x11, cpu.registers[1]);
 }
 
@@ -498,13 +712,14 @@ fn test_execute_cycle_0x8001() {
 fn test_execute_cycle_0x8002() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(1, 0x01);
-    cpu.set_register_value(2, 0x10);
+    cpu.set_register_value(1, 0x01).unwrap();
+    cpu.set_register_value(2, 0x10).unwrap();
     mem.load_program(&[0x81, 0x22]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0x00, cpu.registers[1]);
 }
 
@@ -512,13 +727,14 @@ fn test_execute_cycle_0x8002() {
 fn test_execute_cycle_0x8003() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(1, 0x01);
-    cpu.set_register_value(2, 0x11);
+    cpu.set_register_value(1, 0x01).unwrap();
+    cpu.set_register_value(2, 0x11).unwrap();
     mem.load_program(&[0x81, 0x23]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0x10, cpu.registers[1]);
 }
 
@@ -526,13 +742,14 @@ fn test_execute_cycle_0x8003() {
 fn test_execute_cycle_0x8004_overflow() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(1, 0xFF);
-    cpu.set_register_value(2, 0xFF);
+    cpu.set_register_value(1, 0xFF).unwrap();
+    cpu.set_register_value(2, 0xFF).unwrap();
     mem.load_program(&[0x81, 0x24]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0xFE, cpu.registers[1]);
     assert_eq!(0x01, cpu.registers[15]);
 }
@@ -541,13 +758,14 @@ fn test_execute_cycle_0x8004_overflow() {
 fn test_execute_cycle_0x8004_no_overflow() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(1, 0x01);
-    cpu.set_register_value(2, 0x01);
+    cpu.set_register_value(1, 0x01).unwrap();
+    cpu.set_register_value(2, 0x01).unwrap();
     mem.load_program(&[0x81, 0x24]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0x02, cpu.registers[1]);
     assert_eq!(0x00, cpu.registers[15]);
 }
@@ -556,56 +774,60 @@ fn test_execute_cycle_0x8004_no_overflow() {
 fn test_execute_cycle_0x8005_borrow() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(1, 0x00);
-    cpu.set_register_value(2, 0xFF);
+    cpu.set_register_value(1, 0x00).unwrap();
+    cpu.set_register_value(2, 0xFF).unwrap();
     mem.load_program(&[0x81, 0x25]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0x01, cpu.registers[1]);
-    assert_eq!(0x01, cpu.registers[15]);
+    assert_eq!(0x00, cpu.registers[15]);
 }
 
 #[test]
 fn test_execute_cycle_0x8005_no_borrow() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(1, 0xFF);
-    cpu.set_register_value(2, 0x01);
+    cpu.set_register_value(1, 0xFF).unwrap();
+    cpu.set_register_value(2, 0x01).unwrap();
     mem.load_program(&[0x81, 0x25]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0xFE, cpu.registers[1]);
-    assert_eq!(0x00, cpu.registers[15]);
+    assert_eq!(0x01, cpu.registers[15]);
 }
 
 #[test]
 fn test_execute_cycle_0x8006_lsb_0() {
-    let mut cpu = Cpu::new();
+    let mut cpu = Cpu::with_quirks(Quirks::CHIP_48);
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(1, 0xF0);
+    cpu.set_register_value(1, 0xF0).unwrap();
     mem.load_program(&[0x81, 0x06]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0x78, cpu.registers[1]);
     assert_eq!(0x00, cpu.registers[15]);
 }
 
 #[test]
 fn test_execute_cycle_0x8006_lsb_1() {
-    let mut cpu = Cpu::new();
+    let mut cpu = Cpu::with_quirks(Quirks::CHIP_48);
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(1, 0xF1);
+    cpu.set_register_value(1, 0xF1).unwrap();
     mem.load_program(&[0x81, 0x06]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0x78, cpu.registers[1]);
     assert_eq!(0x01, cpu.registers[15]);
 }
@@ -614,56 +836,60 @@ fn test_execute_cycle_0x8006_lsb_1() {
 fn test_execute_cycle_0x8007_borrow() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(1, 0xFF);
-    cpu.set_register_value(2, 0x00);
+    cpu.set_register_value(1, 0xFF).unwrap();
+    cpu.set_register_value(2, 0x00).unwrap();
     mem.load_program(&[0x81, 0x27]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0x01, cpu.registers[1]);
-    assert_eq!(0x01, cpu.registers[15]);
+    assert_eq!(0x00, cpu.registers[15]);
 }
 
 #[test]
 fn test_execute_cycle_0x8007_no_borrow() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(1, 0x00);
-    cpu.set_register_value(2, 0xFF);
+    cpu.set_register_value(1, 0x00).unwrap();
+    cpu.set_register_value(2, 0xFF).unwrap();
     mem.load_program(&[0x81, 0x27]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0xFF, cpu.registers[1]);
-    assert_eq!(0x00, cpu.registers[15]);
+    assert_eq!(0x01, cpu.registers[15]);
 }
 
 #[test]
 fn test_execute_cycle_0x800e_msb_0() {
-    let mut cpu = Cpu::new();
+    let mut cpu = Cpu::with_quirks(Quirks::CHIP_48);
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(1, 0x70);
+    cpu.set_register_value(1, 0x70).unwrap();
     mem.load_program(&[0x81, 0x0E]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0xE0, cpu.registers[1]);
     assert_eq!(0x00, cpu.registers[15]);
 }
 
 #[test]
 fn test_execute_cycle_0x800e_msb_1() {
-    let mut cpu = Cpu::new();
+    let mut cpu = Cpu::with_quirks(Quirks::CHIP_48);
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(1, 0xFF);
+    cpu.set_register_value(1, 0xFF).unwrap();
     mem.load_program(&[0x81, 0x0E]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0xFE, cpu.registers[1]);
     assert_eq!(0x01, cpu.registers[15]);
 }
@@ -672,12 +898,13 @@ fn test_execute_cycle_0x800e_msb_1() {
 fn test_execute_cycle_0x9000_equal() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(1, 0xFF);
-    cpu.set_register_value(2, 0xFF);
+    cpu.set_register_value(1, 0xFF).unwrap();
+    cpu.set_register_value(2, 0xFF).unwrap();
     mem.load_program(&[0x91, 0x20]).unwrap();
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0x202, cpu.program_counter);
 }
@@ -686,12 +913,13 @@ fn test_execute_cycle_0x9000_equal() {
 fn test_execute_cycle_0x9000_not_equal() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(1, 0x01);
-    cpu.set_register_value(2, 0xFF);
+    cpu.set_register_value(1, 0x01).unwrap();
+    cpu.set_register_value(2, 0xFF).unwrap();
     mem.load_program(&[0x91, 0x20]).unwrap();
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0x204, cpu.program_counter);
 }
@@ -700,13 +928,14 @@ fn test_execute_cycle_0x9000_not_equal() {
 fn test_execute_cycle_0xa000() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
     mem.load_program(&[0xAF, 0xFF, 0xA2, 0xFF]).unwrap();
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0xFFF, cpu.index);
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0x2FF, cpu.index);
 }
 
@@ -714,12 +943,13 @@ fn test_execute_cycle_0xa000() {
 fn test_execute_cycle_0xb000() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(0, 0xFF);
-    cpu.set_register_value(2, 0xFF);
+    cpu.set_register_value(0, 0xFF).unwrap();
+    cpu.set_register_value(2, 0xFF).unwrap();
     mem.load_program(&[0xB0, 0x00]).unwrap();
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0xFF, cpu.program_counter);
 }
@@ -728,12 +958,13 @@ fn test_execute_cycle_0xb000() {
 fn test_execute_cycle_0xc000() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [false; 16];
 
-    cpu.set_register_value(1, 0xFF);
-    cpu.set_register_value(2, 0xFF);
+    cpu.set_register_value(1, 0xFF).unwrap();
+    cpu.set_register_value(2, 0xFF).unwrap();
     mem.load_program(&[0xC1, 0x00]).unwrap();
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0x00, cpu.registers[0]);
 }
@@ -742,13 +973,14 @@ fn test_execute_cycle_0xc000() {
 fn test_execute_cycle_0xe000_pressed() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let mut keypad: [bool; 16] = [false; 16];
 
     keypad[0xE] = true;
-    cpu.set_register_value(0xE, 0xE);
+    cpu.set_register_value(0xE, 0xE).unwrap();
 
     mem.load_program(&[0xEE, 0x9E]).unwrap();
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0x204, cpu.program_counter);
 }
@@ -757,27 +989,66 @@ fn test_execute_cycle_0xe000_pressed() {
 fn test_execute_cycle_0xe000_not_pressed() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let mut keypad: [bool; 16] = [true; 16];
 
     keypad[0xE] = false;
 
-    cpu.set_register_value(0xE, 0xE);
+    cpu.set_register_value(0xE, 0xE).unwrap();
 
     mem.load_program(&[0xEE, 0xA1]).unwrap();
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0x204, cpu.program_counter);
 }
 
+#[test]
+fn test_execute_cycle_0xe000_no_skip() {
+    let mut cpu = Cpu::new();
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let mut keypad: [bool; 16] = [false; 16];
+
+    cpu.set_register_value(0xE, 0xE).unwrap();
+
+    mem.load_program(&[0xEE, 0x9E, 0xEE, 0xA1]).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    assert_eq!(0x202, cpu.program_counter);
+
+    keypad[0xE] = true;
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    assert_eq!(0x204, cpu.program_counter);
+}
+
+#[test]
+fn test_execute_cycle_0xex9e_key_above_0xf() {
+    let mut cpu = Cpu::new();
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let mut keypad: [bool; 16] = [false; 16];
+
+    keypad[0x0] = true;
+    cpu.set_register_value(0x0, 0x20).unwrap();
+
+    mem.load_program(&[0xE0, 0x9E, 0x00, 0x00, 0xE0, 0xA1])
+        .unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    assert_eq!(0x204, cpu.program_counter);
+
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    assert_eq!(0x206, cpu.program_counter);
+}
+
 #[test]
 fn test_execute_cycle_0xfx07_set_delay() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [true; 16];
 
     cpu.delay_timer = 0xFF;
     mem.load_program(&[0xF2, 0x07]).unwrap();
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0xFF, cpu.registers[2]);
 }
@@ -786,21 +1057,22 @@ fn test_execute_cycle_0xfx07_set_delay() {
 fn test_execute_cycle_0xfx0a_wait_for_key() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let mut keypad: [bool; 16] = [false; 16];
     mem.load_program(&[0xF2, 0x0A]).unwrap();
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0x200, cpu.program_counter);
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0x200, cpu.program_counter);
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0x200, cpu.program_counter);
 
     keypad[0xF] = true;
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
     assert_eq!(0x202, cpu.program_counter);
     assert_eq!(0xF, cpu.registers[2]);
 }
@@ -809,28 +1081,64 @@ fn test_execute_cycle_0xfx0a_wait_for_key() {
 fn test_execute_cycle_0xfx15_set_delay_to_register() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [true; 16];
 
-    cpu.set_register_value(2, 0xFF);
+    cpu.set_register_value(2, 0xFF).unwrap();
     mem.load_program(&[0xF2, 0x15]).unwrap();
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0xFF, cpu.delay_timer);
 }
 
+#[test]
+fn test_execute_cycle_0xfx18_set_sound_to_register() {
+    let mut cpu = Cpu::new();
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [true; 16];
+
+    cpu.set_register_value(2, 0x02).unwrap();
+    mem.load_program(&[0xF2, 0x18]).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert_eq!(0x02, cpu.sound_timer);
+    assert_eq!(0x202, cpu.program_counter);
+    assert!(cpu.sound_active());
+}
+
+#[test]
+fn test_tick_timers() {
+    let mut cpu = Cpu::new();
+
+    cpu.delay_timer = 2;
+    cpu.sound_timer = 1;
+
+    cpu.tick_timers();
+    assert_eq!(1, cpu.delay_timer);
+    assert_eq!(0, cpu.sound_timer);
+    assert!(!cpu.sound_active());
+
+    cpu.tick_timers();
+    cpu.tick_timers();
+    assert_eq!(0, cpu.delay_timer);
+    assert_eq!(0, cpu.sound_timer);
+}
+
 #[test]
 fn test_execute_cycle_0xfx1e() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [true; 16];
 
-    cpu.set_register_value(2, 0xFF);
+    cpu.set_register_value(2, 0xFF).unwrap();
     mem.load_program(&[0xF2, 0x1E, 0xF2, 0x1E]).unwrap();
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0xFF, cpu.index);
 
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0xFF + 0xFF, cpu.index);
 }
@@ -839,11 +1147,12 @@ fn test_execute_cycle_0xfx1e() {
 fn test_execute_cycle_0xfx29() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [true; 16];
 
-    cpu.set_register_value(2, 0x0);
+    cpu.set_register_value(2, 0x0).unwrap();
     mem.load_program(&[0xF2, 0x29]).unwrap();
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(cpu.index, 0x1AF)
 }
@@ -852,47 +1161,49 @@ fn test_execute_cycle_0xfx29() {
 fn test_execute_cycle_0xfx33() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [true; 16];
 
-    cpu.set_register_value(2, 0xFF);
+    cpu.set_register_value(2, 0xFF).unwrap();
     cpu.index = 0x200;
     mem.load_program(&[0xF2, 0x33]).unwrap();
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
-    assert_eq!(2, mem.fetch(0x200));
-    assert_eq!(5, mem.fetch(0x201));
-    assert_eq!(5, mem.fetch(0x202));
+    assert_eq!(2, mem.fetch(0x200).unwrap());
+    assert_eq!(5, mem.fetch(0x201).unwrap());
+    assert_eq!(5, mem.fetch(0x202).unwrap());
 }
 
 #[test]
 fn test_execute_cycle_0xfx55() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [true; 16];
 
-    cpu.set_register_value(0, 0x00);
-    cpu.set_register_value(1, 0x01);
-    cpu.set_register_value(2, 0x02);
-    cpu.set_register_value(3, 0x03);
-    cpu.set_register_value(4, 0x04);
-    cpu.set_register_value(5, 0x05);
-    cpu.set_register_value(6, 0x06);
-    cpu.set_register_value(7, 0x07);
-    cpu.set_register_value(8, 0x08);
+    cpu.set_register_value(0, 0x00).unwrap();
+    cpu.set_register_value(1, 0x01).unwrap();
+    cpu.set_register_value(2, 0x02).unwrap();
+    cpu.set_register_value(3, 0x03).unwrap();
+    cpu.set_register_value(4, 0x04).unwrap();
+    cpu.set_register_value(5, 0x05).unwrap();
+    cpu.set_register_value(6, 0x06).unwrap();
+    cpu.set_register_value(7, 0x07).unwrap();
+    cpu.set_register_value(8, 0x08).unwrap();
 
     cpu.index = 0x204;
     mem.load_program(&[0xF8, 0x55]).unwrap();
-    cpu.execute_cycle(&mut mem, &keypad);
-
-    assert_eq!(0, mem.fetch(0x204));
-    assert_eq!(1, mem.fetch(0x205));
-    assert_eq!(2, mem.fetch(0x206));
-    assert_eq!(3, mem.fetch(0x207));
-    assert_eq!(4, mem.fetch(0x208));
-    assert_eq!(5, mem.fetch(0x209));
-    assert_eq!(6, mem.fetch(0x20A));
-    assert_eq!(7, mem.fetch(0x20B));
-    assert_eq!(8, mem.fetch(0x20C));
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert_eq!(0, mem.fetch(0x204).unwrap());
+    assert_eq!(1, mem.fetch(0x205).unwrap());
+    assert_eq!(2, mem.fetch(0x206).unwrap());
+    assert_eq!(3, mem.fetch(0x207).unwrap());
+    assert_eq!(4, mem.fetch(0x208).unwrap());
+    assert_eq!(5, mem.fetch(0x209).unwrap());
+    assert_eq!(6, mem.fetch(0x20A).unwrap());
+    assert_eq!(7, mem.fetch(0x20B).unwrap());
+    assert_eq!(8, mem.fetch(0x20C).unwrap());
     assert_eq!(0x20D, cpu.index);
 }
 
@@ -900,21 +1211,22 @@ fn test_execute_cycle_0xfx55() {
 fn test_execute_cycle_0xfx65() {
     let mut cpu = Cpu::new();
     let mut mem = mem::Mem::new();
+    let mut display = Display::new();
     let keypad: [bool; 16] = [true; 16];
 
-    mem.store(0x300, 0);
-    mem.store(0x301, 1);
-    mem.store(0x302, 2);
-    mem.store(0x303, 3);
-    mem.store(0x304, 4);
-    mem.store(0x305, 5);
-    mem.store(0x306, 6);
-    mem.store(0x307, 7);
-    mem.store(0x308, 8);
+    mem.store(0x300, 0).unwrap();
+    mem.store(0x301, 1).unwrap();
+    mem.store(0x302, 2).unwrap();
+    mem.store(0x303, 3).unwrap();
+    mem.store(0x304, 4).unwrap();
+    mem.store(0x305, 5).unwrap();
+    mem.store(0x306, 6).unwrap();
+    mem.store(0x307, 7).unwrap();
+    mem.store(0x308, 8).unwrap();
 
     cpu.index = 0x300;
     mem.load_program(&[0xF8, 0x65]).unwrap();
-    cpu.execute_cycle(&mut mem, &keypad);
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
 
     assert_eq!(0, cpu.registers[0]);
     assert_eq!(1, cpu.registers[1]);
@@ -926,3 +1238,694 @@ fn test_execute_cycle_0xfx65() {
     assert_eq!(7, cpu.registers[7]);
     assert_eq!(8, cpu.registers[8]);
 }
+
+#[test]
+fn test_execute_cycle_invalid_opcode() {
+    let mut cpu = Cpu::new();
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    mem.load_program(&[0x81, 0x2F]).unwrap();
+
+    assert_eq!(
+        Err(EmulatorError::InvalidOpcode {
+            opcode: 0x812F,
+            address: 0x200
+        }),
+        cpu.execute_cycle(&mut mem, &mut display, &keypad)
+    );
+    assert_eq!(0x200, cpu.program_counter);
+}
+
+#[test]
+fn test_execute_cycle_0x00ee_stack_underflow() {
+    let mut cpu = Cpu::new();
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    mem.load_program(&[0x00, 0xEE]).unwrap();
+
+    assert_eq!(
+        Err(EmulatorError::StackUnderflow),
+        cpu.execute_cycle(&mut mem, &mut display, &keypad)
+    );
+}
+
+#[test]
+fn test_execute_cycle_0x2xxx_stack_depth() {
+    let mut cpu = Cpu::new();
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    // 17 calls, each to the instruction after it
+    let program: Vec<u8> = (0..17u16)
+        .flat_map(|i| (0x2202 + 2 * i).to_be_bytes())
+        .collect();
+    mem.load_program(&program).unwrap();
+    for _ in 0..16 {
+        cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    }
+
+    assert_eq!(16, mem.stack().len());
+    assert_eq!(
+        Err(EmulatorError::StackOverflow),
+        cpu.execute_cycle(&mut mem, &mut display, &keypad)
+    );
+}
+
+#[test]
+fn test_execute_cycle_0xfx55_protected_write() {
+    let mut cpu = Cpu::new();
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    cpu.index = 0x100;
+    mem.load_program(&[0xF0, 0x55]).unwrap();
+
+    assert_eq!(
+        Err(EmulatorError::ProtectedWrite { address: 0x100 }),
+        cpu.execute_cycle(&mut mem, &mut display, &keypad)
+    );
+}
+
+#[test]
+fn test_failing_instructions_change_nothing() {
+    let mut cpu = Cpu::new();
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    // save v0 - v3, bcd v0, load v0 - v3 and draw at the end of memory
+    mem.load_program(&[0xF3, 0x55, 0xF0, 0x33, 0xF3, 0x65, 0xD0, 0x05])
+        .unwrap();
+    cpu.registers[..4].copy_from_slice(&[1, 2, 3, 4]);
+    cpu.registers[0xF] = 7;
+    cpu.index = (mem::MEMORY_SIZE - 2) as u16;
+    let memory = mem.memory().to_vec();
+
+    for _ in 0..4 {
+        let address = cpu.program_counter;
+        assert!(cpu.execute_cycle(&mut mem, &mut display, &keypad).is_err());
+        assert_eq!(address, cpu.program_counter);
+        assert_eq!(memory, mem.memory());
+        assert_eq!([1, 2, 3, 4], cpu.registers[..4]);
+        assert_eq!(7, cpu.registers[0xF]);
+        assert_eq!(Display::new().hash(), display.hash());
+        cpu.program_counter += 2;
+    }
+}
+
+#[test]
+fn test_execute_invalid_operands() {
+    let mut cpu = Cpu::with_quirks(Quirks {
+        schip: true,
+        ..Quirks::COSMAC_VIP
+    });
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    for &instruction in [
+        Instruction::LdByte { x: 16, byte: 0 },
+        Instruction::LdRVx { x: 16 },
+        Instruction::LdReg { x: 0, y: 16 },
+    ]
+    .iter()
+    {
+        assert_eq!(
+            Err(EmulatorError::InvalidRegister { register: 16 }),
+            cpu.execute(instruction, &mut mem, &mut display, &keypad)
+        );
+    }
+    assert_eq!(0x200, cpu.program_counter);
+
+    cpu.registers[0] = 0x10;
+    cpu.execute(Instruction::JpV0(0xFFF8), &mut mem, &mut display, &keypad)
+        .unwrap();
+    assert_eq!(0x0008, cpu.program_counter);
+}
+
+#[test]
+fn test_set_register_value_invalid_register() {
+    let mut cpu = Cpu::new();
+
+    assert_eq!(
+        Err(EmulatorError::InvalidRegister { register: 16 }),
+        cpu.set_register_value(16, 0xFF)
+    );
+}
+
+#[test]
+fn test_execute_cycle_0x00e0() {
+    let mut cpu = Cpu::new();
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    display.set(10, 10, true);
+    mem.load_program(&[0x00, 0xE0]).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert!(!display.get(10, 10));
+    assert_eq!(0x202, cpu.program_counter);
+}
+
+#[test]
+fn test_execute_cycle_0xdxyn() {
+    let mut cpu = Cpu::new();
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    cpu.set_register_value(1, 2).unwrap();
+    cpu.set_register_value(2, 3).unwrap();
+    cpu.index = 0x300;
+    mem.store(0x300, 0b1010_0000).unwrap();
+    mem.store(0x301, 0b0100_0000).unwrap();
+    mem.load_program(&[0xD1, 0x22, 0xD1, 0x22]).unwrap();
+
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    assert!(display.get(2, 3));
+    assert!(!display.get(3, 3));
+    assert!(display.get(4, 3));
+    assert!(display.get(3, 4));
+    assert_eq!(0, cpu.registers[0xF]);
+
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    assert!(!display.get(2, 3));
+    assert!(!display.get(3, 4));
+    assert_eq!(1, cpu.registers[0xF]);
+}
+
+#[test]
+fn test_execute_cycle_0xdxyn_wraps_start_position() {
+    let mut cpu = Cpu::new();
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    cpu.set_register_value(1, 64 + 5).unwrap();
+    cpu.set_register_value(2, 32 + 7).unwrap();
+    cpu.index = 0x300;
+    mem.store(0x300, 0x80).unwrap();
+    mem.load_program(&[0xD1, 0x21]).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert!(display.get(5, 7));
+}
+
+#[test]
+fn test_execute_cycle_0xdxyn_clips() {
+    let mut cpu = Cpu::new();
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    cpu.set_register_value(1, 60).unwrap();
+    cpu.set_register_value(2, 31).unwrap();
+    cpu.index = 0x300;
+    mem.store(0x300, 0xFF).unwrap();
+    mem.store(0x301, 0xFF).unwrap();
+    mem.load_program(&[0xD1, 0x22]).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert!(display.get(60, 31));
+    assert!(display.get(63, 31));
+    assert!(!display.get(0, 31));
+    assert!(!display.get(60, 0));
+    assert!(!display.get(0, 0));
+}
+
+#[test]
+fn test_execute_cycle_0xdxyn_wraps() {
+    let mut cpu = Cpu::with_quirks(Quirks {
+        wrap_sprites: true,
+        ..Quirks::COSMAC_VIP
+    });
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    cpu.set_register_value(1, 60).unwrap();
+    cpu.set_register_value(2, 31).unwrap();
+    cpu.index = 0x300;
+    mem.store(0x300, 0xFF).unwrap();
+    mem.store(0x301, 0xFF).unwrap();
+    mem.load_program(&[0xD1, 0x22]).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert!(display.get(63, 31));
+    assert!(display.get(3, 31));
+    assert!(display.get(60, 0));
+    assert!(display.get(3, 0));
+    assert!(!display.get(4, 0));
+}
+
+#[test]
+fn test_execute_cycle_0x8006_shift_uses_vy() {
+    let mut cpu = Cpu::with_quirks(Quirks::COSMAC_VIP);
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    cpu.set_register_value(1, 0xFF).unwrap();
+    cpu.set_register_value(2, 0x05).unwrap();
+    mem.load_program(&[0x81, 0x26]).unwrap();
+
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    assert_eq!(0x02, cpu.registers[1]);
+    assert_eq!(0x05, cpu.registers[2]);
+    assert_eq!(0x01, cpu.registers[15]);
+}
+
+#[test]
+fn test_execute_cycle_0x800e_shift_uses_vy() {
+    let mut cpu = Cpu::with_quirks(Quirks::COSMAC_VIP);
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    cpu.set_register_value(1, 0x01).unwrap();
+    cpu.set_register_value(2, 0x81).unwrap();
+    mem.load_program(&[0x81, 0x2E]).unwrap();
+
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    assert_eq!(0x02, cpu.registers[1]);
+    assert_eq!(0x01, cpu.registers[15]);
+}
+
+#[test]
+fn test_execute_cycle_0x8001_logic_resets_vf() {
+    let keypad: [bool; 16] = [false; 16];
+
+    for (quirks, expected_vf) in [(Quirks::COSMAC_VIP, 0x00), (Quirks::SCHIP_1_1, 0x01)] {
+        let mut cpu = Cpu::with_quirks(quirks);
+        let mut mem = mem::Mem::new();
+        let mut display = Display::new();
+
+        cpu.set_register_value(0xF, 0x01).unwrap();
+        mem.load_program(&[0x81, 0x21]).unwrap();
+
+        cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+        assert_eq!(expected_vf, cpu.registers[15]);
+    }
+}
+
+#[test]
+fn test_execute_cycle_0xb000_jump_uses_vx() {
+    let mut cpu = Cpu::with_quirks(Quirks::SCHIP_1_1);
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    cpu.set_register_value(0, 0xFF).unwrap();
+    cpu.set_register_value(3, 0x02).unwrap();
+    mem.load_program(&[0xB3, 0x00]).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert_eq!(0x302, cpu.program_counter);
+}
+
+#[test]
+fn test_execute_cycle_0xfx55_without_index_increment() {
+    let mut cpu = Cpu::with_quirks(Quirks::SCHIP_1_1);
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    cpu.set_register_value(1, 0x01).unwrap();
+    cpu.index = 0x300;
+    mem.load_program(&[0xF1, 0x55, 0xF1, 0x65]).unwrap();
+
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    assert_eq!(1, mem.fetch(0x301).unwrap());
+    assert_eq!(0x300, cpu.index);
+
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    assert_eq!(0x300, cpu.index);
+}
+
+#[test]
+fn test_execute_cycle_0xdxyn_display_wait() {
+    let mut cpu = Cpu::with_quirks(Quirks::COSMAC_VIP);
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    mem.load_program(&[0xD0, 0x01]).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    assert!(cpu.waiting_for_vblank());
+
+    cpu.tick_timers();
+    assert!(!cpu.waiting_for_vblank());
+}
+
+#[test]
+fn test_execute_cycle_0x00fe_0x00ff() {
+    let mut cpu = Cpu::with_quirks(Quirks::SCHIP_1_1);
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    mem.load_program(&[0x00, 0xFF, 0x00, 0xFE]).unwrap();
+
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    assert!(display.hires());
+    assert_eq!(128, display.width());
+
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    assert!(!display.hires());
+    assert_eq!(0x204, cpu.program_counter);
+}
+
+#[test]
+fn test_execute_cycle_scroll() {
+    let mut cpu = Cpu::with_quirks(Quirks::SCHIP_1_1);
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    display.set(10, 10, true);
+    mem.load_program(&[0x00, 0xC2, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFC])
+        .unwrap();
+
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    assert!(display.get(10, 12));
+
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    assert!(display.get(14, 12));
+
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    assert!(display.get(6, 12));
+    assert_eq!(0x208, cpu.program_counter);
+}
+
+#[test]
+fn test_execute_cycle_0x00fd() {
+    let mut cpu = Cpu::with_quirks(Quirks::SCHIP_1_1);
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    mem.load_program(&[0x00, 0xFD, 0x61, 0x01]).unwrap();
+
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    assert!(cpu.halted());
+    assert_eq!(0x200, cpu.program_counter);
+    assert_eq!(0, cpu.registers[1]);
+}
+
+#[test]
+fn test_execute_cycle_0xdxy0() {
+    let mut cpu = Cpu::with_quirks(Quirks::SCHIP_1_1);
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    display.set_hires(true);
+    cpu.set_register_value(1, 100).unwrap();
+    cpu.set_register_value(2, 40).unwrap();
+    cpu.index = 0x300;
+    mem.store(0x300, 0x80).unwrap();
+    mem.store(0x301, 0x01).unwrap();
+    mem.store(0x31E, 0xFF).unwrap();
+    mem.store(0x31F, 0xFF).unwrap();
+    mem.load_program(&[0xD1, 0x20]).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert!(display.get(100, 40));
+    assert!(!display.get(101, 40));
+    assert!(display.get(115, 40));
+    assert!(display.get(100, 55));
+    assert!(display.get(115, 55));
+    assert!(!display.get(100, 56));
+}
+
+#[test]
+fn test_execute_cycle_0xdxy0_without_schip() {
+    let mut cpu = Cpu::with_quirks(Quirks::CHIP_48);
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    cpu.index = 0x300;
+    mem.store(0x300, 0xFF).unwrap();
+    mem.load_program(&[0xD1, 0x20]).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert!(!display.get(0, 0));
+    assert_eq!(0x202, cpu.program_counter);
+}
+
+#[test]
+fn test_execute_cycle_0xfx30() {
+    let mut cpu = Cpu::with_quirks(Quirks::SCHIP_1_1);
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    cpu.set_register_value(2, 0x1).unwrap();
+    mem.load_program(&[0xF2, 0x30]).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert_eq!(mem.get_address_for_big_digit(1), cpu.index);
+}
+
+#[test]
+fn test_execute_cycle_0xfx75_0xfx85() {
+    let mut cpu = Cpu::with_quirks(Quirks::SCHIP_1_1);
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    cpu.set_register_value(0, 0x10).unwrap();
+    cpu.set_register_value(1, 0x11).unwrap();
+    cpu.set_register_value(2, 0x12).unwrap();
+    mem.load_program(&[0xF1, 0x75, 0x60, 0x00, 0x61, 0x00, 0x62, 0x00, 0xF2, 0x85])
+        .unwrap();
+    for _ in 0..5 {
+        cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    }
+
+    assert_eq!(0x10, cpu.registers[0]);
+    assert_eq!(0x11, cpu.registers[1]);
+    assert_eq!(0x00, cpu.registers[2]);
+}
+
+#[test]
+fn test_execute_cycle_0xf000_long_index() {
+    let mut cpu = Cpu::with_quirks(Quirks::XO_CHIP);
+    let mut mem = mem::Mem::with_size(mem::XO_CHIP_MEMORY_SIZE);
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    mem.load_program(&[0xF0, 0x00, 0xFF, 0x00]).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert_eq!(0xFF00, cpu.index);
+    assert_eq!(0x204, cpu.program_counter);
+}
+
+#[test]
+fn test_execute_cycle_0x00ff_requires_schip() {
+    let mut cpu = Cpu::with_quirks(Quirks::CHIP_48);
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    mem.load_program(&[0x00, 0xFF]).unwrap();
+
+    assert_eq!(
+        Err(EmulatorError::InvalidOpcode {
+            opcode: 0x00FF,
+            address: 0x200
+        }),
+        cpu.execute_cycle(&mut mem, &mut display, &keypad)
+    );
+    assert!(!display.hires());
+}
+
+#[test]
+fn test_execute_cycle_0xf000_requires_xo_chip() {
+    let mut cpu = Cpu::new();
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    mem.load_program(&[0xF0, 0x00, 0xFF, 0x00]).unwrap();
+
+    assert_eq!(
+        Err(EmulatorError::InvalidOpcode {
+            opcode: 0xF000,
+            address: 0x200
+        }),
+        cpu.execute_cycle(&mut mem, &mut display, &keypad)
+    );
+}
+
+#[test]
+fn test_execute_cycle_skip_over_0xf000() {
+    let mut cpu = Cpu::with_quirks(Quirks::XO_CHIP);
+    let mut mem = mem::Mem::with_size(mem::XO_CHIP_MEMORY_SIZE);
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    mem.load_program(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34])
+        .unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert_eq!(0x206, cpu.program_counter);
+}
+
+#[test]
+fn test_execute_cycle_0x5xy2_0x5xy3() {
+    let mut cpu = Cpu::with_quirks(Quirks::XO_CHIP);
+    let mut mem = mem::Mem::with_size(mem::XO_CHIP_MEMORY_SIZE);
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    cpu.set_register_value(1, 0x11).unwrap();
+    cpu.set_register_value(2, 0x22).unwrap();
+    cpu.set_register_value(3, 0x33).unwrap();
+    cpu.index = 0x300;
+    // store v1 - v3, then load them back reversed into v3 - v1
+    mem.load_program(&[0x51, 0x32, 0x53, 0x13]).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert_eq!(0x11, mem.fetch(0x300).unwrap());
+    assert_eq!(0x22, mem.fetch(0x301).unwrap());
+    assert_eq!(0x33, mem.fetch(0x302).unwrap());
+    assert_eq!(0x300, cpu.index);
+
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert_eq!(0x11, cpu.registers[3]);
+    assert_eq!(0x22, cpu.registers[2]);
+    assert_eq!(0x33, cpu.registers[1]);
+    assert_eq!(0x300, cpu.index);
+}
+
+#[test]
+fn test_execute_cycle_0xfn01_0xdxyn_planes() {
+    let mut cpu = Cpu::with_quirks(Quirks::XO_CHIP);
+    let mut mem = mem::Mem::with_size(mem::XO_CHIP_MEMORY_SIZE);
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    cpu.index = 0x300;
+    // one row for the first plane followed by one row for the second
+    mem.store(0x300, 0xC0).unwrap();
+    mem.store(0x301, 0x60).unwrap();
+    mem.load_program(&[0xF3, 0x01, 0xD0, 0x01, 0xF2, 0x01, 0xD0, 0x01])
+        .unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert_eq!(PLANE_1, display.pixel(0, 0));
+    assert_eq!(PLANE_1 | PLANE_2, display.pixel(1, 0));
+    assert_eq!(PLANE_2, display.pixel(2, 0));
+    assert_eq!(0, cpu.registers[0xF]);
+
+    // the second plane alone reads its sprite from I and collides with itself
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert_eq!(PLANE_1 | PLANE_2, display.pixel(0, 0));
+    assert_eq!(PLANE_1, display.pixel(1, 0));
+    assert_eq!(PLANE_2, display.pixel(2, 0));
+    assert_eq!(1, cpu.registers[0xF]);
+}
+
+#[test]
+fn test_execute_cycle_0x00dn() {
+    let mut cpu = Cpu::with_quirks(Quirks::XO_CHIP);
+    let mut mem = mem::Mem::with_size(mem::XO_CHIP_MEMORY_SIZE);
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    display.set(3, 10, true);
+    mem.load_program(&[0x00, 0xD4]).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert!(!display.get(3, 10));
+    assert!(display.get(3, 6));
+}
+
+#[test]
+fn test_execute_cycle_0xf002_0xfx3a() {
+    let mut cpu = Cpu::with_quirks(Quirks::XO_CHIP);
+    let mut mem = mem::Mem::with_size(mem::XO_CHIP_MEMORY_SIZE);
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    cpu.index = 0x300;
+    for offset in 0..audio::PATTERN_SIZE {
+        mem.store(0x300 + offset, offset as u8).unwrap();
+    }
+    cpu.set_register_value(4, 112).unwrap();
+    mem.load_program(&[0xF0, 0x02, 0xF4, 0x3A]).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+    cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+
+    assert_eq!(15, cpu.audio_pattern()[15]);
+    assert_eq!(1, cpu.audio_pattern()[1]);
+    assert_eq!(112, cpu.pitch());
+    assert_eq!(0x300, cpu.index);
+}
+
+#[test]
+fn test_execute_cycle_0xc000_seeded() {
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+    mem.load_program(&[0xC1, 0xFF, 0xC2, 0xFF]).unwrap();
+    let mut run = |seed| {
+        let mut cpu = Cpu::new();
+        cpu.seed_rng(seed);
+        cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+        cpu.execute_cycle(&mut mem, &mut display, &keypad).unwrap();
+        (cpu.registers[1], cpu.registers[2])
+    };
+
+    let mut expected = Rng::new(1234);
+    assert_eq!((expected.next_u8(), expected.next_u8()), run(1234));
+    assert_eq!(run(1234), run(1234));
+    assert_ne!(run(1234), run(4321));
+}
+
+#[test]
+fn test_execute_decoded_instruction() {
+    let mut cpu = Cpu::new();
+    let mut mem = mem::Mem::new();
+    let mut display = Display::new();
+    let keypad: [bool; 16] = [false; 16];
+
+    cpu.execute(
+        Instruction::LdByte { x: 3, byte: 0x42 },
+        &mut mem,
+        &mut display,
+        &keypad,
+    )
+    .unwrap();
+
+    assert_eq!(0x42, cpu.registers[3]);
+    assert_eq!(0x202, cpu.program_counter);
+    assert_eq!(
+        Err(EmulatorError::InvalidOpcode {
+            opcode: 0x5132,
+            address: 0x202
+        }),
+        cpu.execute(
+            Instruction::SaveRange { x: 1, y: 3 },
+            &mut mem,
+            &mut display,
+            &keypad
+        )
+    );
+}
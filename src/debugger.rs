@@ -0,0 +1,508 @@
+/*
+    The debugger runs a `Chip8` one instruction at a time, so it can stop in the middle of a
+    frame. It counts the instructions run in the current frame itself and ticks the timers
+    where `Chip8::run_frame` would, so a program behaves the same whether it is stepped
+    through or run at full speed.
+
+    Execution stops before the instruction at a breakpoint runs. Continuing from a stop runs
+    that instruction, so resuming at a breakpoint does not stop again right away. A breakpoint
+    with a condition only stops when the condition holds.
+
+    Watchpoints stop after the instruction that triggered them. Memory watchpoints are set on
+    `Chip8::mem`, which records the reads and writes made by the program, while the debugger
+    compares watched registers before and after every instruction.
+*/
+use crate::chip8::Chip8;
+use crate::condition::Condition;
+use crate::error::EmulatorError;
+use crate::instruction::{decode, Instruction};
+use crate::mem::WatchHit;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Why a step or run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// The step finished or the requested address was reached
+    Done,
+    /// Execution reached a breakpoint, the instruction at it has not run yet
+    Breakpoint(u16),
+    /// The program exited with 00FD
+    Halted,
+    /// The instruction limit was reached first
+    Limit,
+    /// The instruction at `address` read or wrote watched memory
+    Watchpoint { address: u16, hit: WatchHit },
+    /// The instruction at `address` changed a watched register
+    RegisterChanged {
+        address: u16,
+        register: Register,
+        old: u16,
+        new: u16,
+    },
+}
+
+/// A register that can be watched for changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Register {
+    V(u8),
+    I,
+}
+
+impl Register {
+    pub fn value(self, chip8: &Chip8) -> u16 {
+        match self {
+            Register::V(register) => chip8.cpu.registers()[register as usize & 0xF] as u16,
+            Register::I => chip8.cpu.index(),
+        }
+    }
+}
+
+/// Pauses, steps and sets breakpoints in a `Chip8`
+pub struct Debugger {
+    breakpoints: BTreeMap<u16, Option<Condition>>,
+    watched_registers: BTreeSet<Register>,
+    paused: bool,
+    /// Instructions run in the current frame
+    cycle: u32,
+    /// Set when execution stopped, so the breakpoint it stopped at does not stop it again
+    resuming: bool,
+    /// A watchpoint triggered by the last instruction, reported before running the next one
+    triggered: Option<Stop>,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: BTreeMap::new(),
+            watched_registers: BTreeSet::new(),
+            paused: false,
+            cycle: 0,
+            resuming: false,
+            triggered: None,
+        }
+    }
+
+    /// True after a stop, until `resume`. Frontends only call `run_frame` while not paused.
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.stop();
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Adds a breakpoint on the instruction at `address`, returns false if there already
+    /// was one
+    pub fn add_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.insert(address, None).is_none()
+    }
+
+    /// Adds a breakpoint that only stops when `condition` holds, replacing any breakpoint
+    /// at `address`
+    pub fn add_conditional_breakpoint(&mut self, address: u16, condition: Condition) -> bool {
+        self.breakpoints.insert(address, Some(condition)).is_none()
+    }
+
+    /// Removes the breakpoint at `address`, returns false if there was none
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address).is_some()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.keys().copied()
+    }
+
+    /// The condition of the breakpoint at `address`, if it has one
+    pub fn breakpoint_condition(&self, address: u16) -> Option<&Condition> {
+        self.breakpoints.get(&address)?.as_ref()
+    }
+
+    /// Stops after any instruction that changes `register`, returns false if it was
+    /// already watched
+    pub fn watch_register(&mut self, register: Register) -> bool {
+        self.watched_registers.insert(register)
+    }
+
+    /// Stops watching `register`, returns false if it was not watched
+    pub fn unwatch_register(&mut self, register: Register) -> bool {
+        self.watched_registers.remove(&register)
+    }
+
+    pub fn watched_registers(&self) -> impl Iterator<Item = Register> + '_ {
+        self.watched_registers.iter().copied()
+    }
+
+    /// Instructions run in the current frame
+    pub fn cycle(&self) -> u32 {
+        self.cycle
+    }
+
+    /// Starts counting a new frame, call this after restoring a state saved between frames
+    pub fn restart_frame(&mut self) {
+        self.cycle = 0;
+    }
+
+    /// Runs the rest of the current frame like `Chip8::run_frame`. Returns `None` when the
+    /// frame ended, or why execution stopped and paused before it did.
+    pub fn run_frame(
+        &mut self,
+        chip8: &mut Chip8,
+        keypad: &[bool; 16],
+    ) -> Result<Option<Stop>, EmulatorError> {
+        loop {
+            if let Some(stop) = self.check_stop(chip8) {
+                return Ok(Some(stop));
+            }
+            if self.execute(chip8, keypad)? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Runs a single instruction, regardless of breakpoints, and pauses
+    pub fn step(&mut self, chip8: &mut Chip8, keypad: &[bool; 16]) -> Result<Stop, EmulatorError> {
+        if chip8.halted() {
+            self.stop();
+            return Ok(Stop::Halted);
+        }
+        self.execute(chip8, keypad)?;
+        let stop = self.triggered.take().unwrap_or(Stop::Done);
+        self.stop();
+        Ok(stop)
+    }
+
+    /// Runs a single instruction, or a whole subroutine when the instruction calls one
+    pub fn step_over(
+        &mut self,
+        chip8: &mut Chip8,
+        keypad: &[bool; 16],
+        limit: u64,
+    ) -> Result<Stop, EmulatorError> {
+        let program_counter = chip8.cpu.program_counter();
+        let opcode = chip8.mem.fetch_opcode(program_counter as usize)?;
+        if let Ok(Instruction::Call(_)) = decode(opcode) {
+            let depth = chip8.mem.stack().len();
+            let return_address = program_counter.wrapping_add(2);
+            self.run_until(chip8, keypad, limit, |chip8| {
+                chip8.cpu.program_counter() == return_address && chip8.mem.stack().len() == depth
+            })
+        } else {
+            self.step(chip8, keypad)
+        }
+    }
+
+    /// Runs until the program counter reaches `address`, at least one instruction
+    pub fn run_to(
+        &mut self,
+        chip8: &mut Chip8,
+        keypad: &[bool; 16],
+        address: u16,
+        limit: u64,
+    ) -> Result<Stop, EmulatorError> {
+        self.run_until(chip8, keypad, limit, |chip8| {
+            chip8.cpu.program_counter() == address
+        })
+    }
+
+    /// Runs until a breakpoint, the program halting or `limit` instructions
+    pub fn run(
+        &mut self,
+        chip8: &mut Chip8,
+        keypad: &[bool; 16],
+        limit: u64,
+    ) -> Result<Stop, EmulatorError> {
+        self.run_until(chip8, keypad, limit, |_| false)
+    }
+
+    fn run_until(
+        &mut self,
+        chip8: &mut Chip8,
+        keypad: &[bool; 16],
+        limit: u64,
+        done: impl Fn(&Chip8) -> bool,
+    ) -> Result<Stop, EmulatorError> {
+        for _ in 0..limit {
+            if let Some(stop) = self.check_stop(chip8) {
+                return Ok(stop);
+            }
+            self.execute(chip8, keypad)?;
+            if let Some(stop) = self.triggered.take() {
+                self.stop();
+                return Ok(stop);
+            }
+            if done(chip8) {
+                self.stop();
+                return Ok(Stop::Done);
+            }
+        }
+
+        self.stop();
+        Ok(Stop::Limit)
+    }
+
+    // Stops and pauses on a watchpoint, at a breakpoint or once the program halted
+    fn check_stop(&mut self, chip8: &Chip8) -> Option<Stop> {
+        let program_counter = chip8.cpu.program_counter();
+        let breakpoint = match self.breakpoints.get(&program_counter) {
+            Some(Some(condition)) => condition.evaluate(chip8),
+            Some(None) => true,
+            None => false,
+        };
+        let stop = if let Some(stop) = self.triggered.take() {
+            stop
+        } else if chip8.halted() {
+            Stop::Halted
+        } else if !self.resuming && breakpoint {
+            Stop::Breakpoint(program_counter)
+        } else {
+            return None;
+        };
+        self.stop();
+        Some(stop)
+    }
+
+    // Runs one instruction and ends the frame where `Chip8::run_frame` would. Returns true
+    // at the end of a frame.
+    fn execute(&mut self, chip8: &mut Chip8, keypad: &[bool; 16]) -> Result<bool, EmulatorError> {
+        let address = chip8.cpu.program_counter();
+        let registers: Vec<(Register, u16)> = self
+            .watched_registers
+            .iter()
+            .map(|&register| (register, register.value(chip8)))
+            .collect();
+        // forget accesses made outside of the debugger
+        chip8.mem.take_watch_hit();
+
+        if let Err(e) = chip8.run_cycle(keypad) {
+            self.stop();
+            return Err(e);
+        }
+        self.resuming = false;
+
+        if let Some(hit) = chip8.mem.take_watch_hit() {
+            self.triggered = Some(Stop::Watchpoint { address, hit });
+        } else if let Some(&(register, old)) = registers
+            .iter()
+            .find(|&&(register, old)| register.value(chip8) != old)
+        {
+            self.triggered = Some(Stop::RegisterChanged {
+                address,
+                register,
+                old,
+                new: register.value(chip8),
+            });
+        }
+        self.cycle += 1;
+
+        if self.cycle >= chip8.instructions_per_frame()
+            || chip8.cpu.waiting_for_vblank()
+            || chip8.halted()
+        {
+            chip8.tick_timers();
+            self.cycle = 0;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn stop(&mut self) {
+        self.paused = true;
+        self.resuming = true;
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+fn test_machine(program: &[u8]) -> Chip8 {
+    let mut chip8 = Chip8::new(crate::quirks::Quirks::SCHIP_1_1);
+    chip8.load_program(program).unwrap();
+    chip8
+}
+
+#[test]
+fn test_step() {
+    // V0 := 1, V1 := 2
+    let mut chip8 = test_machine(&[0x60, 0x01, 0x61, 0x02]);
+    let mut debugger = Debugger::new();
+    let keypad = [false; 16];
+
+    assert_eq!(Ok(Stop::Done), debugger.step(&mut chip8, &keypad));
+    assert!(debugger.paused());
+    assert_eq!(0x202, chip8.cpu.program_counter());
+    assert_eq!(1, chip8.cpu.registers()[0]);
+    assert_eq!(0, chip8.cpu.registers()[1]);
+    assert_eq!(1, debugger.cycle());
+}
+
+#[test]
+fn test_breakpoints() {
+    // V0 += 1, loop
+    let mut chip8 = test_machine(&[0x70, 0x01, 0x12, 0x00]);
+    let mut debugger = Debugger::new();
+    let keypad = [false; 16];
+
+    assert!(debugger.add_breakpoint(0x202));
+    assert!(!debugger.add_breakpoint(0x202));
+    assert_eq!(
+        Ok(Some(Stop::Breakpoint(0x202))),
+        debugger.run_frame(&mut chip8, &keypad)
+    );
+    assert!(debugger.paused());
+    assert_eq!(1, chip8.cpu.registers()[0]);
+
+    // continuing runs the instruction at the breakpoint before stopping there again
+    debugger.resume();
+    assert_eq!(
+        Ok(Some(Stop::Breakpoint(0x202))),
+        debugger.run_frame(&mut chip8, &keypad)
+    );
+    assert_eq!(2, chip8.cpu.registers()[0]);
+
+    assert!(debugger.remove_breakpoint(0x202));
+    assert_eq!(0, debugger.breakpoints().count());
+    debugger.resume();
+    assert_eq!(Ok(None), debugger.run_frame(&mut chip8, &keypad));
+}
+
+#[test]
+fn test_run_frame_matches_chip8() {
+    // V0 += 1, delay := V0, loop
+    let program = [0x70, 0x01, 0xF0, 0x15, 0x12, 0x00];
+    let mut chip8 = test_machine(&program);
+    let mut expected = test_machine(&program);
+    let mut debugger = Debugger::new();
+    let keypad = [false; 16];
+
+    for _ in 0..3 {
+        assert_eq!(Ok(None), debugger.run_frame(&mut chip8, &keypad));
+        expected.run_frame(&keypad).unwrap();
+    }
+
+    assert_eq!(expected.save_state(), chip8.save_state());
+    assert_eq!(0, debugger.cycle());
+}
+
+#[test]
+fn test_step_over() {
+    // call 0x206, V1 := 1, exit, V0 := 5, return
+    let mut chip8 = test_machine(&[0x22, 0x06, 0x61, 0x01, 0x00, 0xFD, 0x60, 0x05, 0x00, 0xEE]);
+    let mut debugger = Debugger::new();
+    let keypad = [false; 16];
+
+    assert_eq!(Ok(Stop::Done), debugger.step_over(&mut chip8, &keypad, 100));
+    assert_eq!(0x202, chip8.cpu.program_counter());
+    assert_eq!(5, chip8.cpu.registers()[0]);
+    assert!(chip8.mem.stack().is_empty());
+
+    assert_eq!(Ok(Stop::Done), debugger.step_over(&mut chip8, &keypad, 100));
+    assert_eq!(0x204, chip8.cpu.program_counter());
+    assert_eq!(Ok(Stop::Done), debugger.step(&mut chip8, &keypad));
+    assert_eq!(Ok(Stop::Halted), debugger.step(&mut chip8, &keypad));
+}
+
+#[test]
+fn test_run_to_and_limit() {
+    // V0 += 1, loop
+    let mut chip8 = test_machine(&[0x70, 0x01, 0x12, 0x00]);
+    let mut debugger = Debugger::new();
+    let keypad = [false; 16];
+
+    assert_eq!(
+        Ok(Stop::Done),
+        debugger.run_to(&mut chip8, &keypad, 0x202, 100)
+    );
+    assert_eq!(0x202, chip8.cpu.program_counter());
+    assert_eq!(
+        Ok(Stop::Limit),
+        debugger.run_to(&mut chip8, &keypad, 0x300, 100)
+    );
+    assert_eq!(Ok(Stop::Limit), debugger.run(&mut chip8, &keypad, 10));
+}
+
+#[test]
+fn test_error_pauses() {
+    let mut chip8 = test_machine(&[0xFF, 0xFF]);
+    let mut debugger = Debugger::new();
+
+    assert!(debugger.run_frame(&mut chip8, &[false; 16]).is_err());
+    assert!(debugger.paused());
+}
+
+#[test]
+fn test_conditional_breakpoint() {
+    // V0 += 1, loop
+    let mut chip8 = test_machine(&[0x70, 0x01, 0x12, 0x00]);
+    let mut debugger = Debugger::new();
+    let keypad = [false; 16];
+
+    let condition = crate::condition::parse_condition("V0 == 3").unwrap();
+    assert!(debugger.add_conditional_breakpoint(0x200, condition.clone()));
+    assert_eq!(Some(&condition), debugger.breakpoint_condition(0x200));
+    assert_eq!(
+        Ok(Stop::Breakpoint(0x200)),
+        debugger.run(&mut chip8, &keypad, 100)
+    );
+    assert_eq!(3, chip8.cpu.registers()[0]);
+}
+
+#[test]
+fn test_memory_watchpoint() {
+    use crate::mem::{Access, Watchpoint};
+
+    // I := 0x300, V0 := 7, save v0, exit
+    let mut chip8 = test_machine(&[0xA3, 0x00, 0x60, 0x07, 0xF0, 0x55, 0x00, 0xFD]);
+    chip8.mem.add_watchpoint(Watchpoint {
+        range: 0x300..=0x3FF,
+        access: Access::Write,
+    });
+    let mut debugger = Debugger::new();
+    let keypad = [false; 16];
+
+    let hit = WatchHit {
+        address: 0x300,
+        access: Access::Write,
+        value: 7,
+    };
+    assert_eq!(
+        Ok(Some(Stop::Watchpoint {
+            address: 0x204,
+            hit
+        })),
+        debugger.run_frame(&mut chip8, &keypad)
+    );
+    assert_eq!(0x206, chip8.cpu.program_counter());
+    debugger.resume();
+    assert_eq!(Ok(None), debugger.run_frame(&mut chip8, &keypad));
+    assert!(chip8.halted());
+}
+
+#[test]
+fn test_register_watch() {
+    // V0 := 0, V1 := 2, V0 := 5
+    let mut chip8 = test_machine(&[0x60, 0x00, 0x61, 0x02, 0x60, 0x05]);
+    let mut debugger = Debugger::new();
+    let keypad = [false; 16];
+
+    assert!(debugger.watch_register(Register::V(0)));
+    assert_eq!(Ok(Stop::Done), debugger.step(&mut chip8, &keypad));
+    assert_eq!(
+        Ok(Stop::RegisterChanged {
+            address: 0x204,
+            register: Register::V(0),
+            old: 0,
+            new: 5
+        }),
+        debugger.run(&mut chip8, &keypad, 100)
+    );
+    assert!(debugger.unwatch_register(Register::V(0)));
+    assert_eq!(0, debugger.watched_registers().count());
+}
@@ -0,0 +1,498 @@
+/*
+    The disassembler walks the code reachable from 0x200, following jumps, calls and both
+    branches of every skip, and treats every byte it does not reach as data. Targets of
+    jumps and calls get a label, as do addresses loaded into I, whose data is listed a byte
+    per line with its pixels since it is most likely a sprite.
+
+    Code only reached through a jump table, Bnnn with anything but the first entry, or
+    through self-modifying code is listed as data.
+*/
+use crate::instruction::{decode, Instruction};
+use crate::mem::PROGRAM_MEMORY_START;
+use std::collections::BTreeMap;
+
+/// Most data bytes listed on one line
+const DATA_BYTES_PER_LINE: usize = 8;
+/// Width of the instruction column, the comments are aligned after it
+const COLUMN_WIDTH: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// Octo assembly, which assembles back into the same program
+    Octo,
+    /// The mnemonics of Cowgod's technical reference
+    Cowgod,
+}
+
+/// What a label marks. An address reached in several ways is named after the highest ranked
+/// kind, in the order Data < Jump < Subroutine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LabelKind {
+    /// Loaded into I, a sprite or other data
+    Data,
+    /// Target of a jump
+    Jump,
+    /// Target of a call
+    Subroutine,
+}
+
+/// A program split into the instructions reachable from its start and data
+pub struct Disassembly {
+    program: Vec<u8>,
+    instructions: BTreeMap<u16, Instruction>,
+    labels: BTreeMap<u16, LabelKind>,
+}
+
+/// Disassembles a program loaded at 0x200
+pub fn disassemble(program: &[u8]) -> Disassembly {
+    let start = PROGRAM_MEMORY_START as u16;
+    let mut disassembly = Disassembly {
+        program: program.to_vec(),
+        instructions: BTreeMap::new(),
+        labels: BTreeMap::new(),
+    };
+    if program.is_empty() {
+        return disassembly;
+    }
+    disassembly.labels.insert(start, LabelKind::Jump);
+    let mut pending = vec![start];
+
+    while let Some(address) = pending.pop() {
+        if disassembly.instructions.contains_key(&address) {
+            continue;
+        }
+        let instruction = match disassembly.decode_at(address) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        disassembly.instructions.insert(address, instruction);
+        let next = address.wrapping_add(instruction.size());
+
+        match instruction {
+            Instruction::Jp(target) | Instruction::JpV0(target) => {
+                disassembly.branch(target, LabelKind::Jump, &mut pending);
+            }
+            Instruction::Call(target) => {
+                disassembly.branch(target, LabelKind::Subroutine, &mut pending);
+                pending.push(next);
+            }
+            Instruction::Ret | Instruction::Exit => {}
+            Instruction::SeByte { .. }
+            | Instruction::SneByte { .. }
+            | Instruction::SeReg { .. }
+            | Instruction::SneReg { .. }
+            | Instruction::Skp { .. }
+            | Instruction::Sknp { .. } => {
+                let skipped = disassembly.decode_at(next).map_or(2, Instruction::size);
+                pending.push(next.wrapping_add(skipped));
+                pending.push(next);
+            }
+            Instruction::LdI(target) => {
+                disassembly.mark_data(target);
+                pending.push(next);
+            }
+            Instruction::LdILong => {
+                disassembly.mark_data(disassembly.word(address.wrapping_add(2)).unwrap_or(0));
+                pending.push(next);
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    disassembly
+}
+
+impl Disassembly {
+    /// The instruction at `address` if it was reached as code
+    pub fn instruction(&self, address: u16) -> Option<Instruction> {
+        self.instructions.get(&address).copied()
+    }
+
+    /// All instructions reached as code, by address
+    pub fn instructions(&self) -> impl Iterator<Item = (u16, Instruction)> + '_ {
+        self.instructions
+            .iter()
+            .map(|(&address, &instruction)| (address, instruction))
+    }
+
+    /// The name of the label at `address`, if any
+    pub fn label(&self, address: u16) -> Option<String> {
+        self.labels
+            .get(&address)
+            .map(|&kind| label_name(address, kind))
+    }
+
+    /// Lists the program, labels on a line of their own and every line commented with its
+    /// address
+    pub fn to_text(&self, syntax: Syntax) -> String {
+        let start = PROGRAM_MEMORY_START;
+        let comment = match syntax {
+            Syntax::Octo => '#',
+            Syntax::Cowgod => ';',
+        };
+        let name = |address: u16| match self.label(address) {
+            Some(label) => label,
+            None => format!("0x{:03X}", address),
+        };
+        let mut output = String::new();
+        let mut sprite = false;
+        let mut offset = 0;
+
+        while offset < self.program.len() {
+            let address = (start + offset) as u16;
+            if let Some(&kind) = self.labels.get(&address) {
+                let label = label_name(address, kind);
+                output += &match syntax {
+                    Syntax::Octo => format!(": {}\n", label),
+                    Syntax::Cowgod => format!("{}:\n", label),
+                };
+                sprite = kind == LabelKind::Data;
+            }
+
+            if let Some(&instruction) = self.instructions.get(&address) {
+                let size = instruction.size() as usize;
+                let long = self.word(address.wrapping_add(2)).unwrap_or(0);
+                let text = format_with(instruction, long, syntax, &name);
+                let bytes: Vec<String> = self.program[offset..offset + size]
+                    .chunks(2)
+                    .map(|word| format!("{:02x}{:02x}", word[0], word[1]))
+                    .collect();
+                output += &line(&text, comment, address, &bytes.join(" "));
+                offset += size;
+                sprite = false;
+                continue;
+            }
+
+            let limit = if sprite { 1 } else { DATA_BYTES_PER_LINE };
+            let mut count = 1;
+            while count < limit && offset + count < self.program.len() {
+                let next = address + count as u16;
+                if self.instructions.contains_key(&next) || self.labels.contains_key(&next) {
+                    break;
+                }
+                count += 1;
+            }
+            let data = &self.program[offset..offset + count];
+            let bytes: Vec<String> = data.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+            let text = match syntax {
+                Syntax::Octo => bytes.join(" "),
+                Syntax::Cowgod => format!("DB {}", bytes.join(", ")),
+            };
+            let pixels = if sprite {
+                format!("{:08b}", data[0])
+                    .replace('0', ".")
+                    .replace('1', "#")
+            } else {
+                String::new()
+            };
+            output += &line(&text, comment, address, &pixels);
+            offset += count;
+        }
+
+        output
+    }
+
+    fn contains(&self, address: u16) -> bool {
+        (address as usize)
+            .checked_sub(PROGRAM_MEMORY_START)
+            .is_some_and(|offset| offset < self.program.len())
+    }
+
+    fn word(&self, address: u16) -> Option<u16> {
+        let offset = (address as usize).checked_sub(PROGRAM_MEMORY_START)?;
+        let bytes = self.program.get(offset..offset + 2)?;
+        Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+    }
+
+    // An instruction that lies entirely within the program
+    fn decode_at(&self, address: u16) -> Option<Instruction> {
+        let instruction = decode(self.word(address)?).ok()?;
+        if instruction.size() == 4 {
+            self.word(address.wrapping_add(2))?;
+        }
+        Some(instruction)
+    }
+
+    fn branch(&mut self, target: u16, kind: LabelKind, pending: &mut Vec<u16>) {
+        if self.contains(target) {
+            self.label_as(target, kind);
+            pending.push(target);
+        }
+    }
+
+    fn mark_data(&mut self, target: u16) {
+        if self.contains(target) {
+            self.label_as(target, LabelKind::Data);
+        }
+    }
+
+    fn label_as(&mut self, address: u16, kind: LabelKind) {
+        let label = self.labels.entry(address).or_insert(kind);
+        *label = (*label).max(kind);
+    }
+}
+
+fn label_name(address: u16, kind: LabelKind) -> String {
+    if address as usize == PROGRAM_MEMORY_START {
+        return "main".to_string();
+    }
+    let prefix = match kind {
+        LabelKind::Data => "data",
+        LabelKind::Jump => "label",
+        LabelKind::Subroutine => "sub",
+    };
+    format!("{}_{:03x}", prefix, address)
+}
+
+fn line(text: &str, comment: char, address: u16, detail: &str) -> String {
+    let line = format!(
+        "  {:width$} {} {:03x}: {}",
+        text,
+        comment,
+        address,
+        detail,
+        width = COLUMN_WIDTH
+    );
+    format!("{}\n", line.trim_end().trim_end_matches(':'))
+}
+
+/// Formats an instruction with numeric addresses. The address of F000 follows it in memory
+/// and is passed in `long`.
+pub fn format_instruction(instruction: Instruction, long: u16, syntax: Syntax) -> String {
+    format_with(instruction, long, syntax, &|address| {
+        format!("0x{:03X}", address)
+    })
+}
+
+fn format_with(
+    instruction: Instruction,
+    long: u16,
+    syntax: Syntax,
+    name: &dyn Fn(u16) -> String,
+) -> String {
+    match syntax {
+        Syntax::Octo => octo(instruction, long, name),
+        Syntax::Cowgod => cowgod(instruction, long, name),
+    }
+}
+
+fn octo(instruction: Instruction, long: u16, name: &dyn Fn(u16) -> String) -> String {
+    let v = |register: u8| format!("v{:x}", register);
+    match instruction {
+        Instruction::ScrollDown(n) => format!("scroll-down {}", n),
+        Instruction::ScrollUp(n) => format!("scroll-up {}", n),
+        Instruction::Cls => "clear".to_string(),
+        Instruction::Ret => "return".to_string(),
+        Instruction::ScrollRight => "scroll-right".to_string(),
+        Instruction::ScrollLeft => "scroll-left".to_string(),
+        Instruction::Exit => "exit".to_string(),
+        Instruction::Low => "lores".to_string(),
+        Instruction::High => "hires".to_string(),
+        Instruction::Jp(address) => format!("jump {}", name(address)),
+        Instruction::Call(address) => format!(":call {}", name(address)),
+        Instruction::SeByte { x, byte } => format!("if {} != 0x{:02X} then", v(x), byte),
+        Instruction::SneByte { x, byte } => format!("if {} == 0x{:02X} then", v(x), byte),
+        Instruction::SeReg { x, y } => format!("if {} != {} then", v(x), v(y)),
+        Instruction::SaveRange { x, y } => format!("save {} - {}", v(x), v(y)),
+        Instruction::LoadRange { x, y } => format!("load {} - {}", v(x), v(y)),
+        Instruction::LdByte { x, byte } => format!("{} := 0x{:02X}", v(x), byte),
+        Instruction::AddByte { x, byte } => format!("{} += 0x{:02X}", v(x), byte),
+        Instruction::LdReg { x, y } => format!("{} := {}", v(x), v(y)),
+        Instruction::Or { x, y } => format!("{} |= {}", v(x), v(y)),
+        Instruction::And { x, y } => format!("{} &= {}", v(x), v(y)),
+        Instruction::Xor { x, y } => format!("{} ^= {}", v(x), v(y)),
+        Instruction::AddReg { x, y } => format!("{} += {}", v(x), v(y)),
+        Instruction::Sub { x, y } => format!("{} -= {}", v(x), v(y)),
+        Instruction::Shr { x, y } => format!("{} >>= {}", v(x), v(y)),
+        Instruction::Subn { x, y } => format!("{} =- {}", v(x), v(y)),
+        Instruction::Shl { x, y } => format!("{} <<= {}", v(x), v(y)),
+        Instruction::SneReg { x, y } => format!("if {} == {} then", v(x), v(y)),
+        Instruction::LdI(address) => format!("i := {}", name(address)),
+        Instruction::JpV0(address) => format!("jump0 {}", name(address)),
+        Instruction::Rnd { x, byte } => format!("{} := random 0x{:02X}", v(x), byte),
+        Instruction::Drw { x, y, n } => format!("sprite {} {} {}", v(x), v(y), n),
+        Instruction::Skp { x } => format!("if {} -key then", v(x)),
+        Instruction::Sknp { x } => format!("if {} key then", v(x)),
+        Instruction::LdILong => format!("i := long {}", name(long)),
+        Instruction::Plane(n) => format!("plane {}", n),
+        Instruction::Audio => "audio".to_string(),
+        Instruction::LdVxDt { x } => format!("{} := delay", v(x)),
+        Instruction::LdVxK { x } => format!("{} := key", v(x)),
+        Instruction::LdDtVx { x } => format!("delay := {}", v(x)),
+        Instruction::LdStVx { x } => format!("buzzer := {}", v(x)),
+        Instruction::AddI { x } => format!("i += {}", v(x)),
+        Instruction::LdF { x } => format!("i := hex {}", v(x)),
+        Instruction::LdHf { x } => format!("i := bighex {}", v(x)),
+        Instruction::LdB { x } => format!("bcd {}", v(x)),
+        Instruction::Pitch { x } => format!("pitch := {}", v(x)),
+        Instruction::LdIVx { x } => format!("save {}", v(x)),
+        Instruction::LdVxI { x } => format!("load {}", v(x)),
+        Instruction::LdRVx { x } => format!("saveflags {}", v(x)),
+        Instruction::LdVxR { x } => format!("loadflags {}", v(x)),
+    }
+}
+
+fn cowgod(instruction: Instruction, long: u16, name: &dyn Fn(u16) -> String) -> String {
+    let v = |register: u8| format!("V{:X}", register);
+    match instruction {
+        Instruction::ScrollDown(n) => format!("SCD {}", n),
+        Instruction::ScrollUp(n) => format!("SCU {}", n),
+        Instruction::Cls => "CLS".to_string(),
+        Instruction::Ret => "RET".to_string(),
+        Instruction::ScrollRight => "SCR".to_string(),
+        Instruction::ScrollLeft => "SCL".to_string(),
+        Instruction::Exit => "EXIT".to_string(),
+        Instruction::Low => "LOW".to_string(),
+        Instruction::High => "HIGH".to_string(),
+        Instruction::Jp(address) => format!("JP {}", name(address)),
+        Instruction::Call(address) => format!("CALL {}", name(address)),
+        Instruction::SeByte { x, byte } => format!("SE {}, 0x{:02X}", v(x), byte),
+        Instruction::SneByte { x, byte } => format!("SNE {}, 0x{:02X}", v(x), byte),
+        Instruction::SeReg { x, y } => format!("SE {}, {}", v(x), v(y)),
+        Instruction::SaveRange { x, y } => format!("SAVE {} - {}", v(x), v(y)),
+        Instruction::LoadRange { x, y } => format!("LOAD {} - {}", v(x), v(y)),
+        Instruction::LdByte { x, byte } => format!("LD {}, 0x{:02X}", v(x), byte),
+        Instruction::AddByte { x, byte } => format!("ADD {}, 0x{:02X}", v(x), byte),
+        Instruction::LdReg { x, y } => format!("LD {}, {}", v(x), v(y)),
+        Instruction::Or { x, y } => format!("OR {}, {}", v(x), v(y)),
+        Instruction::And { x, y } => format!("AND {}, {}", v(x), v(y)),
+        Instruction::Xor { x, y } => format!("XOR {}, {}", v(x), v(y)),
+        Instruction::AddReg { x, y } => format!("ADD {}, {}", v(x), v(y)),
+        Instruction::Sub { x, y } => format!("SUB {}, {}", v(x), v(y)),
+        Instruction::Shr { x, y } => format!("SHR {}, {}", v(x), v(y)),
+        Instruction::Subn { x, y } => format!("SUBN {}, {}", v(x), v(y)),
+        Instruction::Shl { x, y } => format!("SHL {}, {}", v(x), v(y)),
+        Instruction::SneReg { x, y } => format!("SNE {}, {}", v(x), v(y)),
+        Instruction::LdI(address) => format!("LD I, {}", name(address)),
+        Instruction::JpV0(address) => format!("JP V0, {}", name(address)),
+        Instruction::Rnd { x, byte } => format!("RND {}, 0x{:02X}", v(x), byte),
+        Instruction::Drw { x, y, n } => format!("DRW {}, {}, {}", v(x), v(y), n),
+        Instruction::Skp { x } => format!("SKP {}", v(x)),
+        Instruction::Sknp { x } => format!("SKNP {}", v(x)),
+        Instruction::LdILong => format!("LD I, LONG {}", name(long)),
+        Instruction::Plane(n) => format!("PLANE {}", n),
+        Instruction::Audio => "AUDIO".to_string(),
+        Instruction::LdVxDt { x } => format!("LD {}, DT", v(x)),
+        Instruction::LdVxK { x } => format!("LD {}, K", v(x)),
+        Instruction::LdDtVx { x } => format!("LD DT, {}", v(x)),
+        Instruction::LdStVx { x } => format!("LD ST, {}", v(x)),
+        Instruction::AddI { x } => format!("ADD I, {}", v(x)),
+        Instruction::LdF { x } => format!("LD F, {}", v(x)),
+        Instruction::LdHf { x } => format!("LD HF, {}", v(x)),
+        Instruction::LdB { x } => format!("LD B, {}", v(x)),
+        Instruction::Pitch { x } => format!("LD PITCH, {}", v(x)),
+        Instruction::LdIVx { x } => format!("LD [I], {}", v(x)),
+        Instruction::LdVxI { x } => format!("LD {}, [I]", v(x)),
+        Instruction::LdRVx { x } => format!("LD R, {}", v(x)),
+        Instruction::LdVxR { x } => format!("LD {}, R", v(x)),
+    }
+}
+
+#[cfg(test)]
+const TEST_PROGRAM: [u8; 20] = [
+    0x00, 0xE0, // 200: clear
+    0xA2, 0x10, // 202: i := 210
+    0x22, 0x0C, // 204: call 20c
+    0x3F, 0x01, // 206: skip if vf == 1
+    0x12, 0x06, // 208: jump 206
+    0x00, 0xFD, // 20a: exit
+    0xD0, 0x12, // 20c: sprite v0 v1 2
+    0x00, 0xEE, // 20e: return
+    0x3C, 0x42, // 210: sprite
+    0xFF, 0xFF, // 212: unreachable
+];
+
+#[test]
+fn test_disassemble_reachability() {
+    let disassembly = disassemble(&TEST_PROGRAM);
+
+    assert_eq!(Some(Instruction::Cls), disassembly.instruction(0x200));
+    assert_eq!(Some(Instruction::Exit), disassembly.instruction(0x20A));
+    assert_eq!(Some(Instruction::Ret), disassembly.instruction(0x20E));
+    assert_eq!(None, disassembly.instruction(0x210));
+    assert_eq!(None, disassembly.instruction(0x212));
+    assert_eq!(8, disassembly.instructions().count());
+
+    assert_eq!(Some("main".to_string()), disassembly.label(0x200));
+    assert_eq!(Some("label_206".to_string()), disassembly.label(0x206));
+    assert_eq!(Some("sub_20c".to_string()), disassembly.label(0x20C));
+    assert_eq!(Some("data_210".to_string()), disassembly.label(0x210));
+    assert_eq!(None, disassembly.label(0x212));
+}
+
+#[test]
+fn test_disassemble_octo() {
+    let text = disassemble(&TEST_PROGRAM).to_text(Syntax::Octo);
+    let lines: Vec<&str> = text.lines().collect();
+
+    assert_eq!(": main", lines[0]);
+    assert_eq!("  clear                    # 200: 00e0", lines[1]);
+    assert_eq!("  i := data_210            # 202: a210", lines[2]);
+    assert_eq!("  :call sub_20c            # 204: 220c", lines[3]);
+    assert_eq!(": label_206", lines[4]);
+    assert_eq!("  if vf != 0x01 then       # 206: 3f01", lines[5]);
+    assert_eq!("  jump label_206           # 208: 1206", lines[6]);
+    assert_eq!("  exit                     # 20a: 00fd", lines[7]);
+    assert_eq!(": sub_20c", lines[8]);
+    assert_eq!("  sprite v0 v1 2           # 20c: d012", lines[9]);
+    assert_eq!("  return                   # 20e: 00ee", lines[10]);
+    assert_eq!(": data_210", lines[11]);
+    assert_eq!("  0x3C                     # 210: ..####..", lines[12]);
+    assert_eq!("  0x42                     # 211: .#....#.", lines[13]);
+    assert_eq!("  0xFF                     # 212: ########", lines[14]);
+    assert_eq!("  0xFF                     # 213: ########", lines[15]);
+    assert_eq!(16, lines.len());
+}
+
+#[test]
+fn test_disassemble_cowgod() {
+    let text = disassemble(&TEST_PROGRAM).to_text(Syntax::Cowgod);
+    let lines: Vec<&str> = text.lines().collect();
+
+    assert_eq!("main:", lines[0]);
+    assert_eq!("  LD I, data_210           ; 202: a210", lines[2]);
+    assert_eq!("  CALL sub_20c             ; 204: 220c", lines[3]);
+    assert_eq!("  SE VF, 0x01              ; 206: 3f01", lines[5]);
+    assert_eq!("  DB 0x3C                  ; 210: ..####..", lines[12]);
+}
+
+#[test]
+fn test_disassemble_data() {
+    // the jump skips over data that is not referenced
+    let text =
+        disassemble(&[0x12, 0x0C, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 0x12, 0x0C]).to_text(Syntax::Octo);
+    let lines: Vec<&str> = text.lines().collect();
+
+    assert_eq!("  0x01 0x02 0x03 0x04 0x05 0x06 0x07 0x08 # 202", lines[2]);
+    assert_eq!("  0x09 0x0A                # 20a", lines[3]);
+    assert_eq!(": label_20c", lines[4]);
+}
+
+#[test]
+fn test_disassemble_skips_long_instruction() {
+    // the skip steps over all four bytes of F000 NNNN
+    let disassembly = disassemble(&[0x30, 0x00, 0xF0, 0x00, 0x03, 0x00, 0x00, 0xFD]);
+
+    assert_eq!(Some(Instruction::LdILong), disassembly.instruction(0x202));
+    assert_eq!(None, disassembly.instruction(0x204));
+    assert_eq!(Some(Instruction::Exit), disassembly.instruction(0x206));
+    assert!(disassembly
+        .to_text(Syntax::Octo)
+        .contains("  i := long 0x300          # 202: f000 0300\n"));
+}
+
+#[test]
+fn test_format_instruction() {
+    assert_eq!(
+        "vf := random 0xFF",
+        format_instruction(Instruction::Rnd { x: 15, byte: 0xFF }, 0, Syntax::Octo)
+    );
+    assert_eq!(
+        "JP V0, 0x300",
+        format_instruction(Instruction::JpV0(0x300), 0, Syntax::Cowgod)
+    );
+    assert_eq!(
+        "i := long 0x1234",
+        format_instruction(Instruction::LdILong, 0x1234, Syntax::Octo)
+    );
+}
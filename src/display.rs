@@ -0,0 +1,440 @@
+/*
+    The CHIP-8 display is a 64x32 monochrome plane, (0, 0) is the top left corner.
+    SUPER-CHIP adds a 128x64 high resolution mode and XO-CHIP a second plane, giving four
+    colours. Pixels are drawn by XOR-ing sprites onto the selected planes.
+*/
+use crate::error::StateError;
+use crate::state::{self, StateReader, StateWriter};
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+/// Bit of a pixel value belonging to each plane
+pub const PLANE_1: u8 = 0b01;
+pub const PLANE_2: u8 = 0b10;
+const ALL_PLANES: u8 = PLANE_1 | PLANE_2;
+
+/// Colours used when converting the display to a pixel buffer, given as 0xRRGGBB
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub background: u32,
+    /// Pixels set in the first plane only, the only colour used by CHIP-8 and SUPER-CHIP
+    pub foreground: u32,
+    /// Pixels set in the second plane only
+    pub plane_2: u32,
+    /// Pixels set in both planes
+    pub blend: u32,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            background: 0x000000,
+            foreground: 0x00FF00,
+            plane_2: 0xFF8000,
+            blend: 0xFFFFFF,
+        }
+    }
+}
+
+impl Palette {
+    fn color(&self, pixel: u8) -> u32 {
+        match pixel & ALL_PLANES {
+            PLANE_1 => self.foreground,
+            PLANE_2 => self.plane_2,
+            ALL_PLANES => self.blend,
+            _ => self.background,
+        }
+    }
+}
+
+pub struct Display {
+    pixels: [u8; HIRES_WIDTH * HIRES_HEIGHT],
+    hires: bool,
+    selected_planes: u8,
+}
+
+impl Display {
+    pub fn new() -> Display {
+        Display {
+            pixels: [0; HIRES_WIDTH * HIRES_HEIGHT],
+            hires: false,
+            selected_planes: PLANE_1,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            HEIGHT
+        }
+    }
+
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Switches between the 64x32 and 128x64 resolutions, clearing all planes
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.pixels = [0; HIRES_WIDTH * HIRES_HEIGHT];
+    }
+
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    /// Selects the planes drawn, cleared and scrolled by later operations as a mask of
+    /// `PLANE_1` and `PLANE_2`
+    pub fn select_planes(&mut self, planes: u8) {
+        self.selected_planes = planes & ALL_PLANES;
+    }
+
+    /// Returns true if the pixel at x, y is set in any plane
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.pixel(x, y) != 0
+    }
+
+    /// Returns the planes set at x, y as a mask of `PLANE_1` and `PLANE_2`
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[x + y * self.width()]
+    }
+
+    /// Sets or clears the pixel at x, y in the selected planes
+    pub fn set(&mut self, x: usize, y: usize, value: bool) {
+        let width = self.width();
+        let planes = self.selected_planes;
+        let pixel = &mut self.pixels[x + y * width];
+        if value {
+            *pixel |= planes;
+        } else {
+            *pixel &= !planes;
+        }
+    }
+
+    /// Flips the pixel at x, y in the selected planes and returns true if it was turned
+    /// off, i.e. a collision
+    pub fn toggle(&mut self, x: usize, y: usize) -> bool {
+        self.toggle_plane(x, y, self.selected_planes)
+    }
+
+    /// Flips the pixel at x, y in the given planes and returns true if it was turned off
+    /// in any of them
+    pub fn toggle_plane(&mut self, x: usize, y: usize, planes: u8) -> bool {
+        let width = self.width();
+        let pixel = &mut self.pixels[x + y * width];
+        let collision = *pixel & planes != 0;
+        *pixel ^= planes;
+        collision
+    }
+
+    /// Clears the selected planes
+    pub fn clear(&mut self) {
+        let planes = self.selected_planes;
+        for pixel in self.pixels.iter_mut() {
+            *pixel &= !planes;
+        }
+    }
+
+    /// Moves the picture `rows` pixels down, the rows scrolled in at the top are blank
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.scroll(0, rows as isize);
+    }
+
+    /// Moves the picture `rows` pixels up
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.scroll(0, -(rows as isize));
+    }
+
+    /// Moves the picture `columns` pixels to the right
+    pub fn scroll_right(&mut self, columns: usize) {
+        self.scroll(columns as isize, 0);
+    }
+
+    /// Moves the picture `columns` pixels to the left
+    pub fn scroll_left(&mut self, columns: usize) {
+        self.scroll(-(columns as isize), 0);
+    }
+
+    // only the selected planes are scrolled
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let planes = self.selected_planes;
+        let mut scrolled = self.pixels;
+
+        for y in 0..height {
+            for x in 0..width {
+                let (source_x, source_y) = (x - dx, y - dy);
+                let source = if (0..width).contains(&source_x) && (0..height).contains(&source_y) {
+                    self.pixels[(source_x + source_y * width) as usize]
+                } else {
+                    0
+                };
+                let pixel = &mut scrolled[(x + y * width) as usize];
+                *pixel = (*pixel & !planes) | (source & planes);
+            }
+        }
+
+        self.pixels = scrolled;
+    }
+
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.hires);
+        writer.u8(self.selected_planes);
+        writer.bytes(&self.pixels);
+    }
+
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.hires = reader.bool()?;
+        self.selected_planes = reader.u8()? & ALL_PLANES;
+        reader.read_into(&mut self.pixels)?;
+        Ok(())
+    }
+
+    fn visible_pixels(&self) -> &[u8] {
+        &self.pixels[..self.width() * self.height()]
+    }
+
+    /// Renders the display as text, one line per row: `.` for blank pixels, `#` for the first
+    /// plane, `o` for the second and `@` for both
+    pub fn to_text(&self) -> String {
+        let mut text = String::with_capacity((self.width() + 1) * self.height());
+        for row in self.visible_pixels().chunks(self.width()) {
+            text.extend(row.iter().map(|&pixel| match pixel & ALL_PLANES {
+                PLANE_1 => '#',
+                PLANE_2 => 'o',
+                ALL_PLANES => '@',
+                _ => '.',
+            }));
+            text.push('\n');
+        }
+        text
+    }
+
+    /// FNV-1a hash of the resolution and visible pixels, for comparing frames in tests
+    pub fn hash(&self) -> u64 {
+        let mut data = vec![self.hires as u8];
+        data.extend_from_slice(self.visible_pixels());
+        state::fnv1a(&data)
+    }
+
+    /// Converts the display to RGBA bytes, four per pixel, row by row, at the current
+    /// resolution
+    pub fn to_rgba(&self, palette: &Palette) -> Vec<u8> {
+        self.visible_pixels()
+            .iter()
+            .flat_map(|&pixel| {
+                let color = palette.color(pixel);
+                [(color >> 16) as u8, (color >> 8) as u8, color as u8, 0xFF]
+            })
+            .collect()
+    }
+
+    /// Converts the display to one 0xAARRGGBB value per pixel, row by row, the format
+    /// used by minifb and most software framebuffers
+    pub fn to_argb(&self, palette: &Palette) -> Vec<u32> {
+        self.visible_pixels()
+            .iter()
+            .map(|&pixel| 0xFF00_0000 | palette.color(pixel))
+            .collect()
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_get_and_set() {
+    let mut display = Display::new();
+    display.set(3, 1, true);
+
+    assert!(display.get(3, 1));
+    assert_eq!(PLANE_1, display.pixels[67]);
+}
+
+#[test]
+fn test_toggle() {
+    let mut display = Display::new();
+
+    assert!(!display.toggle(63, 31));
+    assert!(display.get(63, 31));
+    assert!(display.toggle(63, 31));
+    assert!(!display.get(63, 31));
+}
+
+#[test]
+fn test_clear() {
+    let mut display = Display::new();
+    display.set(10, 10, true);
+    display.clear();
+
+    assert!(!display.get(10, 10));
+}
+
+#[test]
+fn test_to_rgba() {
+    let mut display = Display::new();
+    let palette = Palette {
+        background: 0x102030,
+        foreground: 0xA0B0C0,
+        ..Palette::default()
+    };
+    display.set(1, 0, true);
+
+    let rgba = display.to_rgba(&palette);
+
+    assert_eq!(WIDTH * HEIGHT * 4, rgba.len());
+    assert_eq!(&[0x10, 0x20, 0x30, 0xFF], &rgba[0..4]);
+    assert_eq!(&[0xA0, 0xB0, 0xC0, 0xFF], &rgba[4..8]);
+}
+
+#[test]
+fn test_to_argb() {
+    let mut display = Display::new();
+    display.set(0, 1, true);
+
+    let argb = display.to_argb(&Palette::default());
+
+    assert_eq!(WIDTH * HEIGHT, argb.len());
+    assert_eq!(0xFF000000, argb[0]);
+    assert_eq!(0xFF00FF00, argb[WIDTH]);
+}
+
+#[test]
+fn test_set_hires() {
+    let mut display = Display::new();
+    display.set(1, 1, true);
+    display.set_hires(true);
+
+    assert_eq!(HIRES_WIDTH, display.width());
+    assert_eq!(HIRES_HEIGHT, display.height());
+    assert!(!display.get(1, 1));
+
+    display.set(127, 63, true);
+    assert!(display.get(127, 63));
+    assert_eq!(
+        HIRES_WIDTH * HIRES_HEIGHT,
+        display.to_argb(&Palette::default()).len()
+    );
+}
+
+#[test]
+fn test_scroll_down() {
+    let mut display = Display::new();
+    display.set(5, 0, true);
+    display.set(5, 31, true);
+    display.scroll_down(3);
+
+    assert!(!display.get(5, 0));
+    assert!(display.get(5, 3));
+    assert!(!display.get(5, 2));
+}
+
+#[test]
+fn test_scroll_left_and_right() {
+    let mut display = Display::new();
+    display.set(0, 4, true);
+    display.set(10, 4, true);
+
+    display.scroll_right(4);
+    assert!(display.get(4, 4));
+    assert!(display.get(14, 4));
+    assert!(!display.get(0, 4));
+
+    display.scroll_left(4);
+    display.scroll_left(4);
+    assert!(!display.get(0, 4));
+    assert!(display.get(6, 4));
+}
+
+#[test]
+fn test_planes() {
+    let mut display = Display::new();
+    display.set(0, 0, true);
+    display.select_planes(PLANE_2);
+    display.set(1, 0, true);
+    display.select_planes(PLANE_1 | PLANE_2);
+    display.set(2, 0, true);
+
+    assert_eq!(PLANE_1, display.pixel(0, 0));
+    assert_eq!(PLANE_2, display.pixel(1, 0));
+    assert_eq!(PLANE_1 | PLANE_2, display.pixel(2, 0));
+
+    let argb = display.to_argb(&Palette::default());
+    let palette = Palette::default();
+    assert_eq!(0xFF000000 | palette.foreground, argb[0]);
+    assert_eq!(0xFF000000 | palette.plane_2, argb[1]);
+    assert_eq!(0xFF000000 | palette.blend, argb[2]);
+    assert_eq!(0xFF000000 | palette.background, argb[3]);
+}
+
+#[test]
+fn test_toggle_plane() {
+    let mut display = Display::new();
+
+    assert!(!display.toggle_plane(4, 4, PLANE_2));
+    assert!(!display.toggle_plane(4, 4, PLANE_1));
+    assert!(display.toggle_plane(4, 4, PLANE_2));
+    assert_eq!(PLANE_1, display.pixel(4, 4));
+}
+
+#[test]
+fn test_clear_and_scroll_selected_planes() {
+    let mut display = Display::new();
+    display.select_planes(PLANE_1 | PLANE_2);
+    display.set(5, 5, true);
+
+    display.select_planes(PLANE_2);
+    display.scroll_up(2);
+    assert_eq!(PLANE_1, display.pixel(5, 5));
+    assert_eq!(PLANE_2, display.pixel(5, 3));
+
+    display.clear();
+    assert_eq!(PLANE_1, display.pixel(5, 5));
+    assert_eq!(0, display.pixel(5, 3));
+}
+
+#[test]
+fn test_to_text() {
+    let mut display = Display::new();
+    display.set(0, 0, true);
+    display.select_planes(PLANE_2);
+    display.set(1, 0, true);
+    display.select_planes(PLANE_1 | PLANE_2);
+    display.set(2, 31, true);
+
+    let text = display.to_text();
+    let lines: Vec<&str> = text.lines().collect();
+
+    assert_eq!(HEIGHT, lines.len());
+    assert!(lines[0].starts_with("#o.."));
+    assert_eq!(WIDTH, lines[0].len());
+    assert!(lines[31].starts_with("..@."));
+}
+
+#[test]
+fn test_hash() {
+    let mut display = Display::new();
+    let blank = display.hash();
+    display.set(4, 4, true);
+
+    assert_ne!(blank, display.hash());
+    display.set(4, 4, false);
+    assert_eq!(blank, display.hash());
+    display.set_hires(true);
+    assert_ne!(blank, display.hash());
+}
@@ -0,0 +1,165 @@
+use std::error::Error;
+use std::fmt;
+
+/// Errors raised while executing a program. The emulator is left in the state it was in
+/// before the failing instruction, so the host can decide whether to halt, skip the
+/// instruction or reset the machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmulatorError {
+    /// The opcode fetched from `address` is not a known instruction
+    InvalidOpcode { opcode: u16, address: u16 },
+    /// A subroutine call was made with a full stack
+    StackOverflow,
+    /// A return was made with an empty stack
+    StackUnderflow,
+    /// An access was made outside of the addressable memory
+    MemoryOutOfBounds { address: usize },
+    /// A write was made to the memory reserved for the interpreter
+    ProtectedWrite { address: usize },
+    /// A register outside of V0-VF was addressed
+    InvalidRegister { register: usize },
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulatorError::InvalidOpcode { opcode, address } => {
+                write!(f, "invalid opcode {:04x} at {:03x}", opcode, address)
+            }
+            EmulatorError::StackOverflow => write!(f, "stack overflow"),
+            EmulatorError::StackUnderflow => write!(f, "stack underflow"),
+            EmulatorError::MemoryOutOfBounds { address } => {
+                write!(f, "memory access out of bounds at {:04x}", address)
+            }
+            EmulatorError::ProtectedWrite { address } => {
+                write!(f, "write to protected memory at {:03x}", address)
+            }
+            EmulatorError::InvalidRegister { register } => {
+                write!(f, "invalid register V{}", register)
+            }
+        }
+    }
+}
+
+impl Error for EmulatorError {}
+
+/// An opcode that does not decode to any instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub opcode: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown opcode {:04x}", self.opcode)
+    }
+}
+
+impl Error for DecodeError {}
+
+/// An error in a program passed to the assembler
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblyError {
+    /// Line of the source the error was found on, starting at 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AssemblyError {}
+
+/// A breakpoint condition that could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConditionError {
+    pub message: String,
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid condition: {}", self.message)
+    }
+}
+
+impl Error for ConditionError {}
+
+/// Errors raised while restoring a save state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    /// The data does not start with the save state signature
+    InvalidMagic,
+    /// The save state was written by an incompatible version of the emulator
+    UnsupportedVersion { version: u16 },
+    /// The save state was made while running a different ROM
+    RomMismatch { expected: u64, found: u64 },
+    /// The save state is shorter than its contents require
+    Truncated,
+    /// The save state was made with a different memory size, i.e. another quirk profile
+    MemorySizeMismatch { expected: usize, found: usize },
+    /// The save state holds a value the machine cannot be in
+    Corrupt,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::InvalidMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion { version } => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::RomMismatch { expected, found } => write!(
+                f,
+                "save state is for another ROM (hash {:016x}, expected {:016x})",
+                found, expected
+            ),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::MemorySizeMismatch { expected, found } => write!(
+                f,
+                "save state has {} bytes of memory, expected {}",
+                found, expected
+            ),
+            StateError::Corrupt => write!(f, "save state is corrupt"),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+/// Errors raised while reading or starting the playback of an input movie
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieError {
+    /// The data does not start with the movie signature
+    InvalidMagic,
+    /// The movie was written by an incompatible version of the emulator
+    UnsupportedVersion { version: u16 },
+    /// The movie was recorded with a different ROM
+    RomMismatch { expected: u64, found: u64 },
+    /// The movie was recorded with different quirks
+    QuirksMismatch,
+    /// The movie is shorter than its header says
+    Truncated,
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::InvalidMagic => write!(f, "not a movie"),
+            MovieError::UnsupportedVersion { version } => {
+                write!(f, "unsupported movie version {}", version)
+            }
+            MovieError::RomMismatch { expected, found } => write!(
+                f,
+                "movie was recorded with another ROM (hash {:016x}, expected {:016x})",
+                found, expected
+            ),
+            MovieError::QuirksMismatch => write!(f, "movie was recorded with other quirks"),
+            MovieError::Truncated => write!(f, "movie is truncated"),
+        }
+    }
+}
+
+impl Error for MovieError {}
@@ -0,0 +1,606 @@
+/*
+    A stub for the GDB remote serial protocol, so debuggers that speak it can attach to a
+    `Chip8` over TCP with `target remote localhost:PORT`.
+
+    The registers are V0-VF, I, PC and SP, the number of return addresses on the stack, in
+    that order. I and PC are two bytes and the others one, sent big-endian like the words in
+    CHIP-8 memory. Debuggers get the layout from target.xml through qXfer.
+
+    The host runs the program a frame at a time, and the stub answers packets between frames:
+    `poll` handles the packets received since the last call, then `run_frame` runs a frame
+    while the debugger has the program continuing.
+
+    https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+*/
+use crate::chip8::Chip8;
+use crate::debugger::{Debugger, Stop};
+use crate::error::EmulatorError;
+use crate::mem::{Access, Watchpoint};
+use std::convert::TryFrom;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+
+const INTERRUPT: u8 = 0x03;
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+/// Sizes of V0-VF, I, PC and SP in bytes
+const REGISTER_SIZES: [usize; 19] = [1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 1];
+const PACKET_SIZE: usize = 0x1000;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.knocket.chip8">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+enum Packet {
+    Command(String),
+    Interrupt,
+}
+
+/// Serves one debugger connection
+pub struct GdbStub {
+    stream: TcpStream,
+    debugger: Debugger,
+    /// Bytes received that do not make a whole packet yet
+    received: Vec<u8>,
+    /// Whether packets are acknowledged, until the debugger asks for no-ack mode
+    acknowledge: bool,
+    connected: bool,
+}
+
+impl GdbStub {
+    /// Serves the debugger connected to `stream`, with the program paused
+    pub fn new(stream: TcpStream) -> GdbStub {
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        GdbStub {
+            stream,
+            debugger,
+            received: Vec::new(),
+            acknowledge: true,
+            connected: true,
+        }
+    }
+
+    /// Handles the packets received since the last call without waiting for more. Returns
+    /// false once the debugger detached, killed the program or disconnected.
+    pub fn poll(&mut self, chip8: &mut Chip8, keypad: &[bool; 16]) -> io::Result<bool> {
+        self.receive()?;
+        while let Some(packet) = self.next_packet()? {
+            match packet {
+                Packet::Interrupt if !self.debugger.paused() => {
+                    self.debugger.pause();
+                    self.send(&format!("S{:02x}", SIGINT))?;
+                }
+                Packet::Interrupt => {}
+                Packet::Command(command) => {
+                    if let Some(reply) = self.reply(&command, chip8, keypad) {
+                        self.send(&reply)?;
+                    }
+                    if command == "QStartNoAckMode" {
+                        self.acknowledge = false;
+                    }
+                }
+            }
+        }
+        Ok(self.connected)
+    }
+
+    /// Runs the rest of the frame while the program is continuing, returns true when the
+    /// frame completed
+    pub fn run_frame(&mut self, chip8: &mut Chip8, keypad: &[bool; 16]) -> io::Result<bool> {
+        if self.debugger.paused() {
+            return Ok(false);
+        }
+        let reply = match self.debugger.run_frame(chip8, keypad) {
+            Ok(None) => return Ok(true),
+            Ok(Some(stop)) => stop_reply(stop),
+            Err(e) => error_reply(e),
+        };
+        self.send(&reply)?;
+        Ok(false)
+    }
+
+    /// Call after the machine jumped to a state saved between frames
+    pub fn restart_frame(&mut self) {
+        self.debugger.restart_frame();
+    }
+
+    fn receive(&mut self) -> io::Result<()> {
+        self.stream.set_nonblocking(true)?;
+        let mut buffer = [0; 1024];
+        let result = loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.connected = false;
+                    break Ok(());
+                }
+                Ok(count) => self.received.extend_from_slice(&buffer[..count]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result
+    }
+
+    // Takes the next whole packet out of the received bytes, skipping acknowledgements and
+    // asking for packets with a bad checksum to be sent again
+    fn next_packet(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            match self.received.first() {
+                None => return Ok(None),
+                Some(&INTERRUPT) => {
+                    self.received.remove(0);
+                    return Ok(Some(Packet::Interrupt));
+                }
+                Some(&b'$') => {}
+                Some(_) => {
+                    self.received.remove(0);
+                    continue;
+                }
+            }
+
+            let end = match self.received.iter().position(|&byte| byte == b'#') {
+                Some(end) if end + 3 <= self.received.len() => end,
+                _ => return Ok(None),
+            };
+            let data = String::from_utf8_lossy(&self.received[1..end]).into_owned();
+            let checksum = std::str::from_utf8(&self.received[end + 1..end + 3])
+                .ok()
+                .and_then(|text| u8::from_str_radix(text, 16).ok());
+            self.received.drain(..end + 3);
+
+            if checksum == Some(checksum_of(data.as_bytes())) {
+                if self.acknowledge {
+                    self.stream.write_all(b"+")?;
+                }
+                return Ok(Some(Packet::Command(data)));
+            } else if self.acknowledge {
+                self.stream.write_all(b"-")?;
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())
+    }
+
+    // The reply to a packet, `None` for continue which replies once the program stops
+    fn reply(&mut self, packet: &str, chip8: &mut Chip8, keypad: &[bool; 16]) -> Option<String> {
+        let arguments = packet.get(1..).unwrap_or("");
+        let reply = match packet.get(..1).unwrap_or("") {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => encode(&registers(chip8)),
+            "G" => status(write_registers(chip8, arguments)),
+            "p" => read_register(chip8, arguments).unwrap_or_else(|| "E01".to_string()),
+            "P" => status(write_register(chip8, arguments)),
+            "m" => read_memory(chip8, arguments).unwrap_or_else(|| "E01".to_string()),
+            "M" => status(write_memory(chip8, arguments)),
+            "Z" => status(self.breakpoint(chip8, arguments, true)),
+            "z" => status(self.breakpoint(chip8, arguments, false)),
+            "s" => match resume_at(chip8, arguments) {
+                Some(()) => match self.debugger.step(chip8, keypad) {
+                    Ok(stop) => stop_reply(stop),
+                    Err(e) => error_reply(e),
+                },
+                None => "E01".to_string(),
+            },
+            "c" => match resume_at(chip8, arguments) {
+                Some(()) => {
+                    self.debugger.resume();
+                    return None;
+                }
+                None => "E01".to_string(),
+            },
+            "D" => {
+                self.connected = false;
+                "OK".to_string()
+            }
+            "k" => {
+                self.connected = false;
+                return None;
+            }
+            "H" => "OK".to_string(),
+            "q" | "Q" => query(packet),
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    // Z0 and Z1 add breakpoints and Z2, Z3 and Z4 write, read and access watchpoints, z
+    // removes them
+    fn breakpoint(&mut self, chip8: &mut Chip8, arguments: &str, insert: bool) -> Option<()> {
+        let mut fields = arguments.split(',');
+        let kind = fields.next()?;
+        let address = hex(fields.next()?)?;
+        let length = hex(fields.next()?)?.max(1);
+
+        let access = match kind {
+            "0" | "1" if insert => {
+                self.debugger.add_breakpoint(u16::try_from(address).ok()?);
+                return Some(());
+            }
+            "0" | "1" => {
+                return self
+                    .debugger
+                    .remove_breakpoint(u16::try_from(address).ok()?)
+                    .then_some(())
+            }
+            "2" => Access::Write,
+            "3" => Access::Read,
+            "4" => Access::ReadWrite,
+            _ => return None,
+        };
+        let watchpoint = Watchpoint {
+            range: address..=address.checked_add(length - 1)?,
+            access,
+        };
+        if insert {
+            chip8.mem.add_watchpoint(watchpoint);
+            Some(())
+        } else {
+            chip8.mem.remove_watchpoint(&watchpoint).then_some(())
+        }
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// fails on an odd number of digits, as the last pair is cut short
+fn decode(text: &str) -> Option<Vec<u8>> {
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn status(result: Option<()>) -> String {
+    match result {
+        Some(()) => "OK".to_string(),
+        None => "E01".to_string(),
+    }
+}
+
+// `s` and `c` can give the address to resume at, which must be in memory
+fn resume_at(chip8: &mut Chip8, arguments: &str) -> Option<()> {
+    if arguments.is_empty() {
+        return Some(());
+    }
+    let address = hex(arguments)?;
+    if address >= chip8.mem.size() {
+        return None;
+    }
+    chip8.cpu.set_program_counter(address as u16);
+    Some(())
+}
+
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        Stop::Halted => "W00".to_string(),
+        Stop::Watchpoint { hit, .. } => {
+            let kind = match hit.access {
+                Access::Read => "rwatch",
+                _ => "watch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, kind, hit.address)
+        }
+        _ => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+fn error_reply(error: EmulatorError) -> String {
+    match error {
+        EmulatorError::InvalidOpcode { .. } => format!("S{:02x}", SIGILL),
+        _ => format!("S{:02x}", SIGSEGV),
+    }
+}
+
+fn registers(chip8: &Chip8) -> Vec<u8> {
+    let mut bytes = chip8.cpu.registers().to_vec();
+    bytes.extend_from_slice(&chip8.cpu.index().to_be_bytes());
+    bytes.extend_from_slice(&chip8.cpu.program_counter().to_be_bytes());
+    bytes.push(chip8.mem.stack().len() as u8);
+    bytes
+}
+
+fn read_register(chip8: &Chip8, arguments: &str) -> Option<String> {
+    let register = hex(arguments)?;
+    let size = REGISTER_SIZES.get(register)?;
+    let offset: usize = REGISTER_SIZES[..register].iter().sum();
+    Some(encode(&registers(chip8)[offset..offset + size]))
+}
+
+fn write_register(chip8: &mut Chip8, arguments: &str) -> Option<()> {
+    let (register, value) = arguments.split_once('=')?;
+    set_register(chip8, hex(register)?, &decode(value)?)
+}
+
+fn write_registers(chip8: &mut Chip8, arguments: &str) -> Option<()> {
+    let bytes = decode(arguments)?;
+    if bytes.len() != REGISTER_SIZES.iter().sum::<usize>() {
+        return None;
+    }
+    let (values, rest) = bytes.split_at(16);
+    let index = u16::from_be_bytes([rest[0], rest[1]]);
+    let program_counter = u16::from_be_bytes([rest[2], rest[3]]);
+    // the stack pointer is the only value that can be rejected, so it goes first and a bad
+    // one leaves every register untouched
+    chip8.mem.set_stack_len(rest[4] as usize).ok()?;
+    for (register, &value) in values.iter().enumerate() {
+        chip8.cpu.set_register_value(register, value).ok()?;
+    }
+    chip8.cpu.set_index(index);
+    chip8.cpu.set_program_counter(program_counter);
+    Some(())
+}
+
+fn set_register(chip8: &mut Chip8, register: usize, bytes: &[u8]) -> Option<()> {
+    if REGISTER_SIZES.get(register) != Some(&bytes.len()) {
+        return None;
+    }
+    match register {
+        0..=15 => chip8.cpu.set_register_value(register, bytes[0]).ok()?,
+        16 => chip8
+            .cpu
+            .set_index(u16::from_be_bytes([bytes[0], bytes[1]])),
+        17 => chip8
+            .cpu
+            .set_program_counter(u16::from_be_bytes([bytes[0], bytes[1]])),
+        _ => chip8.mem.set_stack_len(bytes[0] as usize).ok()?,
+    }
+    Some(())
+}
+
+fn read_memory(chip8: &Chip8, arguments: &str) -> Option<String> {
+    let (address, length) = arguments.split_once(',')?;
+    let address = hex(address)?;
+    let end = address.checked_add(hex(length)?)?;
+    Some(encode(chip8.mem.memory().get(address..end)?))
+}
+
+fn write_memory(chip8: &mut Chip8, arguments: &str) -> Option<()> {
+    let (range, data) = arguments.split_once(':')?;
+    let (address, length) = range.split_once(',')?;
+    let address = hex(address)?;
+    let bytes = decode(data)?;
+    if bytes.len() != hex(length)? {
+        return None;
+    }
+    let end = address.checked_add(bytes.len())?;
+    chip8
+        .mem
+        .memory_mut()
+        .get_mut(address..end)?
+        .copy_from_slice(&bytes);
+    Some(())
+}
+
+fn query(packet: &str) -> String {
+    if packet.starts_with("qSupported") {
+        return format!(
+            "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+",
+            PACKET_SIZE
+        );
+    }
+    if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        return target_xml(range).unwrap_or_else(|| "E01".to_string());
+    }
+    match packet {
+        "QStartNoAckMode" => "OK",
+        "qAttached" => "1",
+        "qC" => "QC1",
+        "qfThreadInfo" => "m1",
+        "qsThreadInfo" => "l",
+        _ => "",
+    }
+    .to_string()
+}
+
+// `length` bytes of target.xml from `offset`, starting with l when they are the last
+fn target_xml(range: &str) -> Option<String> {
+    let (offset, length) = range.split_once(',')?;
+    let start = hex(offset)?.min(TARGET_XML.len());
+    let end = start.saturating_add(hex(length)?).min(TARGET_XML.len());
+    let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+    Some(format!("{}{}", marker, &TARGET_XML[start..end]))
+}
+
+#[cfg(test)]
+struct TestClient {
+    stream: TcpStream,
+    stub: GdbStub,
+    chip8: Chip8,
+}
+
+#[cfg(test)]
+impl TestClient {
+    fn connect(program: &[u8]) -> TestClient {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_millis(10)))
+            .unwrap();
+        let mut chip8 = Chip8::new(crate::quirks::Quirks::SCHIP_1_1);
+        chip8.load_program(program).unwrap();
+        TestClient {
+            stream,
+            stub: GdbStub::new(server),
+            chip8,
+        }
+    }
+
+    fn send(&mut self, packet: &str) {
+        let packet = format!("${}#{:02x}", packet, checksum_of(packet.as_bytes()));
+        self.stream.write_all(packet.as_bytes()).unwrap();
+    }
+
+    // Polls the stub, running frames while the program continues, until a reply arrives
+    fn receive(&mut self) -> String {
+        let mut received = Vec::new();
+        for _ in 0..1000 {
+            assert!(self.stub.poll(&mut self.chip8, &[false; 16]).unwrap());
+            self.stub.run_frame(&mut self.chip8, &[false; 16]).unwrap();
+
+            let mut buffer = [0; 1024];
+            if let Ok(count) = self.stream.read(&mut buffer) {
+                received.extend_from_slice(&buffer[..count]);
+            }
+            let text = String::from_utf8_lossy(&received).into_owned();
+            let packet = text.trim_start_matches('+');
+            if let Some(end) = packet.find('#') {
+                if packet.len() >= end + 3 {
+                    assert_eq!(
+                        format!("{:02x}", checksum_of(&packet.as_bytes()[1..end])),
+                        packet[end + 1..end + 3]
+                    );
+                    return packet[1..end].to_string();
+                }
+            }
+        }
+        panic!("no reply from the stub");
+    }
+
+    fn exchange(&mut self, packet: &str) -> String {
+        self.send(packet);
+        self.receive()
+    }
+}
+
+#[test]
+fn test_gdb_registers_and_memory() {
+    // V3 := 0x42, I := 0x300
+    let mut client = TestClient::connect(&[0x63, 0x42, 0xA3, 0x00]);
+
+    assert_eq!("S05", client.exchange("?"));
+    assert_eq!("OK", client.exchange("P3=42"));
+    assert_eq!("OK", client.exchange("P10=0300"));
+    assert_eq!(
+        "000000420000000000000000000000000300020000",
+        client.exchange("g")
+    );
+    assert_eq!("0200", client.exchange("p11"));
+    assert_eq!("E01", client.exchange("p13"));
+
+    assert_eq!("63", client.exchange("m200,1"));
+    assert_eq!("OK", client.exchange("M300,2:beef"));
+    assert_eq!("beef00", client.exchange("m300,3"));
+    assert_eq!("E01", client.exchange("mfff,2"));
+
+    let registers = "0102030405060708090a0b0c0d0e0f10034502040000";
+    assert_eq!("E01", client.exchange(&format!("G{}", registers)));
+    // a stack pointer above the stack size rejects the whole packet
+    assert_eq!("E01", client.exchange(&format!("G{}11", &registers[..40])));
+    assert_eq!(0x42, client.chip8.cpu.registers()[3]);
+    assert_eq!(0x300, client.chip8.cpu.index());
+    assert_eq!("OK", client.exchange(&format!("G{}", &registers[..42])));
+    assert_eq!(16, client.chip8.cpu.registers()[0xF]);
+    assert_eq!(0x345, client.chip8.cpu.index());
+    assert_eq!(0x204, client.chip8.cpu.program_counter());
+}
+
+#[test]
+fn test_gdb_breakpoints_and_stepping() {
+    // V0 += 1, V1 += 2, loop
+    let mut client = TestClient::connect(&[0x70, 0x01, 0x71, 0x02, 0x12, 0x00]);
+
+    assert_eq!("S05", client.exchange("s"));
+    assert_eq!(0x202, client.chip8.cpu.program_counter());
+    assert_eq!("OK", client.exchange("Z0,204,2"));
+    assert_eq!("S05", client.exchange("c"));
+    assert_eq!(0x204, client.chip8.cpu.program_counter());
+    assert_eq!("S05", client.exchange("c"));
+    assert_eq!(2, client.chip8.cpu.registers()[0]);
+
+    assert_eq!("OK", client.exchange("z0,204,2"));
+    assert_eq!("E01", client.exchange("z0,204,2"));
+    assert_eq!("E01", client.exchange("s10200"));
+    assert_eq!("E01", client.exchange("c10200"));
+    assert_eq!(0x204, client.chip8.cpu.program_counter());
+    assert_eq!("S05", client.exchange("s200"));
+    assert_eq!(0x202, client.chip8.cpu.program_counter());
+    client.send("c");
+    client.stream.write_all(&[INTERRUPT]).unwrap();
+    assert_eq!("S02", client.receive());
+}
+
+#[test]
+fn test_gdb_watchpoints() {
+    // I := 0x300, V0 := 7, save v0, exit
+    let mut client = TestClient::connect(&[0xA3, 0x00, 0x60, 0x07, 0xF0, 0x55, 0x00, 0xFD]);
+
+    assert_eq!("OK", client.exchange("Z2,300,1"));
+    assert_eq!("T05watch:300;", client.exchange("c"));
+    assert_eq!("OK", client.exchange("z2,300,1"));
+    assert_eq!("E01", client.exchange(&format!("Z2,{:x},2", usize::MAX)));
+    assert_eq!("E01", client.exchange("Z0,10200,2"));
+    assert!(client.chip8.mem.watchpoints().is_empty());
+    assert_eq!("W00", client.exchange("c"));
+}
+
+#[test]
+fn test_gdb_queries_and_checksums() {
+    let mut client = TestClient::connect(&[0x00, 0xE0]);
+
+    assert!(client
+        .exchange("qSupported:xmlRegisters=i386")
+        .contains("qXfer:features:read+"));
+    let start = client.exchange("qXfer:features:read:target.xml:0,a");
+    assert_eq!("m<?xml vers", start);
+    let end = client.exchange(&format!(
+        "qXfer:features:read:target.xml:{:x},100",
+        TARGET_XML.len() - 10
+    ));
+    assert_eq!("l</target>\n", end);
+    assert_eq!("", client.exchange("vMustReplyEmpty"));
+
+    client.stream.write_all(b"$g#00").unwrap();
+    let mut buffer = [0; 1];
+    client.stub.poll(&mut client.chip8, &[false; 16]).unwrap();
+    client.stream.read_exact(&mut buffer).unwrap();
+    assert_eq!(b"-", &buffer);
+
+    assert_eq!("OK", client.exchange("QStartNoAckMode"));
+    client.send("qAttached");
+    client.stub.poll(&mut client.chip8, &[false; 16]).unwrap();
+    let mut reply = [0; 5];
+    client.stream.read_exact(&mut reply).unwrap();
+    assert_eq!(b"$1#31", &reply);
+}
@@ -0,0 +1,442 @@
+/*
+    Opcodes are decoded into an `Instruction` before they are executed. Decoding only looks at
+    the opcode, whether an instruction is available depends on the quirks and is checked when
+    it is executed. Variants are named after the mnemonics in Cowgod's technical reference,
+    the SCHIP and XO-CHIP extensions after their Octo statements.
+
+    x and y are register indices, n is a nibble, byte an 8 bit immediate and addresses 12 bits.
+
+    http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
+    http://johnearnest.github.io/Octo/docs/XO-ChipSpecification.html
+*/
+use crate::error::DecodeError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 00Cn, scroll the display down n rows
+    ScrollDown(u8),
+    /// 00Dn, XO-CHIP: scroll the display up n rows
+    ScrollUp(u8),
+    /// 00E0, clear the display
+    Cls,
+    /// 00EE, return from a subroutine
+    Ret,
+    /// 00FB, scroll the display right 4 pixels
+    ScrollRight,
+    /// 00FC, scroll the display left 4 pixels
+    ScrollLeft,
+    /// 00FD, exit the interpreter
+    Exit,
+    /// 00FE, switch to low resolution
+    Low,
+    /// 00FF, switch to high resolution
+    High,
+    /// 1nnn, jump to nnn
+    Jp(u16),
+    /// 2nnn, call the subroutine at nnn
+    Call(u16),
+    /// 3xkk, skip the next instruction if Vx == kk
+    SeByte { x: u8, byte: u8 },
+    /// 4xkk, skip the next instruction if Vx != kk
+    SneByte { x: u8, byte: u8 },
+    /// 5xy0, skip the next instruction if Vx == Vy
+    SeReg { x: u8, y: u8 },
+    /// 5xy2, XO-CHIP: store Vx to Vy at I without changing I
+    SaveRange { x: u8, y: u8 },
+    /// 5xy3, XO-CHIP: load Vx to Vy from I without changing I
+    LoadRange { x: u8, y: u8 },
+    /// 6xkk, Vx = kk
+    LdByte { x: u8, byte: u8 },
+    /// 7xkk, Vx = Vx + kk, VF is not changed
+    AddByte { x: u8, byte: u8 },
+    /// 8xy0, Vx = Vy
+    LdReg { x: u8, y: u8 },
+    /// 8xy1, Vx = Vx OR Vy
+    Or { x: u8, y: u8 },
+    /// 8xy2, Vx = Vx AND Vy
+    And { x: u8, y: u8 },
+    /// 8xy3, Vx = Vx XOR Vy
+    Xor { x: u8, y: u8 },
+    /// 8xy4, Vx = Vx + Vy, VF is the carry
+    AddReg { x: u8, y: u8 },
+    /// 8xy5, Vx = Vx - Vy, VF is 1 when there is no borrow
+    Sub { x: u8, y: u8 },
+    /// 8xy6, Vx = Vx >> 1, or Vy >> 1 with the shift quirk, VF is the bit shifted out
+    Shr { x: u8, y: u8 },
+    /// 8xy7, Vx = Vy - Vx, VF is 1 when there is no borrow
+    Subn { x: u8, y: u8 },
+    /// 8xyE, Vx = Vx << 1, or Vy << 1 with the shift quirk, VF is the bit shifted out
+    Shl { x: u8, y: u8 },
+    /// 9xy0, skip the next instruction if Vx != Vy
+    SneReg { x: u8, y: u8 },
+    /// Annn, I = nnn
+    LdI(u16),
+    /// Bnnn, jump to nnn + V0, or nnn + Vx with the jump quirk where x is the top nibble
+    JpV0(u16),
+    /// Cxkk, Vx = random byte AND kk
+    Rnd { x: u8, byte: u8 },
+    /// Dxyn, draw the n rows high sprite at I at Vx, Vy, n = 0 draws a 16x16 sprite
+    Drw { x: u8, y: u8, n: u8 },
+    /// Ex9E, skip the next instruction if the key in Vx is pressed
+    Skp { x: u8 },
+    /// ExA1, skip the next instruction if the key in Vx is not pressed
+    Sknp { x: u8 },
+    /// F000 nnnn, XO-CHIP: I = the 16 bit address in the following two bytes
+    LdILong,
+    /// Fn01, XO-CHIP: select the drawing planes in n
+    Plane(u8),
+    /// F002, XO-CHIP: load the audio pattern from I
+    Audio,
+    /// Fx07, Vx = delay timer
+    LdVxDt { x: u8 },
+    /// Fx0A, wait for a key press and store it in Vx
+    LdVxK { x: u8 },
+    /// Fx15, delay timer = Vx
+    LdDtVx { x: u8 },
+    /// Fx18, sound timer = Vx
+    LdStVx { x: u8 },
+    /// Fx1E, I = I + Vx
+    AddI { x: u8 },
+    /// Fx29, I = address of the small font digit in Vx
+    LdF { x: u8 },
+    /// Fx30, I = address of the big font digit in Vx
+    LdHf { x: u8 },
+    /// Fx33, store the decimal digits of Vx at I, I + 1 and I + 2
+    LdB { x: u8 },
+    /// Fx3A, XO-CHIP: pitch = Vx
+    Pitch { x: u8 },
+    /// Fx55, store V0 to Vx at I
+    LdIVx { x: u8 },
+    /// Fx65, load V0 to Vx from I
+    LdVxI { x: u8 },
+    /// Fx75, store V0 to Vx in the RPL user flags
+    LdRVx { x: u8 },
+    /// Fx85, load V0 to Vx from the RPL user flags
+    LdVxR { x: u8 },
+}
+
+/// Decodes an opcode, regardless of the quirks it is executed with
+pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+    let x = (opcode >> 8 & 0x0F) as u8;
+    let y = (opcode >> 4 & 0x0F) as u8;
+    let n = (opcode & 0x0F) as u8;
+    let byte = (opcode & 0xFF) as u8;
+    let address = opcode & 0x0FFF;
+
+    let instruction = match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00C0..=0x00CF => Instruction::ScrollDown(n),
+            0x00D0..=0x00DF => Instruction::ScrollUp(n),
+            0x00E0 => Instruction::Cls,
+            0x00EE => Instruction::Ret,
+            0x00FB => Instruction::ScrollRight,
+            0x00FC => Instruction::ScrollLeft,
+            0x00FD => Instruction::Exit,
+            0x00FE => Instruction::Low,
+            0x00FF => Instruction::High,
+            _ => return Err(DecodeError { opcode }),
+        },
+        0x1000 => Instruction::Jp(address),
+        0x2000 => Instruction::Call(address),
+        0x3000 => Instruction::SeByte { x, byte },
+        0x4000 => Instruction::SneByte { x, byte },
+        0x5000 => match n {
+            0x0 => Instruction::SeReg { x, y },
+            0x2 => Instruction::SaveRange { x, y },
+            0x3 => Instruction::LoadRange { x, y },
+            _ => return Err(DecodeError { opcode }),
+        },
+        0x6000 => Instruction::LdByte { x, byte },
+        0x7000 => Instruction::AddByte { x, byte },
+        0x8000 => match n {
+            0x0 => Instruction::LdReg { x, y },
+            0x1 => Instruction::Or { x, y },
+            0x2 => Instruction::And { x, y },
+            0x3 => Instruction::Xor { x, y },
+            0x4 => Instruction::AddReg { x, y },
+            0x5 => Instruction::Sub { x, y },
+            0x6 => Instruction::Shr { x, y },
+            0x7 => Instruction::Subn { x, y },
+            0xE => Instruction::Shl { x, y },
+            _ => return Err(DecodeError { opcode }),
+        },
+        0x9000 if n == 0 => Instruction::SneReg { x, y },
+        0xA000 => Instruction::LdI(address),
+        0xB000 => Instruction::JpV0(address),
+        0xC000 => Instruction::Rnd { x, byte },
+        0xD000 => Instruction::Drw { x, y, n },
+        0xE000 => match byte {
+            0x9E => Instruction::Skp { x },
+            0xA1 => Instruction::Sknp { x },
+            _ => return Err(DecodeError { opcode }),
+        },
+        0xF000 => match byte {
+            0x00 if x == 0 => Instruction::LdILong,
+            0x01 => Instruction::Plane(x),
+            0x02 if x == 0 => Instruction::Audio,
+            0x07 => Instruction::LdVxDt { x },
+            0x0A => Instruction::LdVxK { x },
+            0x15 => Instruction::LdDtVx { x },
+            0x18 => Instruction::LdStVx { x },
+            0x1E => Instruction::AddI { x },
+            0x29 => Instruction::LdF { x },
+            0x30 => Instruction::LdHf { x },
+            0x33 => Instruction::LdB { x },
+            0x3A => Instruction::Pitch { x },
+            0x55 => Instruction::LdIVx { x },
+            0x65 => Instruction::LdVxI { x },
+            0x75 => Instruction::LdRVx { x },
+            0x85 => Instruction::LdVxR { x },
+            _ => return Err(DecodeError { opcode }),
+        },
+        _ => return Err(DecodeError { opcode }),
+    };
+
+    Ok(instruction)
+}
+
+fn xy(prefix: u16, x: u8, y: u8, n: u16) -> u16 {
+    prefix | (x as u16 & 0x0F) << 8 | (y as u16 & 0x0F) << 4 | n
+}
+
+fn xkk(prefix: u16, x: u8, byte: u8) -> u16 {
+    prefix | (x as u16 & 0x0F) << 8 | byte as u16
+}
+
+impl Instruction {
+    /// The opcode the instruction decodes from, the inverse of `decode`
+    pub fn encode(self) -> u16 {
+        match self {
+            Instruction::ScrollDown(n) => 0x00C0 | (n as u16 & 0x0F),
+            Instruction::ScrollUp(n) => 0x00D0 | (n as u16 & 0x0F),
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Low => 0x00FE,
+            Instruction::High => 0x00FF,
+            Instruction::Jp(address) => 0x1000 | (address & 0x0FFF),
+            Instruction::Call(address) => 0x2000 | (address & 0x0FFF),
+            Instruction::SeByte { x, byte } => xkk(0x3000, x, byte),
+            Instruction::SneByte { x, byte } => xkk(0x4000, x, byte),
+            Instruction::SeReg { x, y } => xy(0x5000, x, y, 0x0),
+            Instruction::SaveRange { x, y } => xy(0x5000, x, y, 0x2),
+            Instruction::LoadRange { x, y } => xy(0x5000, x, y, 0x3),
+            Instruction::LdByte { x, byte } => xkk(0x6000, x, byte),
+            Instruction::AddByte { x, byte } => xkk(0x7000, x, byte),
+            Instruction::LdReg { x, y } => xy(0x8000, x, y, 0x0),
+            Instruction::Or { x, y } => xy(0x8000, x, y, 0x1),
+            Instruction::And { x, y } => xy(0x8000, x, y, 0x2),
+            Instruction::Xor { x, y } => xy(0x8000, x, y, 0x3),
+            Instruction::AddReg { x, y } => xy(0x8000, x, y, 0x4),
+            Instruction::Sub { x, y } => xy(0x8000, x, y, 0x5),
+            Instruction::Shr { x, y } => xy(0x8000, x, y, 0x6),
+            Instruction::Subn { x, y } => xy(0x8000, x, y, 0x7),
+            Instruction::Shl { x, y } => xy(0x8000, x, y, 0xE),
+            Instruction::SneReg { x, y } => xy(0x9000, x, y, 0x0),
+            Instruction::LdI(address) => 0xA000 | (address & 0x0FFF),
+            Instruction::JpV0(address) => 0xB000 | (address & 0x0FFF),
+            Instruction::Rnd { x, byte } => xkk(0xC000, x, byte),
+            Instruction::Drw { x, y, n } => xy(0xD000, x, y, n as u16 & 0x0F),
+            Instruction::Skp { x } => xkk(0xE000, x, 0x9E),
+            Instruction::Sknp { x } => xkk(0xE000, x, 0xA1),
+            Instruction::LdILong => 0xF000,
+            Instruction::Plane(n) => xkk(0xF000, n, 0x01),
+            Instruction::Audio => 0xF002,
+            Instruction::LdVxDt { x } => xkk(0xF000, x, 0x07),
+            Instruction::LdVxK { x } => xkk(0xF000, x, 0x0A),
+            Instruction::LdDtVx { x } => xkk(0xF000, x, 0x15),
+            Instruction::LdStVx { x } => xkk(0xF000, x, 0x18),
+            Instruction::AddI { x } => xkk(0xF000, x, 0x1E),
+            Instruction::LdF { x } => xkk(0xF000, x, 0x29),
+            Instruction::LdHf { x } => xkk(0xF000, x, 0x30),
+            Instruction::LdB { x } => xkk(0xF000, x, 0x33),
+            Instruction::Pitch { x } => xkk(0xF000, x, 0x3A),
+            Instruction::LdIVx { x } => xkk(0xF000, x, 0x55),
+            Instruction::LdVxI { x } => xkk(0xF000, x, 0x65),
+            Instruction::LdRVx { x } => xkk(0xF000, x, 0x75),
+            Instruction::LdVxR { x } => xkk(0xF000, x, 0x85),
+        }
+    }
+
+    /// True for the instructions only available with the SUPER-CHIP quirk
+    pub fn is_schip(self) -> bool {
+        matches!(
+            self,
+            Instruction::ScrollDown(_)
+                | Instruction::ScrollRight
+                | Instruction::ScrollLeft
+                | Instruction::Exit
+                | Instruction::Low
+                | Instruction::High
+                | Instruction::LdHf { .. }
+                | Instruction::LdRVx { .. }
+                | Instruction::LdVxR { .. }
+        )
+    }
+
+    /// True for the instructions only available with the XO-CHIP quirk
+    pub fn is_xo_chip(self) -> bool {
+        matches!(
+            self,
+            Instruction::ScrollUp(_)
+                | Instruction::SaveRange { .. }
+                | Instruction::LoadRange { .. }
+                | Instruction::LdILong
+                | Instruction::Plane(_)
+                | Instruction::Audio
+                | Instruction::Pitch { .. }
+        )
+    }
+
+    /// The highest register the instruction addresses, decoded instructions never name one
+    /// above VF but hand-built ones can
+    pub fn highest_register(self) -> Option<u8> {
+        match self {
+            Instruction::SeReg { x, y }
+            | Instruction::SaveRange { x, y }
+            | Instruction::LoadRange { x, y }
+            | Instruction::LdReg { x, y }
+            | Instruction::Or { x, y }
+            | Instruction::And { x, y }
+            | Instruction::Xor { x, y }
+            | Instruction::AddReg { x, y }
+            | Instruction::Sub { x, y }
+            | Instruction::Shr { x, y }
+            | Instruction::Subn { x, y }
+            | Instruction::Shl { x, y }
+            | Instruction::SneReg { x, y }
+            | Instruction::Drw { x, y, .. } => Some(x.max(y)),
+            Instruction::SeByte { x, .. }
+            | Instruction::SneByte { x, .. }
+            | Instruction::LdByte { x, .. }
+            | Instruction::AddByte { x, .. }
+            | Instruction::Rnd { x, .. }
+            | Instruction::Skp { x }
+            | Instruction::Sknp { x }
+            | Instruction::LdVxDt { x }
+            | Instruction::LdVxK { x }
+            | Instruction::LdDtVx { x }
+            | Instruction::LdStVx { x }
+            | Instruction::AddI { x }
+            | Instruction::LdF { x }
+            | Instruction::LdHf { x }
+            | Instruction::LdB { x }
+            | Instruction::Pitch { x }
+            | Instruction::LdIVx { x }
+            | Instruction::LdVxI { x }
+            | Instruction::LdRVx { x }
+            | Instruction::LdVxR { x } => Some(x),
+            _ => None,
+        }
+    }
+
+    /// Size of the instruction in bytes, F000 is followed by its address
+    pub fn size(self) -> u16 {
+        match self {
+            Instruction::LdILong => 4,
+            _ => 2,
+        }
+    }
+}
+
+#[test]
+fn test_decode_system() {
+    assert_eq!(Ok(Instruction::Cls), decode(0x00E0));
+    assert_eq!(Ok(Instruction::Ret), decode(0x00EE));
+    assert_eq!(Ok(Instruction::ScrollDown(4)), decode(0x00C4));
+    assert_eq!(Ok(Instruction::ScrollUp(0xF)), decode(0x00DF));
+    assert_eq!(Ok(Instruction::ScrollRight), decode(0x00FB));
+    assert_eq!(Ok(Instruction::ScrollLeft), decode(0x00FC));
+    assert_eq!(Ok(Instruction::Exit), decode(0x00FD));
+    assert_eq!(Ok(Instruction::Low), decode(0x00FE));
+    assert_eq!(Ok(Instruction::High), decode(0x00FF));
+    // 0nnn machine code routines are not supported
+    assert_eq!(Err(DecodeError { opcode: 0x0123 }), decode(0x0123));
+}
+
+#[test]
+fn test_decode_jumps_and_skips() {
+    assert_eq!(Ok(Instruction::Jp(0x2A4)), decode(0x12A4));
+    assert_eq!(Ok(Instruction::Call(0x300)), decode(0x2300));
+    assert_eq!(Ok(Instruction::JpV0(0x345)), decode(0xB345));
+    assert_eq!(Ok(Instruction::SeByte { x: 3, byte: 0x10 }), decode(0x3310));
+    assert_eq!(
+        Ok(Instruction::SneByte { x: 0xA, byte: 0xFF }),
+        decode(0x4AFF)
+    );
+    assert_eq!(Ok(Instruction::SeReg { x: 1, y: 2 }), decode(0x5120));
+    assert_eq!(Ok(Instruction::SneReg { x: 1, y: 2 }), decode(0x9120));
+    assert_eq!(Ok(Instruction::Skp { x: 4 }), decode(0xE49E));
+    assert_eq!(Ok(Instruction::Sknp { x: 4 }), decode(0xE4A1));
+    assert_eq!(Err(DecodeError { opcode: 0x5121 }), decode(0x5121));
+    assert_eq!(Err(DecodeError { opcode: 0x9121 }), decode(0x9121));
+    assert_eq!(Err(DecodeError { opcode: 0xE400 }), decode(0xE400));
+}
+
+#[test]
+fn test_decode_arithmetic() {
+    assert_eq!(Ok(Instruction::LdByte { x: 5, byte: 7 }), decode(0x6507));
+    assert_eq!(Ok(Instruction::AddByte { x: 5, byte: 7 }), decode(0x7507));
+    assert_eq!(Ok(Instruction::LdReg { x: 1, y: 2 }), decode(0x8120));
+    assert_eq!(Ok(Instruction::Or { x: 1, y: 2 }), decode(0x8121));
+    assert_eq!(Ok(Instruction::And { x: 1, y: 2 }), decode(0x8122));
+    assert_eq!(Ok(Instruction::Xor { x: 1, y: 2 }), decode(0x8123));
+    assert_eq!(Ok(Instruction::AddReg { x: 1, y: 2 }), decode(0x8124));
+    assert_eq!(Ok(Instruction::Sub { x: 1, y: 2 }), decode(0x8125));
+    assert_eq!(Ok(Instruction::Shr { x: 1, y: 2 }), decode(0x8126));
+    assert_eq!(Ok(Instruction::Subn { x: 1, y: 2 }), decode(0x8127));
+    assert_eq!(Ok(Instruction::Shl { x: 1, y: 2 }), decode(0x812E));
+    assert_eq!(Ok(Instruction::Rnd { x: 0, byte: 0x0F }), decode(0xC00F));
+    assert_eq!(Err(DecodeError { opcode: 0x8128 }), decode(0x8128));
+}
+
+#[test]
+fn test_decode_memory_and_display() {
+    assert_eq!(Ok(Instruction::LdI(0x0FF)), decode(0xA0FF));
+    assert_eq!(Ok(Instruction::Drw { x: 1, y: 2, n: 5 }), decode(0xD125));
+    assert_eq!(Ok(Instruction::Drw { x: 1, y: 2, n: 0 }), decode(0xD120));
+    assert_eq!(Ok(Instruction::LdVxDt { x: 2 }), decode(0xF207));
+    assert_eq!(Ok(Instruction::LdVxK { x: 2 }), decode(0xF20A));
+    assert_eq!(Ok(Instruction::LdDtVx { x: 2 }), decode(0xF215));
+    assert_eq!(Ok(Instruction::LdStVx { x: 2 }), decode(0xF218));
+    assert_eq!(Ok(Instruction::AddI { x: 2 }), decode(0xF21E));
+    assert_eq!(Ok(Instruction::LdF { x: 2 }), decode(0xF229));
+    assert_eq!(Ok(Instruction::LdHf { x: 2 }), decode(0xF230));
+    assert_eq!(Ok(Instruction::LdB { x: 2 }), decode(0xF233));
+    assert_eq!(Ok(Instruction::LdIVx { x: 2 }), decode(0xF255));
+    assert_eq!(Ok(Instruction::LdVxI { x: 2 }), decode(0xF265));
+    assert_eq!(Ok(Instruction::LdRVx { x: 2 }), decode(0xF275));
+    assert_eq!(Ok(Instruction::LdVxR { x: 2 }), decode(0xF285));
+    assert_eq!(Err(DecodeError { opcode: 0xFFFF }), decode(0xFFFF));
+}
+
+#[test]
+fn test_decode_xo_chip() {
+    assert_eq!(Ok(Instruction::SaveRange { x: 1, y: 3 }), decode(0x5132));
+    assert_eq!(Ok(Instruction::LoadRange { x: 3, y: 1 }), decode(0x5313));
+    assert_eq!(Ok(Instruction::LdILong), decode(0xF000));
+    assert_eq!(Ok(Instruction::Plane(3)), decode(0xF301));
+    assert_eq!(Ok(Instruction::Audio), decode(0xF002));
+    assert_eq!(Ok(Instruction::Pitch { x: 7 }), decode(0xF73A));
+    assert_eq!(Err(DecodeError { opcode: 0xF100 }), decode(0xF100));
+    assert_eq!(Err(DecodeError { opcode: 0xF102 }), decode(0xF102));
+
+    assert!(Instruction::Plane(1).is_xo_chip());
+    assert!(!Instruction::Cls.is_xo_chip());
+    assert!(Instruction::High.is_schip());
+    assert!(!Instruction::Drw { x: 0, y: 0, n: 0 }.is_schip());
+    assert!(!Instruction::Drw { x: 0, y: 0, n: 5 }.is_schip());
+    assert_eq!(4, Instruction::LdILong.size());
+    assert_eq!(2, Instruction::Audio.size());
+}
+
+#[test]
+fn test_encode_round_trip() {
+    for opcode in 0..=0xFFFF {
+        if let Ok(instruction) = decode(opcode) {
+            assert_eq!(opcode, instruction.encode(), "{:?}", instruction);
+        }
+    }
+}
@@ -0,0 +1,33 @@
+//! knocket is a CHIP-8 emulator core.
+//!
+//! The core has no dependency on any windowing or audio stack; frontends drive a [`Chip8`]
+//! by feeding it the keypad state and reading back memory and the display. The minifb frontend
+//! shipped with the crate lives behind the `window` feature.
+
+pub mod assembler;
+pub mod audio;
+pub mod chip8;
+pub mod condition;
+pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
+pub mod display;
+pub mod error;
+pub mod gdb;
+pub mod instruction;
+pub mod mem;
+pub mod movie;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod state;
+
+pub use chip8::Chip8;
+pub use cpu::Cpu;
+pub use display::Display;
+pub use error::{
+    AssemblyError, ConditionError, DecodeError, EmulatorError, MovieError, StateError,
+};
+pub use instruction::Instruction;
+pub use mem::Mem;
+pub use quirks::Quirks;
@@ -1,17 +1,26 @@
 /*
     Memory map:
     0x000 - 0x1FF - Interpreter for Chip 8
-    0x000 - 0x0A0 - 4x4 built in font set
+    0x10F - 0x1AE - 8x10 SUPER-CHIP font set
+    0x1AF - 0x1FE - 4x5 built in font set
     0x200 - 0xFFF - Program ROM and RAM
+    0x200 - 0xFFFF - Program ROM and RAM on XO-CHIP
 
     http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#0.0
+    https://johnearnest.github.io/Octo/docs/XO-ChipSpecification.html
 */
-const MEMORY_SIZE: usize = 0x1000;
+use crate::error::{EmulatorError, StateError};
+use crate::state::{StateReader, StateWriter};
+use std::cell::Cell;
+use std::ops::RangeInclusive;
+
+pub const MEMORY_SIZE: usize = 0x1000;
+pub const XO_CHIP_MEMORY_SIZE: usize = 0x10000;
+/// Address programs are loaded at and start executing from
+pub const PROGRAM_MEMORY_START: usize = 0x200;
 const STACK_SIZE: usize = 0x10;
-const PROGRAM_MEMORY_START: usize = 0x200;
-const GRAPHICS_WIDTH: usize = 64;
-const GRAPHICS_HEIGHT: usize = 64;
 const DIGITS_MEMORY_START: usize = 0x1AF;
+const BIG_DIGITS_MEMORY_START: usize = 0x10F;
 
 const DIGITS: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -32,28 +41,88 @@ const DIGITS: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+const BIG_DIGITS: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// Kind of memory access a watchpoint stops on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn includes(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+/// Watches an address range for reads or writes by the program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<usize>,
+    pub access: Access,
+}
+
+/// A read or write by the program that matched a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub address: usize,
+    /// `Read` or `Write`
+    pub access: Access,
+    /// The byte read or written
+    pub value: u8,
+}
+
 pub struct Mem {
-    memory: [u8; MEMORY_SIZE],
+    memory: Vec<u8>,
     stack: [u16; STACK_SIZE],
-    pub graphics: [u32; GRAPHICS_HEIGHT * GRAPHICS_WIDTH],
     stack_pointer: usize,
+    watchpoints: Vec<Watchpoint>,
+    // the first access to match a watchpoint since the last `take_watch_hit`, a `Cell` since
+    // reads only borrow the memory
+    watch_hit: Cell<Option<WatchHit>>,
 }
 
 impl Mem {
     pub fn new() -> Mem {
+        Mem::with_size(MEMORY_SIZE)
+    }
+
+    /// Creates a memory of `size` bytes, 4 KiB for CHIP-8 and 64 KiB for XO-CHIP
+    pub fn with_size(size: usize) -> Mem {
         let mut mem = Mem {
-            memory: [0; MEMORY_SIZE],
+            memory: vec![0; size],
             stack: [0; STACK_SIZE],
-            graphics: [0; GRAPHICS_HEIGHT * GRAPHICS_WIDTH],
             stack_pointer: 0,
+            watchpoints: Vec::new(),
+            watch_hit: Cell::new(None),
         };
 
         mem.memory[DIGITS_MEMORY_START..0x1FF].copy_from_slice(&DIGITS);
+        mem.memory[BIG_DIGITS_MEMORY_START..DIGITS_MEMORY_START].copy_from_slice(&BIG_DIGITS);
         mem
     }
 
     pub fn load_program(&mut self, program: &[u8]) -> Result<(), &'static str> {
-        if PROGRAM_MEMORY_START + program.len() > MEMORY_SIZE {
+        if PROGRAM_MEMORY_START + program.len() > self.memory.len() {
             return Err("Program is too large to fit in memory");
         }
 
@@ -62,52 +131,204 @@ impl Mem {
         Ok(())
     }
 
-    pub fn fetch_opcode(&self, index: usize) -> u16 {
-        (self.memory[index] as u16) << 8 | (self.memory[index + 1]) as u16
+    pub fn size(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// The whole address space, including the interpreter area
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// The whole address space, for debuggers. Writes are not protected or watched.
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    /// The return addresses on the stack, the most recent call last
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.stack_pointer]
+    }
+
+    /// Grows or shrinks the stack to `len` return addresses, for debuggers
+    pub fn set_stack_len(&mut self, len: usize) -> Result<(), EmulatorError> {
+        if len > STACK_SIZE {
+            return Err(EmulatorError::StackOverflow);
+        }
+        self.stack_pointer = len;
+        Ok(())
+    }
+
+    /// Fetches an instruction, which does not trigger read watchpoints
+    pub fn fetch_opcode(&self, index: usize) -> Result<u16, EmulatorError> {
+        Ok((self.read(index)? as u16) << 8 | self.read(index + 1)? as u16)
+    }
+
+    pub fn fetch(&self, index: usize) -> Result<u8, EmulatorError> {
+        let value = self.read(index)?;
+        self.watch(index, Access::Read, value);
+        Ok(value)
+    }
+
+    fn read(&self, index: usize) -> Result<u8, EmulatorError> {
+        self.memory
+            .get(index)
+            .copied()
+            .ok_or(EmulatorError::MemoryOutOfBounds { address: index })
+    }
+
+    /// Fills `bytes` from memory starting at `index`. Nothing is read unless the whole range
+    /// is in memory.
+    pub fn fetch_bytes(&self, index: usize, bytes: &mut [u8]) -> Result<(), EmulatorError> {
+        self.check_range(index, bytes.len())?;
+        for (offset, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.fetch(index + offset)?;
+        }
+        Ok(())
+    }
+
+    /// Stores `bytes` starting at `index`. Nothing is written unless the whole range is in
+    /// writable memory.
+    pub fn store_bytes(&mut self, index: usize, bytes: &[u8]) -> Result<(), EmulatorError> {
+        if !bytes.is_empty() && index < PROGRAM_MEMORY_START {
+            return Err(EmulatorError::ProtectedWrite { address: index });
+        }
+        self.check_range(index, bytes.len())?;
+        for (offset, &byte) in bytes.iter().enumerate() {
+            self.store(index + offset, byte)?;
+        }
+        Ok(())
     }
 
-    pub fn fetch(&self, index: usize) -> u8 {
-        self.memory[index]
+    // fails with the first address of the range outside of memory
+    fn check_range(&self, index: usize, len: usize) -> Result<(), EmulatorError> {
+        if len > 0 && index.saturating_add(len) > self.memory.len() {
+            return Err(EmulatorError::MemoryOutOfBounds {
+                address: index.max(self.memory.len()),
+            });
+        }
+        Ok(())
     }
 
-    pub fn store(&mut self, index: usize, value: u8) {
+    pub fn store(&mut self, index: usize, value: u8) -> Result<(), EmulatorError> {
         if index < PROGRAM_MEMORY_START {
-            panic!("Segmentation fault");
+            return Err(EmulatorError::ProtectedWrite { address: index });
         }
 
-        self.memory[index] = value;
+        let cell = self
+            .memory
+            .get_mut(index)
+            .ok_or(EmulatorError::MemoryOutOfBounds { address: index })?;
+        *cell = value;
+        self.watch(index, Access::Write, value);
+        Ok(())
     }
 
-    pub fn fetch_graphics(&self, x: usize, y: usize) -> u8 {
-        (self.graphics[x + (y << 6)] & 0xFF) as u8
+    /// Adds a watchpoint, returns false if the same one was already set
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        if self.watchpoints.contains(&watchpoint) {
+            return false;
+        }
+        self.watchpoints.push(watchpoint);
+        true
     }
 
-    pub fn store_graphics(&mut self, x: usize, y: usize, val: u8) {
-        self.graphics[x + (y << 6)] = val as u32 | 0xFF00;
+    /// Removes a watchpoint, returns false if it was not set
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|w| w != watchpoint);
+        self.watchpoints.len() != count
     }
 
-    pub fn clear_graphics(&mut self) {
-        for n in self.graphics.iter_mut() {
-            *n = 0;
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Moves the watchpoints of `previous` into this memory, for when the machine replaces its
+    /// memory on a reset or a state load
+    pub(crate) fn keep_watchpoints(&mut self, previous: &mut Mem) {
+        self.watchpoints = std::mem::take(&mut previous.watchpoints);
+    }
+
+    /// The first access that matched a watchpoint since the last call
+    pub fn take_watch_hit(&self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    fn watch(&self, address: usize, access: Access, value: u8) {
+        if self.watch_hit.get().is_some() {
+            return;
+        }
+        let watched = self
+            .watchpoints
+            .iter()
+            .any(|w| w.access.includes(access) && w.range.contains(&address));
+        if watched {
+            self.watch_hit.set(Some(WatchHit {
+                address,
+                access,
+                value,
+            }));
         }
     }
 
-    pub fn push(&mut self, addr: u16) {
-        self.stack_pointer += 1;
+    pub fn push(&mut self, addr: u16) -> Result<(), EmulatorError> {
+        if self.stack_pointer >= STACK_SIZE {
+            return Err(EmulatorError::StackOverflow);
+        }
+
         self.stack[self.stack_pointer] = addr;
+        self.stack_pointer += 1;
+        Ok(())
     }
 
     pub fn peek(&self) -> u16 {
-        self.stack[self.stack_pointer]
+        self.stack[self.stack_pointer.saturating_sub(1)]
     }
 
-    pub fn pop(&mut self) -> u16 {
+    pub fn pop(&mut self) -> Result<u16, EmulatorError> {
+        if self.stack_pointer == 0 {
+            return Err(EmulatorError::StackUnderflow);
+        }
+
         self.stack_pointer -= 1;
-        self.stack[self.stack_pointer + 1]
+        Ok(self.stack[self.stack_pointer])
+    }
+
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.u32(self.memory.len() as u32);
+        writer.bytes(&self.memory);
+        for &address in self.stack.iter() {
+            writer.u16(address);
+        }
+        writer.u8(self.stack_pointer as u8);
+    }
+
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let size = reader.u32()? as usize;
+        if size != self.memory.len() {
+            return Err(StateError::MemorySizeMismatch {
+                expected: self.memory.len(),
+                found: size,
+            });
+        }
+        reader.read_into(&mut self.memory)?;
+        for address in self.stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        self.stack_pointer = reader.u8()? as usize;
+        if self.stack_pointer > STACK_SIZE {
+            return Err(StateError::Corrupt);
+        }
+        Ok(())
     }
 
     pub fn get_address_for_digit(&self, digit: u8) -> u16 {
-        (DIGITS_MEMORY_START + 5 * digit as usize) as u16
+        (DIGITS_MEMORY_START + 5 * (digit & 0x0F) as usize) as u16
+    }
+
+    pub fn get_address_for_big_digit(&self, digit: u8) -> u16 {
+        (BIG_DIGITS_MEMORY_START + 10 * (digit & 0x0F) as usize) as u16
     }
 }
 
@@ -129,16 +350,60 @@ fn test_load_program() {
     assert_eq!(4, mem.memory[PROGRAM_MEMORY_START + 3]);
 }
 
+#[test]
+fn test_load_program_too_large() {
+    let program = vec![0; MEMORY_SIZE];
+
+    assert!(Mem::new().load_program(&program).is_err());
+    assert!(Mem::with_size(XO_CHIP_MEMORY_SIZE)
+        .load_program(&program)
+        .is_ok());
+}
+
 #[test]
 fn test_stack() {
     let mut mem = Mem::new();
 
-    mem.push(1);
+    mem.push(1).unwrap();
     assert_eq!(1, mem.peek());
-    let val = mem.pop();
+    let val = mem.pop().unwrap();
     assert_eq!(1, val);
 }
 
+#[test]
+fn test_stack_contents() {
+    let mut mem = Mem::new();
+    assert!(mem.stack().is_empty());
+
+    mem.push(0x202).unwrap();
+    mem.push(0x304).unwrap();
+    assert_eq!(&[0x202, 0x304], mem.stack());
+
+    mem.set_stack_len(1).unwrap();
+    assert_eq!(&[0x202], mem.stack());
+    assert_eq!(
+        Err(EmulatorError::StackOverflow),
+        mem.set_stack_len(STACK_SIZE + 1)
+    );
+}
+
+#[test]
+fn test_stack_overflow() {
+    let mut mem = Mem::new();
+
+    for addr in 0..STACK_SIZE as u16 {
+        mem.push(addr).unwrap();
+    }
+    assert_eq!(Err(EmulatorError::StackOverflow), mem.push(0));
+}
+
+#[test]
+fn test_stack_underflow() {
+    let mut mem = Mem::new();
+
+    assert_eq!(Err(EmulatorError::StackUnderflow), mem.pop());
+}
+
 #[test]
 fn test_fetch_digit_address() {
     let mem = Mem::new();
@@ -151,18 +416,124 @@ fn test_fetch_digit_address() {
     );
 }
 
+#[test]
+fn test_fetch_big_digit_address() {
+    let mem = Mem::new();
+
+    assert_eq!(
+        BIG_DIGITS_MEMORY_START as u16,
+        mem.get_address_for_big_digit(0)
+    );
+    assert_eq!(
+        Ok(0x18),
+        mem.fetch(mem.get_address_for_big_digit(1) as usize)
+    );
+    assert_eq!(
+        Ok(0xC0),
+        mem.fetch(mem.get_address_for_big_digit(0xF) as usize + 9)
+    );
+}
+
 #[test]
 fn test_store_and_fetch() {
     let mut mem = Mem::new();
 
-    mem.store(0x200, 0xFF);
-    assert_eq!(0xFF, mem.fetch(0x200));
+    mem.store(0x200, 0xFF).unwrap();
+    assert_eq!(0xFF, mem.fetch(0x200).unwrap());
 }
 
 #[test]
-fn test_fetch_graphics() {
+fn test_store_protected() {
     let mut mem = Mem::new();
-    mem.graphics[67] = 255;
 
-    assert_eq!(255, mem.fetch_graphics(3, 1));
+    assert_eq!(
+        Err(EmulatorError::ProtectedWrite { address: 0x1FF }),
+        mem.store(0x1FF, 0xFF)
+    );
+}
+
+#[test]
+fn test_fetch_out_of_bounds() {
+    let mut mem = Mem::new();
+
+    assert_eq!(
+        Err(EmulatorError::MemoryOutOfBounds {
+            address: MEMORY_SIZE
+        }),
+        mem.fetch(MEMORY_SIZE)
+    );
+    assert_eq!(
+        Err(EmulatorError::MemoryOutOfBounds {
+            address: MEMORY_SIZE
+        }),
+        mem.store(MEMORY_SIZE, 0xFF)
+    );
+    assert!(mem.fetch_opcode(MEMORY_SIZE - 1).is_err());
+}
+
+#[test]
+fn test_bytes_are_all_or_nothing() {
+    let mut mem = Mem::new();
+    let mut bytes = [0xAA; 3];
+
+    assert_eq!(
+        Err(EmulatorError::MemoryOutOfBounds {
+            address: MEMORY_SIZE
+        }),
+        mem.store_bytes(MEMORY_SIZE - 2, &[1, 2, 3])
+    );
+    assert_eq!(0, mem.fetch(MEMORY_SIZE - 2).unwrap());
+    assert!(mem.fetch_bytes(MEMORY_SIZE - 2, &mut bytes).is_err());
+    assert_eq!([0xAA; 3], bytes);
+
+    mem.store_bytes(0x300, &[1, 2, 3]).unwrap();
+    mem.fetch_bytes(0x300, &mut bytes).unwrap();
+    assert_eq!([1, 2, 3], bytes);
+}
+
+#[test]
+fn test_watchpoints() {
+    let mut mem = Mem::new();
+    let watchpoint = Watchpoint {
+        range: 0x300..=0x303,
+        access: Access::Write,
+    };
+    assert!(mem.add_watchpoint(watchpoint.clone()));
+    assert!(!mem.add_watchpoint(watchpoint.clone()));
+
+    mem.store(0x304, 1).unwrap();
+    mem.fetch(0x300).unwrap();
+    assert_eq!(None, mem.take_watch_hit());
+
+    mem.store(0x302, 7).unwrap();
+    mem.store(0x303, 8).unwrap();
+    let hit = WatchHit {
+        address: 0x302,
+        access: Access::Write,
+        value: 7,
+    };
+    assert_eq!(Some(hit), mem.take_watch_hit());
+    assert_eq!(None, mem.take_watch_hit());
+
+    assert!(mem.remove_watchpoint(&watchpoint));
+    assert!(!mem.remove_watchpoint(&watchpoint));
+    mem.store(0x302, 7).unwrap();
+    assert_eq!(None, mem.take_watch_hit());
+}
+
+#[test]
+fn test_read_watchpoints_skip_instruction_fetches() {
+    let mut mem = Mem::new();
+    mem.add_watchpoint(Watchpoint {
+        range: 0x200..=0x201,
+        access: Access::ReadWrite,
+    });
+
+    mem.fetch_opcode(0x200).unwrap();
+    assert_eq!(None, mem.take_watch_hit());
+    mem.fetch(0x201).unwrap();
+    assert_eq!(
+        Some(Access::Read),
+        mem.take_watch_hit().map(|hit| hit.access)
+    );
 }
@@ -0,0 +1,259 @@
+/*
+    An input movie is the keypad state of every frame since power-on, along with everything
+    else a run depends on, so playing it back reproduces the session exactly.
+
+    Layout, all values little endian:
+
+    0x00 - 4 bytes - "KNKM"
+    0x04 - u16     - format version
+    0x06 - u64     - FNV-1a hash of the ROM
+    0x0E - u64     - random seed
+    0x16 - u32     - instructions per frame
+    0x1A - u8      - quirks, one bit each
+    0x1B - u32     - frame count
+    0x1F - u16     - keypad of each frame, bit n set while key n is down
+*/
+use crate::chip8::Chip8;
+use crate::error::MovieError;
+use crate::quirks::Quirks;
+use crate::state::{StateReader, StateWriter};
+
+pub const MAGIC: &[u8; 4] = b"KNKM";
+pub const VERSION: u16 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    rom_hash: u64,
+    seed: u64,
+    instructions_per_frame: u32,
+    quirks: u8,
+    frames: Vec<u16>,
+}
+
+fn quirks_bits(quirks: Quirks) -> u8 {
+    [
+        quirks.shift_uses_vy,
+        quirks.load_store_increments_index,
+        quirks.jump_uses_vx,
+        quirks.logic_resets_vf,
+        quirks.display_wait,
+        quirks.wrap_sprites,
+        quirks.xo_chip,
+        quirks.schip,
+    ]
+    .iter()
+    .enumerate()
+    .fold(0, |bits, (bit, &set)| bits | (set as u8) << bit)
+}
+
+fn pack_keypad(keypad: &[bool; 16]) -> u16 {
+    keypad
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (key, &down)| bits | (down as u16) << key)
+}
+
+fn unpack_keypad(bits: u16) -> [bool; 16] {
+    let mut keypad = [false; 16];
+    for (key, down) in keypad.iter_mut().enumerate() {
+        *down = bits & (1 << key) != 0;
+    }
+    keypad
+}
+
+impl Movie {
+    /// Starts an empty recording for the program, seed and settings of `chip8`. Reset the
+    /// machine before recording the first frame so playback starts from the same state.
+    pub fn new(chip8: &Chip8) -> Movie {
+        Movie {
+            rom_hash: chip8.rom_hash(),
+            seed: chip8.seed(),
+            instructions_per_frame: chip8.instructions_per_frame(),
+            quirks: quirks_bits(chip8.quirks()),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
+    }
+
+    /// Number of recorded frames
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Appends the keypad state passed to the next `Chip8::run_frame`
+    pub fn record(&mut self, keypad: &[bool; 16]) {
+        self.frames.push(pack_keypad(keypad));
+    }
+
+    /// The keypad state of a recorded frame
+    pub fn keypad(&self, frame: usize) -> Option<[bool; 16]> {
+        self.frames.get(frame).map(|&bits| unpack_keypad(bits))
+    }
+
+    /// Prepares `chip8` for playback: checks that it runs the recorded ROM with the recorded
+    /// quirks, applies the seed and instructions per frame, and resets it.
+    pub fn start_playback(&self, chip8: &mut Chip8) -> Result<(), MovieError> {
+        if chip8.rom_hash() != self.rom_hash {
+            return Err(MovieError::RomMismatch {
+                expected: chip8.rom_hash(),
+                found: self.rom_hash,
+            });
+        }
+        if quirks_bits(chip8.quirks()) != self.quirks {
+            return Err(MovieError::QuirksMismatch);
+        }
+
+        chip8.set_seed(self.seed);
+        chip8.set_instructions_per_frame(self.instructions_per_frame);
+        chip8.reset();
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.bytes(MAGIC);
+        writer.u16(VERSION);
+        writer.u64(self.rom_hash);
+        writer.u64(self.seed);
+        writer.u32(self.instructions_per_frame);
+        writer.u8(self.quirks);
+        writer.u32(self.frames.len() as u32);
+        for &frame in self.frames.iter() {
+            writer.u16(frame);
+        }
+        writer.into_bytes()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, MovieError> {
+        let truncated = |_| MovieError::Truncated;
+        let mut reader = StateReader::new(data);
+        if reader.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(MovieError::InvalidMagic);
+        }
+        let version = reader.u16().map_err(truncated)?;
+        if version != VERSION {
+            return Err(MovieError::UnsupportedVersion { version });
+        }
+
+        let rom_hash = reader.u64().map_err(truncated)?;
+        let seed = reader.u64().map_err(truncated)?;
+        let instructions_per_frame = reader.u32().map_err(truncated)?;
+        let quirks = reader.u8().map_err(truncated)?;
+        let count = reader.u32().map_err(truncated)?;
+        let frames = (0..count)
+            .map(|_| reader.u16())
+            .collect::<Result<Vec<u16>, _>>()
+            .map_err(truncated)?;
+
+        Ok(Movie {
+            rom_hash,
+            seed,
+            instructions_per_frame,
+            quirks,
+            frames,
+        })
+    }
+}
+
+#[test]
+fn test_keypad_packing() {
+    let mut keypad = [false; 16];
+    keypad[0] = true;
+    keypad[0xF] = true;
+
+    assert_eq!(0x8001, pack_keypad(&keypad));
+    assert_eq!(keypad, unpack_keypad(0x8001));
+}
+
+#[test]
+fn test_to_and_from_bytes() {
+    let mut chip8 = Chip8::default();
+    chip8.set_seed(99);
+    chip8.load_program(&[0x12, 0x00]).unwrap();
+    let mut movie = Movie::new(&chip8);
+    let mut keypad = [false; 16];
+    movie.record(&keypad);
+    keypad[5] = true;
+    movie.record(&keypad);
+
+    let data = movie.to_bytes();
+
+    assert_eq!(Ok(movie.clone()), Movie::from_bytes(&data));
+    assert_eq!(Some(keypad), movie.keypad(1));
+    assert_eq!(None, movie.keypad(2));
+    assert_eq!(Err(MovieError::InvalidMagic), Movie::from_bytes(b"KNKT"));
+    assert_eq!(
+        Err(MovieError::Truncated),
+        Movie::from_bytes(&data[..data.len() - 1])
+    );
+}
+
+#[test]
+fn test_playback_reproduces_run() {
+    // wait for a key in V0, V1 = rand masked by V0, draw digit V1, loop
+    let program = [
+        0xF0, 0x0A, 0xC1, 0xFF, 0x81, 0x02, 0xF1, 0x29, 0xD0, 0x05, 0x12, 0x00,
+    ];
+    let mut chip8 = Chip8::default();
+    chip8.set_seed(7);
+    chip8.load_program(&program).unwrap();
+    let mut movie = Movie::new(&chip8);
+
+    for frame in 0..30 {
+        let mut keypad = [false; 16];
+        keypad[frame % 16] = frame % 3 == 0;
+        movie.record(&keypad);
+        chip8.run_frame(&keypad).unwrap();
+    }
+    let recorded = chip8.save_state();
+
+    let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+    let mut player = Chip8::default();
+    player.load_program(&program).unwrap();
+    movie.start_playback(&mut player).unwrap();
+    for frame in 0..movie.len() {
+        player.run_frame(&movie.keypad(frame).unwrap()).unwrap();
+    }
+
+    assert_eq!(7, player.seed());
+    assert_eq!(recorded, player.save_state());
+}
+
+#[test]
+fn test_playback_mismatch() {
+    let mut chip8 = Chip8::default();
+    chip8.load_program(&[0x12, 0x00]).unwrap();
+    let movie = Movie::new(&chip8);
+
+    let mut other_rom = Chip8::default();
+    other_rom.load_program(&[0x12, 0x02]).unwrap();
+    assert_eq!(
+        Err(MovieError::RomMismatch {
+            expected: other_rom.rom_hash(),
+            found: chip8.rom_hash()
+        }),
+        movie.start_playback(&mut other_rom)
+    );
+
+    let mut other_quirks = Chip8::new(Quirks::CHIP_48);
+    other_quirks.load_program(&[0x12, 0x00]).unwrap();
+    assert_eq!(
+        Err(MovieError::QuirksMismatch),
+        movie.start_playback(&mut other_quirks)
+    );
+}
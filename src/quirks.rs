@@ -0,0 +1,101 @@
+/// Behaviour that differs between CHIP-8 interpreters. ROMs are usually written against one
+/// interpreter and may misbehave when run with the quirks of another, so pick the preset
+/// matching the interpreter the ROM targets.
+///
+/// https://github.com/Timendus/chip8-test-suite#quirks-test
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8xy6 and 8xyE shift VY and store the result in VX, instead of shifting VX in place
+    pub shift_uses_vy: bool,
+    /// Fx55 and Fx65 leave I pointing past the last register stored or loaded
+    pub load_store_increments_index: bool,
+    /// Bnnn jumps to nnn + VX, where x is the highest nibble of nnn, instead of nnn + V0
+    pub jump_uses_vx: bool,
+    /// 8xy1, 8xy2 and 8xy3 reset VF to zero
+    pub logic_resets_vf: bool,
+    /// Dxyn waits for the next vertical blank, limiting drawing to one sprite per frame
+    pub display_wait: bool,
+    /// Sprites drawn past the right or bottom edge wrap around to the opposite edge instead
+    /// of being clipped. The starting coordinate always wraps.
+    pub wrap_sprites: bool,
+    /// Enables the SUPER-CHIP extensions: 00FE/00FF hires mode, 00CN/00FB/00FC scrolling, 00FD
+    /// exit, 16x16 Dxy0 sprites, Fx30 big digits and Fx75/Fx85 flag registers
+    pub schip: bool,
+    /// Enables the XO-CHIP extensions: 64 KiB of memory, F000 NNNN, 5xy2, 5xy3, Fn01 plane
+    /// selection and 00DN scrolling up
+    pub xo_chip: bool,
+}
+
+impl Quirks {
+    /// The original interpreter on the RCA COSMAC VIP
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_index: true,
+        jump_uses_vx: false,
+        logic_resets_vf: true,
+        display_wait: true,
+        wrap_sprites: false,
+        schip: false,
+        xo_chip: false,
+    };
+
+    /// CHIP-48 on the HP-48 graphing calculators
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_index: true,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        display_wait: false,
+        wrap_sprites: false,
+        schip: false,
+        xo_chip: false,
+    };
+
+    /// SUPER-CHIP 1.1
+    pub const SCHIP_1_1: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_index: false,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        display_wait: false,
+        wrap_sprites: false,
+        schip: true,
+        xo_chip: false,
+    };
+
+    /// XO-CHIP as implemented by Octo
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_index: true,
+        jump_uses_vx: false,
+        logic_resets_vf: false,
+        display_wait: false,
+        wrap_sprites: true,
+        schip: true,
+        xo_chip: true,
+    };
+
+    /// Looks up a preset by the name used on the command line: `vip`, `chip48`, `schip` or
+    /// `xochip`
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" => Some(Quirks::COSMAC_VIP),
+            "chip48" => Some(Quirks::CHIP_48),
+            "schip" => Some(Quirks::SCHIP_1_1),
+            "xochip" => Some(Quirks::XO_CHIP),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::COSMAC_VIP
+    }
+}
+
+#[test]
+fn test_preset() {
+    assert_eq!(Some(Quirks::SCHIP_1_1), Quirks::preset("schip"));
+    assert_eq!(None, Quirks::preset("superchip"));
+}
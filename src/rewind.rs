@@ -0,0 +1,215 @@
+/*
+    Rewinding keeps the most recent save state in full and, for every older snapshot, the
+    difference to the one after it. Consecutive states differ in a handful of bytes, so the
+    XOR of two states is mostly zeros and is stored run length encoded:
+
+    repeated: varint zero run, varint literal count, literal bytes
+*/
+use crate::chip8::Chip8;
+use crate::error::StateError;
+use std::collections::VecDeque;
+
+/// A bounded history of snapshots to step a `Chip8` back through
+pub struct Rewind {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    /// Creates a history holding up to `capacity` snapshots, at least one
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind {
+            capacity: capacity.max(1),
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of snapshots held
+    pub fn len(&self) -> usize {
+        match self.latest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// Bytes used by the snapshots
+    pub fn memory_usage(&self) -> usize {
+        self.latest.as_ref().map_or(0, Vec::len) + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+
+    /// Takes a snapshot of the machine, dropping the oldest one when the history is full
+    pub fn push(&mut self, chip8: &Chip8) {
+        let state = chip8.save_state();
+        if let Some(latest) = self.latest.take() {
+            if latest.len() == state.len() {
+                self.deltas.push_back(encode_delta(&latest, &state));
+            } else {
+                self.deltas.clear();
+            }
+        }
+        self.latest = Some(state);
+
+        while self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    /// Restores the machine to the snapshot before the most recent one and drops the most
+    /// recent one. The oldest snapshot is kept, so holding rewind stops there. Returns false
+    /// if there is nothing to rewind to.
+    pub fn rewind(&mut self, chip8: &mut Chip8) -> Result<bool, StateError> {
+        let latest = match self.latest.as_mut() {
+            Some(latest) => latest,
+            None => return Ok(false),
+        };
+        if let Some(delta) = self.deltas.pop_back() {
+            apply_delta(latest, &delta);
+        }
+        chip8.load_state(latest)?;
+        Ok(true)
+    }
+}
+
+fn write_varint(output: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        output.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+fn read_varint(input: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some(&byte) = input.get(*position) {
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+
+/// Encodes the XOR of two equally long states
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = from.iter().zip(to).map(|(a, b)| a ^ b).collect();
+    let mut output = Vec::new();
+    let mut position = 0;
+
+    while position < xor.len() {
+        let zeros = xor[position..]
+            .iter()
+            .take_while(|&&byte| byte == 0)
+            .count();
+        position += zeros;
+        let literals = xor[position..]
+            .iter()
+            .take_while(|&&byte| byte != 0)
+            .count();
+        write_varint(&mut output, zeros);
+        write_varint(&mut output, literals);
+        output.extend_from_slice(&xor[position..position + literals]);
+        position += literals;
+    }
+
+    output
+}
+
+/// XORs an encoded delta onto `state`, turning one side of the delta into the other
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut input = 0;
+    let mut position = 0;
+
+    while input < delta.len() {
+        position += read_varint(delta, &mut input);
+        let literals = read_varint(delta, &mut input);
+        for (byte, change) in state[position..position + literals]
+            .iter_mut()
+            .zip(&delta[input..input + literals])
+        {
+            *byte ^= change;
+        }
+        input += literals;
+        position += literals;
+    }
+}
+
+#[test]
+fn test_delta_round_trip() {
+    let from: Vec<u8> = (0..=255).collect();
+    let mut to = from.clone();
+    to[0] = 0xFF;
+    to[200] = 0;
+    to[201] = 1;
+    to[255] = 7;
+
+    let delta = encode_delta(&from, &to);
+    assert!(delta.len() < 16);
+
+    let mut state = from.clone();
+    apply_delta(&mut state, &delta);
+    assert_eq!(to, state);
+    apply_delta(&mut state, &delta);
+    assert_eq!(from, state);
+}
+
+#[test]
+fn test_varint() {
+    let mut output = Vec::new();
+    write_varint(&mut output, 5);
+    write_varint(&mut output, 300);
+    let mut position = 0;
+
+    assert_eq!(3, output.len());
+    assert_eq!(5, read_varint(&output, &mut position));
+    assert_eq!(300, read_varint(&output, &mut position));
+}
+
+#[test]
+fn test_rewind() {
+    let mut chip8 = Chip8::default();
+    let keypad = [false; 16];
+    // V0 += 1, loop
+    chip8.load_program(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+    chip8.set_instructions_per_frame(2);
+    let mut rewind = Rewind::new(3);
+    let mut states = Vec::new();
+
+    for _ in 0..5 {
+        chip8.run_frame(&keypad).unwrap();
+        rewind.push(&chip8);
+        states.push(chip8.save_state());
+    }
+
+    assert_eq!(3, rewind.len());
+    assert!(rewind.memory_usage() < states[0].len() + 100);
+
+    assert_eq!(Ok(true), rewind.rewind(&mut chip8));
+    assert_eq!(states[3], chip8.save_state());
+    assert_eq!(Ok(true), rewind.rewind(&mut chip8));
+    assert_eq!(states[2], chip8.save_state());
+    // the oldest snapshot is kept
+    assert_eq!(Ok(true), rewind.rewind(&mut chip8));
+    assert_eq!(states[2], chip8.save_state());
+    assert_eq!(1, rewind.len());
+
+    rewind.clear();
+    assert_eq!(Ok(false), rewind.rewind(&mut chip8));
+}
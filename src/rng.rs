@@ -0,0 +1,71 @@
+/*
+    xorshift64* generator for Cxkk. It is part of the machine state so that runs with the
+    same seed and input are reproducible, and save states restore the random sequence too.
+
+    https://en.wikipedia.org/wiki/Xorshift#xorshift*
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // scramble the seed with splitmix64, xorshift must never start from zero
+        let mut state = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        state ^= state >> 31;
+        Rng::from_state(state)
+    }
+
+    /// Resumes a generator from a value returned by `state`
+    pub fn from_state(state: u64) -> Rng {
+        Rng {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Rng::new(0)
+    }
+}
+
+#[test]
+fn test_same_seed_same_sequence() {
+    let mut a = Rng::new(42);
+    let mut b = Rng::new(42);
+    let mut c = Rng::new(43);
+
+    let sequence: Vec<u8> = (0..16).map(|_| a.next_u8()).collect();
+    assert_eq!(sequence, (0..16).map(|_| b.next_u8()).collect::<Vec<u8>>());
+    assert_ne!(sequence, (0..16).map(|_| c.next_u8()).collect::<Vec<u8>>());
+}
+
+#[test]
+fn test_from_state() {
+    let mut rng = Rng::new(7);
+    rng.next_u8();
+    let mut resumed = Rng::from_state(rng.state());
+
+    assert_eq!(rng.next_u64(), resumed.next_u64());
+    assert_ne!(0, Rng::from_state(0).next_u64());
+}
@@ -0,0 +1,143 @@
+/*
+    Save state layout, all values little endian:
+
+    0x00 - 4 bytes - "KNKT"
+    0x04 - u16     - format version
+    0x06 - u64     - FNV-1a hash of the ROM
+    0x0E -         - CPU, memory and display state
+
+    Bump VERSION whenever the layout of the state changes.
+*/
+use crate::error::StateError;
+
+pub const MAGIC: &[u8; 4] = b"KNKT";
+pub const VERSION: u16 = 3;
+
+/// 64 bit FNV-1a hash
+pub fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Hash used to tie save states and movies to the ROM they were made with
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    fnv1a(rom)
+}
+
+#[derive(Default)]
+pub(crate) struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter::default()
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data }
+    }
+
+    pub fn bytes(&mut self, count: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < count {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn read_into(&mut self, target: &mut [u8]) -> Result<(), StateError> {
+        target.copy_from_slice(self.bytes(target.len())?);
+        Ok(())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let mut bytes = [0; 2];
+        self.read_into(&mut bytes)?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0; 4];
+        self.read_into(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        self.read_into(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+#[test]
+fn test_rom_hash() {
+    assert_eq!(0xcbf2_9ce4_8422_2325, rom_hash(&[]));
+    assert_eq!(0xaf63_dc4c_8601_ec8c, rom_hash(b"a"));
+    assert_ne!(rom_hash(&[0x12, 0x00]), rom_hash(&[0x00, 0x12]));
+}
+
+#[test]
+fn test_writer_and_reader() {
+    let mut writer = StateWriter::new();
+    writer.u8(0x12);
+    writer.bool(true);
+    writer.u16(0x3456);
+    writer.u32(0x789A_BCDE);
+    writer.u64(0x0102_0304_0506_0708);
+    writer.bytes(&[1, 2, 3]);
+    let data = writer.into_bytes();
+
+    let mut reader = StateReader::new(&data);
+    assert_eq!(Ok(0x12), reader.u8());
+    assert_eq!(Ok(true), reader.bool());
+    assert_eq!(Ok(0x3456), reader.u16());
+    assert_eq!(Ok(0x789A_BCDE), reader.u32());
+    assert_eq!(Ok(0x0102_0304_0506_0708), reader.u64());
+    assert_eq!(Ok(&[1, 2, 3][..]), reader.bytes(3));
+    assert_eq!(Err(StateError::Truncated), reader.u8());
+}
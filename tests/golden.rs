@@ -0,0 +1,153 @@
+//! Golden image tests: each ROM in tests/roms is run for a fixed number of frames and the
+//! display is compared against a text image in tests/golden, `.` for a blank pixel and `#`
+//! for a lit one.
+//!
+//! Run with `KNOCKET_BLESS=1` to write the images from the current output instead, then review
+//! them before committing.
+
+use knocket::{Chip8, Quirks};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+struct Case {
+    /// Name of the ROM in tests/roms and of the image in tests/golden, without extension
+    name: &'static str,
+    quirks: Quirks,
+    frames: u64,
+    /// Keys held as (first frame, last frame, key)
+    keys: &'static [(u64, u64, usize)],
+}
+
+fn path(directory: &str, file: String) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", directory, &file]
+        .iter()
+        .collect()
+}
+
+fn keypad(case: &Case, frame: u64) -> [bool; 16] {
+    let mut keypad = [false; 16];
+    for &(first, last, key) in case.keys {
+        if (first..=last).contains(&frame) {
+            keypad[key] = true;
+        }
+    }
+    keypad
+}
+
+fn run(case: &Case, rom: &[u8]) -> String {
+    let mut chip8 = Chip8::new(case.quirks);
+    chip8.load_program(rom).expect("ROM fits in memory");
+    for frame in 0..case.frames {
+        chip8
+            .run_frame(&keypad(case, frame))
+            .unwrap_or_else(|e| panic!("{} failed in frame {}: {}", case.name, frame, e));
+    }
+    chip8.display.to_text()
+}
+
+/// Shows both images side by side, marking the rows that differ and the differing columns
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let width = expected.iter().chain(&actual).map(|row| row.len()).max();
+    let width = width.unwrap_or(0);
+    let mut output = format!("     row | {:width$} | actual\n", "expected", width = width);
+
+    for row in 0..expected.len().max(actual.len()) {
+        let left = expected.get(row).copied().unwrap_or("");
+        let right = actual.get(row).copied().unwrap_or("");
+        let marker = if left == right { ' ' } else { '>' };
+        output += &format!(
+            "{}  {:5} | {:width$} | {}\n",
+            marker,
+            row,
+            left,
+            right,
+            width = width
+        );
+        if left != right {
+            let columns: String = (0..left.len().max(right.len()))
+                .map(|column| {
+                    if left.as_bytes().get(column) == right.as_bytes().get(column) {
+                        ' '
+                    } else {
+                        '^'
+                    }
+                })
+                .collect();
+            output += &format!("         | {:width$} |\n", columns, width = width);
+        }
+    }
+
+    output
+}
+
+fn check(case: &Case) {
+    let rom_path = path("roms", format!("{}.ch8", case.name));
+    let rom = fs::read(&rom_path).unwrap_or_else(|_| panic!("{} not found", rom_path.display()));
+    let actual = run(case, &rom);
+    let golden_path = path("golden", format!("{}.txt", case.name));
+
+    if env::var_os("KNOCKET_BLESS").is_some() {
+        fs::write(&golden_path, &actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&golden_path).unwrap_or_else(|_| {
+        panic!(
+            "{} has no golden image, run with KNOCKET_BLESS=1 to create {}",
+            case.name,
+            golden_path.display()
+        )
+    });
+    if expected != actual {
+        panic!(
+            "{} does not match {} after {} frames\n{}",
+            case.name,
+            golden_path.display(),
+            case.frames,
+            diff(&expected, &actual)
+        );
+    }
+}
+
+#[test]
+fn knocket_flags() {
+    check(&Case {
+        name: "knocket-flags",
+        quirks: Quirks::COSMAC_VIP,
+        frames: 60,
+        keys: &[],
+    });
+}
+
+#[test]
+fn knocket_flags_chip_48() {
+    // the program gives the same results with either shift quirk
+    check(&Case {
+        name: "knocket-flags",
+        quirks: Quirks::CHIP_48,
+        frames: 60,
+        keys: &[],
+    });
+}
+
+#[test]
+fn knocket_skips() {
+    check(&Case {
+        name: "knocket-skips",
+        quirks: Quirks::COSMAC_VIP,
+        frames: 60,
+        keys: &[(0, 59, 5)],
+    });
+}
+
+#[test]
+fn test_diff() {
+    let output = diff("..\n##\n", "..\n#.\n");
+
+    assert!(output.contains("   0 | .."));
+    assert!(output.contains(">      1 | ## | #."));
+    assert!(output.contains("|  ^ |"));
+}